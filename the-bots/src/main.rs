@@ -0,0 +1,229 @@
+use clap::{Parser, ValueEnum};
+use futures_util::{SinkExt, StreamExt};
+use game_42_net::controls::{ButtonType, InputUpdate};
+use game_42_net::protocol::ClientPacket;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::time::{interval, sleep};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Pretends to be lots of phones, to load test the net and the host.
+#[derive(Parser, Debug, Clone)]
+#[command(version, about)]
+struct Args {
+    /// WebSocket URL of the controller endpoint
+    #[arg(long, default_value = "ws://localhost:8000/game/ws")]
+    url: String,
+
+    /// How many bots to connect
+    #[arg(short, long, default_value_t = 12)]
+    bots: usize,
+
+    /// What the bots do once connected
+    #[arg(long, value_enum, default_value_t = Behaviour::Racer)]
+    behaviour: Behaviour,
+
+    /// Stop after this many seconds (runs forever if not given)
+    #[arg(long)]
+    duration: Option<u64>,
+
+    /// How many times a second each bot considers sending input
+    #[arg(long, default_value_t = 10., value_parser = positive_rate)]
+    rate: f64,
+
+    /// Chance per second that a bot drops its connection and reconnects
+    #[arg(long, default_value_t = 0.0)]
+    reconnect_chance: f64,
+
+    /// Seed for the bots' random decisions
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+}
+
+/// A rate has to be more than zero, or the bots would never tick
+fn positive_rate(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(rate) if rate > 0. && rate.is_finite() => Ok(rate),
+        Ok(_) => Err("must be more than 0".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Behaviour {
+    /// Connect and do nothing
+    Idle,
+    /// Press READY and keep holding it
+    Ready,
+    /// Ready up, then hold accelerate and steer back and forth
+    Racer,
+    /// Mash random buttons
+    Random,
+}
+
+#[derive(Default)]
+struct Stats {
+    connects: AtomicU64,
+    connect_failures: AtomicU64,
+    disconnects: AtomicU64,
+    sent: AtomicU64,
+    received: AtomicU64,
+    send_failures: AtomicU64,
+}
+
+const ALL_BUTTONS: [ButtonType; 8] = [
+    ButtonType::A,
+    ButtonType::B,
+    ButtonType::X,
+    ButtonType::Y,
+    ButtonType::Up,
+    ButtonType::Down,
+    ButtonType::Left,
+    ButtonType::Right,
+];
+
+/// What a bot does on a given tick, as (button, pressed) changes
+fn decide(behaviour: Behaviour, tick: u64, rate: f64, rng: &mut StdRng) -> Vec<(ButtonType, bool)> {
+    let second = (tick as f64 / rate) as u64;
+    match behaviour {
+        Behaviour::Idle => vec![],
+        Behaviour::Ready => {
+            if tick == 0 {
+                vec![(ButtonType::A, true)]
+            } else {
+                vec![]
+            }
+        }
+        Behaviour::Racer => {
+            let mut changes = vec![];
+            if tick == 0 {
+                changes.push((ButtonType::A, true));
+                changes.push((ButtonType::Up, true));
+            }
+            // steer left for a second, then right for a second, and so on
+            let steer_left = second % 2 == 0;
+            changes.push((ButtonType::Left, steer_left));
+            changes.push((ButtonType::Right, !steer_left));
+            // now and then tap B, letting go on the next tick
+            changes.push((ButtonType::B, rng.gen_bool(0.02)));
+            changes
+        }
+        Behaviour::Random => {
+            let button = ALL_BUTTONS[rng.gen_range(0..ALL_BUTTONS.len())];
+            vec![(button, rng.gen_bool(0.5))]
+        }
+    }
+}
+
+/// Keep one bot connected (and reconnecting) until `deadline`
+async fn run_bot(id: usize, args: Args, stats: Arc<Stats>, deadline: Option<Instant>) {
+    let mut rng = StdRng::seed_from_u64(args.seed.wrapping_add(id as u64));
+    let period = Duration::from_secs_f64(1. / args.rate);
+    let drop_chance = (args.reconnect_chance / args.rate).clamp(0., 1.);
+    let past_deadline = || deadline.is_some_and(|d| Instant::now() >= d);
+    while !past_deadline() {
+        let (socket, _response) = match connect_async(args.url.as_str()).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                stats.connect_failures.fetch_add(1, Ordering::Relaxed);
+                eprintln!("bot {id}: could not connect: {e}");
+                sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        stats.connects.fetch_add(1, Ordering::Relaxed);
+        let (mut sink, mut stream) = socket.split();
+        let mut ticker = interval(period);
+        let mut tick = 0;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    if past_deadline() {
+                        let _ = sink.close().await;
+                        return;
+                    }
+                    if rng.gen_bool(drop_chance) {
+                        // drop the connection and come back as a "new phone"
+                        let _ = sink.close().await;
+                        break;
+                    }
+                    for (button, pressed) in decide(args.behaviour, tick, args.rate, &mut rng) {
+                        let packet = ClientPacket::Input(InputUpdate::Button(button, pressed));
+                        let json = serde_json::to_string(&packet).expect("ClientPacket is serializable");
+                        if sink.send(Message::Text(json)).await.is_ok() {
+                            stats.sent.fetch_add(1, Ordering::Relaxed);
+                        } else {
+                            stats.send_failures.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    tick += 1;
+                }
+                msg = stream.next() => match msg {
+                    Some(Ok(_)) => {
+                        stats.received.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Some(Err(e)) => {
+                        eprintln!("bot {id}: connection error: {e}");
+                        break;
+                    }
+                    None => break,
+                }
+            }
+        }
+        stats.disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn report(stats: &Stats, elapsed: Duration) {
+    let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+    let sent = stats.sent.load(Ordering::Relaxed);
+    let received = stats.received.load(Ordering::Relaxed);
+    println!(
+        "[{:>6.1}s] connects={} failed={} disconnects={} sent={} ({:.1}/s) received={} ({:.1}/s) send-failures={}",
+        secs,
+        stats.connects.load(Ordering::Relaxed),
+        stats.connect_failures.load(Ordering::Relaxed),
+        stats.disconnects.load(Ordering::Relaxed),
+        sent,
+        sent as f64 / secs,
+        received,
+        received as f64 / secs,
+        stats.send_failures.load(Ordering::Relaxed),
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let stats = Arc::new(Stats::default());
+    let start = Instant::now();
+    let deadline = args.duration.map(|d| start + Duration::from_secs(d));
+    println!("Starting {} {:?} bots against {}", args.bots, args.behaviour, args.url);
+
+    let bots: Vec<_> = (0..args.bots)
+        .map(|id| tokio::spawn(run_bot(id, args.clone(), stats.clone(), deadline)))
+        .collect();
+
+    let reporter_stats = stats.clone();
+    let reporter = tokio::spawn(async move {
+        let mut every_second = interval(Duration::from_secs(1));
+        loop {
+            every_second.tick().await;
+            report(&reporter_stats, start.elapsed());
+        }
+    });
+
+    for bot in bots {
+        let _ = bot.await;
+    }
+    reporter.abort();
+    println!("--- summary ---");
+    report(&stats, start.elapsed());
+    if stats.connect_failures.load(Ordering::Relaxed) > 0 {
+        std::process::exit(1);
+    }
+}
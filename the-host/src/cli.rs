@@ -0,0 +1,99 @@
+use crate::games::CurrentGame;
+use bevy::log::Level;
+use bevy::prelude::{Res, Resource};
+use clap::Parser;
+use game_42_net::ServerConfig;
+
+/// The host for game-42. Players join by visiting the web server on their phones.
+#[derive(Parser, Resource, Clone, Debug)]
+#[command(version, about)]
+pub struct HostOptions {
+    /// Show debug information and spawn a keyboard-controlled debug car
+    #[arg(long)]
+    pub debug: bool,
+
+    /// Seed for the random number generator
+    #[arg(long, default_value_t = 1029301923)]
+    pub seed: u64,
+
+    /// Make races reproducible: every race uses `--seed`, inputs are applied on fixed
+    /// ticks and the race only moves on a fixed tick at a time
+    #[arg(long)]
+    pub deterministic: bool,
+
+    /// Config file, relative to the asset root
+    #[arg(long, default_value = "config.json")]
+    pub config: String,
+
+    /// Directory to load assets from
+    #[arg(long, default_value = "assets")]
+    pub assets: String,
+
+    /// Directory to keep local saves in, like time trial ghosts and leaderboards
+    #[arg(long, default_value = "saves")]
+    pub save_dir: String,
+
+    /// Address the web server for phones binds to
+    #[arg(long, default_value = "0.0.0.0")]
+    pub bind: String,
+
+    /// Port the web server for phones listens on
+    #[arg(long, default_value_t = 8000)]
+    pub port: u16,
+
+    /// Skip voting and go straight to this game
+    #[arg(long, value_enum)]
+    pub game: Option<CurrentGame>,
+
+    /// Window width
+    #[arg(long, default_value_t = 1080.)]
+    pub width: f32,
+
+    /// Window height
+    #[arg(long, default_value_t = 1260.)]
+    pub height: f32,
+
+    #[arg(long)]
+    pub fullscreen: bool,
+
+    /// Most verbose log level to show (error, warn, info, debug, trace)
+    #[arg(long, default_value_t = Level::INFO)]
+    pub log_level: Level,
+
+    /// Run without a window, renderer or web server
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Check a racing track (a .glb, relative to the asset root) for problems and exit
+    #[arg(long, value_name = "TRACK")]
+    pub validate_track: Option<String>,
+
+    /// Watch a saved replay (a .replay file from the save directory) instead of racing
+    #[arg(long, value_name = "FILE")]
+    pub replay: Option<String>,
+}
+
+impl Default for HostOptions {
+    fn default() -> Self {
+        HostOptions::parse_from(["the_host"])
+    }
+}
+
+impl HostOptions {
+    pub fn server_config(&self) -> ServerConfig {
+        ServerConfig {
+            address: self.bind.clone(),
+            port: self.port,
+        }
+    }
+}
+
+/// Run condition for debug-only systems
+pub fn is_debug_mode(options: Option<Res<HostOptions>>) -> bool {
+    options.is_some_and(|o| o.debug)
+}
+
+/// Run condition for systems that only run in deterministic mode
+pub fn is_deterministic(options: Option<Res<HostOptions>>) -> bool {
+    options.is_some_and(|o| o.deterministic)
+}
@@ -0,0 +1,49 @@
+use crate::{MessageNet, PlayerMapping, PlayerNum};
+use bevy::app::App;
+use bevy::prelude::{Event, EventReader, Res, Resource, Update};
+use game_42_net::feedback::{Feedback, FeedbackSettings};
+use game_42_net::protocol::HostPacket;
+use std::collections::HashMap;
+
+pub fn init(app: &mut App) {
+    app.add_event::<PhoneFeedback>()
+        .init_resource::<PlayerFeedbackSettings>()
+        .add_systems(Update, send_feedback);
+}
+
+/// Send this to make a player's phone vibrate and/or play a sound
+#[derive(Event, Debug)]
+pub struct PhoneFeedback {
+    pub player: PlayerNum,
+    pub feedback: Feedback,
+}
+
+/// What each player's phone can do, as reported by the phone
+#[derive(Resource, Default)]
+pub struct PlayerFeedbackSettings(pub HashMap<PlayerNum, FeedbackSettings>);
+
+impl PhoneFeedback {
+    pub fn new(player: PlayerNum, feedback: Feedback) -> Self {
+        PhoneFeedback { player, feedback }
+    }
+}
+
+fn send_feedback(
+    mut events: EventReader<PhoneFeedback>,
+    player_mapping: Res<PlayerMapping>,
+    settings: Res<PlayerFeedbackSettings>,
+    message_net: Res<MessageNet>,
+) {
+    for PhoneFeedback { player, feedback } in events.read() {
+        let Some(user_id) = player_mapping.0.get(player) else {
+            continue;
+        };
+        let feedback = match settings.0.get(player) {
+            Some(settings) => feedback.clone().filtered(settings),
+            None => feedback.clone(),
+        };
+        if !feedback.is_empty() {
+            message_net.send_to(*user_id, HostPacket::Feedback(feedback));
+        }
+    }
+}
@@ -1,9 +1,16 @@
 use bevy::app::Update;
 use bevy::log::info;
-use crate::PlayerNum;
-use bevy::prelude::{App, AppExtStates, Assets, Component, NextState, Res, ResMut, State, States};
+use crate::{MessageNet, PlayerNum};
+use bevy::prelude::{App, AppExtStates, Assets, Component, Condition, IntoScheduleConfigs, NextState, Res, ResMut, Resource, State, States, World, state_changed};
 use crate::config::{Config, ConfigAccessor};
 use crate::debug_input::DebugPlayerInput;
+use game_42_net::controls::ButtonType;
+use game_42_net::controls::layout::{ControllerLayout, LayoutElement};
+use game_42_net::protocol::HostPacket;
+use crate::cli::HostOptions;
+use crate::games::racing::RelayTeams;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 
 pub mod racing;
 pub mod waiting;
@@ -12,7 +19,13 @@ pub mod waiting;
 #[derive(Component, Clone, PartialEq, Eq, Debug, Hash)]
 pub struct Player(PlayerNum);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, States)]
+impl Player {
+    pub fn number(&self) -> PlayerNum {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, States, Serialize, Deserialize, ValueEnum)]
 pub enum CurrentGame {
     /// Special reusable screen for waiting for anything
     Waiting,
@@ -26,7 +39,7 @@ pub enum CurrentGame {
 
 /// These are all the states for the program.
 /// Do not extend this.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, States)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, States, Serialize, Deserialize)]
 pub enum GamePhase {
     /// Special menu for debug controls, etc.
     Menu,
@@ -41,6 +54,10 @@ pub enum GamePhase {
     PostGame,
 }
 
+/// The controller layout phones should be showing right now
+#[derive(Resource, Default)]
+pub struct CurrentLayout(pub ControllerLayout);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, States)]
 enum ConfigLoadState {
     #[default]
@@ -52,14 +69,43 @@ pub fn init_games(app: &mut App) {
     // init things for all games
     app.add_systems(Update, update_config_load_state);
     app.init_state::<ConfigLoadState>();
-    app.init_state::<GamePhase>();
-    app.init_state::<CurrentGame>();
+    let options = app.world().get_resource::<HostOptions>();
+    // a saved replay is watched on the racing game's results screen
+    let watching_replay = options.is_some_and(|options| options.replay.is_some());
+    let starting_game = options
+        .and_then(|options| options.game)
+        .or(watching_replay.then_some(CurrentGame::Racing));
+    if let Some(game) = starting_game {
+        // skip voting
+        app.insert_state(if watching_replay {
+            GamePhase::PostGame
+        } else {
+            GamePhase::PreGame
+        });
+        app.insert_state(game);
+    } else {
+        app.init_state::<GamePhase>();
+        app.init_state::<CurrentGame>();
+    }
+    app.init_resource::<CurrentLayout>();
+    app.add_systems(
+        Update,
+        update_controller_layout
+            .run_if(state_changed::<CurrentGame>.or(state_changed::<GamePhase>)),
+    );
     app.add_systems(Update, debug_go_to_racing_game_on_spacebar); // to be removed
     
     // init specific games
     racing::init_app(app);
 }
 
+/// Whether the config file has finished loading
+pub fn config_loaded(world: &World) -> bool {
+    world
+        .get_resource::<State<ConfigLoadState>>()
+        .is_some_and(|state| *state.get() == ConfigLoadState::Loaded)
+}
+
 fn update_config_load_state(
     config_load_state: Res<State<ConfigLoadState>>,
     mut next_state: ResMut<NextState<ConfigLoadState>>,
@@ -74,6 +120,47 @@ fn update_config_load_state(
     }
 }
 
+/// Pick the controller layout for a game and phase
+fn controller_layout(
+    game: CurrentGame,
+    phase: GamePhase,
+    relay_teams: Option<&RelayTeams>,
+) -> ControllerLayout {
+    match (game, phase) {
+        (_, GamePhase::Menu) => ControllerLayout::new("Menu"),
+        (_, GamePhase::Voting) | (CurrentGame::Voting, _) => ControllerLayout::new("Vote!")
+            .with(LayoutElement::Identity)
+            .with(LayoutElement::Break)
+            .with(LayoutElement::Choice {
+                id: "vote".to_string(),
+                label: "What's next?".to_string(),
+                options: vec![format!("{:?}", CurrentGame::Racing)],
+            }),
+        (CurrentGame::Waiting, _) => ControllerLayout::new("Waiting...")
+            .with(LayoutElement::Identity)
+            .with(LayoutElement::Break)
+            .with(LayoutElement::button(ButtonType::A, "Wiggle").icon("🙃")),
+        (CurrentGame::Racing, phase) => racing::controller_layout(phase, relay_teams),
+    }
+}
+
+fn update_controller_layout(
+    game: Res<State<CurrentGame>>,
+    phase: Res<State<GamePhase>>,
+    relay_teams: Option<Res<RelayTeams>>,
+    mut current_layout: ResMut<CurrentLayout>,
+    message_net: Option<Res<MessageNet>>,
+) {
+    let layout = controller_layout(*game.get(), *phase.get(), relay_teams.as_deref());
+    if layout == current_layout.0 {
+        return;
+    }
+    current_layout.0 = layout.clone();
+    if let Some(message_net) = message_net {
+        message_net.broadcast(HostPacket::Layout(layout));
+    }
+}
+
 fn debug_go_to_racing_game_on_spacebar(
     mut next_game_phase: ResMut<NextState<GamePhase>>,
     mut next_game: ResMut<NextState<CurrentGame>>,
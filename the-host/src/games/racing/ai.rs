@@ -0,0 +1,130 @@
+use crate::PlayerNum;
+use crate::games::racing::track::{Tether, Tragnet};
+use bevy::prelude::{Component, Transform, Vec3};
+use std::f32::consts::PI;
+
+// Computer-driven cars. The AI only decides how hard to press the pedal and
+// which way to steer; the result goes through the same ControlOutput as a
+// phone would, so AI cars have no physics advantages (or disadvantages).
+
+/// Player numbers for AI cars start here, well clear of any phones
+pub const FIRST_AI_PLAYER: PlayerNum = 101;
+
+/// How far off the target (in radians) the wheels need to be to steer as hard as possible
+const FULL_LOCK: f32 = PI / 4.;
+/// How quickly (radians per second) a sloppy driver's steering wanders
+const WOBBLE_RATE: f32 = 1.3;
+/// Look ahead as if going at least this fast, so a stopped car still has somewhere to aim
+const MIN_LOOKAHEAD_SPEED: f32 = 4.0;
+
+#[derive(Component, Debug, Clone, Copy)]
+pub struct AiDriver {
+    pub difficulty: AiDifficulty,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AiDifficulty {
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+}
+
+/// How a difficulty level drives
+struct Skill {
+    /// Fraction of the top speed it's willing to go on a straight
+    speed: f32,
+    /// How far ahead it aims for, in seconds of driving at its current speed
+    lookahead: f32,
+    /// Most it wanders off the racing line, in radians
+    wobble: f32,
+    /// How much it slows down for a hairpin (0 is not at all, 1 is to a stop)
+    corner_braking: f32,
+}
+
+impl AiDifficulty {
+    pub fn from_name(name: &str) -> Option<AiDifficulty> {
+        match name.to_lowercase().as_str() {
+            "easy" => Some(AiDifficulty::Easy),
+            "medium" => Some(AiDifficulty::Medium),
+            "hard" => Some(AiDifficulty::Hard),
+            _ => None,
+        }
+    }
+
+    fn skill(self) -> Skill {
+        match self {
+            AiDifficulty::Easy => Skill {
+                speed: 0.6,
+                lookahead: 0.3,
+                wobble: 0.25,
+                corner_braking: 0.8,
+            },
+            AiDifficulty::Medium => Skill {
+                speed: 0.8,
+                lookahead: 0.4,
+                wobble: 0.12,
+                corner_braking: 0.6,
+            },
+            AiDifficulty::Hard => Skill {
+                speed: 1.0,
+                lookahead: 0.5,
+                wobble: 0.03,
+                corner_braking: 0.4,
+            },
+        }
+    }
+}
+
+impl AiDriver {
+    pub fn new(difficulty: AiDifficulty) -> Self {
+        AiDriver { difficulty }
+    }
+
+    /// Pick (throttle, steer) for a car, both from -1 to 1. Positive steer is left.
+    /// `speed` is how fast the car is going forwards, and `clock` is a time in seconds
+    /// that should be offset per car, so that each one makes different mistakes.
+    pub fn drive(
+        &self,
+        car: &Transform,
+        speed: f32,
+        tether: &Tether,
+        tragnet: &Tragnet,
+        top_speed: f32,
+        clock: f32,
+    ) -> (f32, f32) {
+        let skill = self.difficulty.skill();
+        let lookahead = speed.max(MIN_LOOKAHEAD_SPEED) * skill.lookahead;
+        let (Some(near), Some(far)) = (
+            tragnet.position_ahead(tether, lookahead),
+            tragnet.position_ahead(tether, lookahead * 2.),
+        ) else {
+            // lost, so wait for the tragnet to pull us back onto the track
+            return (0., 0.);
+        };
+
+        // cars drive towards local +Z, and steering left turns towards local +X
+        let local_target = car.rotation.inverse().mul_vec3(near - car.translation);
+        let wobble = skill.wobble * (clock * WOBBLE_RATE).sin();
+        let off_target = local_target.x.atan2(local_target.z) + wobble;
+        let steer = (off_target / FULL_LOCK).clamp(-1., 1.);
+
+        // slow down for the corner coming up, and when pointing the wrong way
+        let corner = flat(near - car.translation).angle_between(flat(far - near));
+        let corner = if corner.is_finite() { corner } else { 0. };
+        let slowdown = (1. - skill.corner_braking * corner / PI) * (1. - off_target.abs() / PI);
+        let target_speed = top_speed * skill.speed * slowdown.max(0.2);
+        let throttle = if speed < target_speed {
+            1.
+        } else if speed > target_speed * 1.2 {
+            -1.
+        } else {
+            0.
+        };
+        (throttle, steer)
+    }
+}
+
+fn flat(v: Vec3) -> Vec3 {
+    Vec3::new(v.x, 0., v.z)
+}
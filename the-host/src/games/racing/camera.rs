@@ -0,0 +1,462 @@
+use crate::PlayerNum;
+use crate::config::{Config, ConfigAccessor};
+use crate::games::Player;
+use crate::games::racing::ai::AiDriver;
+use crate::games::racing::replay::{ReplayCar, ReplayView, ReplayViewer};
+use crate::games::racing::track::{LapCounter, Tether, Tragnet};
+use crate::games::racing::{CAMERA_MODE, CAR_SIZE, GAME, RaceGameMarker};
+use crate::remote::{parse_params, to_brp_result};
+use bevy::asset::Assets;
+use bevy::math::{UVec2, Vec3};
+use bevy::prelude::{
+    Camera, Camera2d, Camera3d, ClearColorConfig, Commands, Component, Entity, GlobalTransform, In,
+    IsDefaultUiCamera, PerspectiveProjection, Query, Res, ResMut, Resource, Single, Time,
+    Transform, Visibility, With, Without, default, info, warn,
+};
+use bevy::remote::BrpResult;
+use bevy::render::camera::Viewport;
+use bevy::window::{PrimaryWindow, Window};
+use bevy_fly_camera::FlyCamera;
+use itertools::Itertools;
+use serde::Deserialize;
+use serde_json::Value;
+use std::f32::consts::PI;
+
+// How the race is watched. The big screen either shows one director camera
+// that keeps the pack in shot (or, once the pack spreads out, the leaders and
+// one other car: the player picked with `game42/watch_player`, or else each of
+// the rest taking turns), or splits into a chase camera for each phone player.
+// Cameras never jump: they ease towards where they want to be.
+
+/// Most players that get their own part of a split screen
+const MAX_SPLIT_PLAYERS: usize = 4;
+/// How quickly cameras catch up with where they want to be (higher is snappier)
+const CAMERA_EASING: f32 = 3.0;
+/// How far the director looks down on the pack
+const DIRECTOR_PITCH: f32 = PI / 5.;
+/// The director frames at least this much of the track around the cars, in car sizes
+const MIN_FRAME_RADIUS: f32 = 4.0;
+/// Once the pack is spread wider than this (in car sizes), the director stops framing everyone
+const PACK_RADIUS: f32 = 15.0;
+/// How many cars at the front the director keeps in shot once the pack has spread out
+const LEADERS: usize = 2;
+/// How long the director follows each other car, in seconds
+const FOCUS_TIME: f32 = 8.0;
+/// Where a chase camera sits behind and above its car, and how far ahead it looks, in car sizes
+const CHASE_BEHIND: f32 = 6.0;
+const CHASE_ABOVE: f32 = 2.5;
+const CHASE_LOOK_AHEAD: f32 = 3.0;
+/// The UI is drawn by its own camera over the top of everything else
+const UI_CAMERA_ORDER: isize = 10;
+
+/// Which cameras the race is watched with. Read from the config when each race starts.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RaceCameraMode {
+    /// One camera framing the pack
+    #[default]
+    Director,
+    /// A chase camera for each phone player (up to 4), or the director if there are none
+    Split,
+    /// The keyboard and mouse controlled debug camera
+    Fly,
+}
+
+impl RaceCameraMode {
+    pub fn from_name(name: &str) -> Option<RaceCameraMode> {
+        match name.to_lowercase().as_str() {
+            "director" => Some(RaceCameraMode::Director),
+            "split" => Some(RaceCameraMode::Split),
+            "fly" => Some(RaceCameraMode::Fly),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct DirectorCamera;
+
+#[derive(Component)]
+pub struct ChaseCamera {
+    player: PlayerNum,
+}
+
+/// Which car (other than the leaders) the director is showing, and for how much longer
+#[derive(Resource, Default)]
+pub struct DirectorFocus {
+    player: Option<PlayerNum>,
+    remaining: f32,
+    /// The player picked to be watched. Kept from race to race.
+    selected: Option<PlayerNum>,
+}
+
+#[derive(Deserialize)]
+struct WatchPlayerParams {
+    player: Option<PlayerNum>,
+}
+
+/// Set up the cameras for a race, in whichever mode the config asks for
+pub fn spawn_race_cameras(
+    mut commands: Commands,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+    mut focus: ResMut<DirectorFocus>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let mode_name = cstr![config, CAMERA_MODE];
+    let mode = RaceCameraMode::from_name(mode_name).unwrap_or_else(|| {
+        warn!("Unknown camera mode {mode_name}, using the director");
+        RaceCameraMode::default()
+    });
+    commands.insert_resource(mode);
+    *focus = DirectorFocus {
+        selected: focus.selected,
+        ..default()
+    };
+
+    let start = Transform::from_xyz(0., 3., 0.);
+    if mode == RaceCameraMode::Fly {
+        commands.spawn((RaceGameMarker, Camera3d::default(), FlyCamera::default(), start));
+    } else {
+        commands.spawn((RaceGameMarker, DirectorCamera, Camera3d::default(), start));
+    }
+    // so the overlay covers the whole window, however it's split up
+    commands.spawn((
+        RaceGameMarker,
+        Camera2d,
+        Camera {
+            order: UI_CAMERA_ORDER,
+            clear_color: ClearColorConfig::None,
+            ..default()
+        },
+        IsDefaultUiCamera,
+    ));
+}
+
+/// `game42/watch_player`: have the director keep `player` in shot along with the
+/// leaders, or with `null`, go back to taking turns. Returns who was picked before.
+pub fn watch_player(In(params): In<Option<Value>>, mut focus: ResMut<DirectorFocus>) -> BrpResult {
+    let WatchPlayerParams { player } = parse_params(params)?;
+    info!("Remote request to watch {player:?}");
+    to_brp_result(std::mem::replace(&mut focus.selected, player))
+}
+
+/// Keep the director's shot on the pack, or on the leaders and the picked player
+/// (or whoever's turn it is)
+pub fn direct_camera(
+    mut camera: Single<&mut Transform, With<DirectorCamera>>,
+    cars: Query<(&Player, &GlobalTransform, &Tether, &LapCounter), With<RaceGameMarker>>,
+    tragnet: Option<Single<&Tragnet>>,
+    mut focus: ResMut<DirectorFocus>,
+    time: Res<Time>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let car_size = cfloat![config, CAR_SIZE];
+    let tragnet = tragnet.as_deref().copied();
+    // front of the race first
+    let order: Vec<_> = cars
+        .iter()
+        .map(|(player, transform, tether, counter)| {
+            let progress = tragnet
+                .map(|t| t.race_progress(tether, counter))
+                .unwrap_or(counter.lap() as f32);
+            (player.0, transform.translation(), tether, progress)
+        })
+        .sorted_by(|(_, _, _, a), (_, _, _, b)| b.total_cmp(a))
+        .collect();
+    let Some((_, leader_position, leader_tether, _)) = order.first() else {
+        return;
+    };
+
+    // show the picked player if they're racing, otherwise take turns showing the
+    // cars behind the leaders
+    let chasing: Vec<_> = order.iter().skip(LEADERS).map(|(p, ..)| *p).collect();
+    focus.remaining -= time.delta_secs();
+    let still_racing = focus.player.is_some_and(|p| chasing.contains(&p));
+    let selected = focus
+        .selected
+        .filter(|selected| order.iter().any(|(player, ..)| player == selected));
+    if selected.is_some() {
+        focus.player = selected;
+    } else if focus.remaining <= 0.0 || !still_racing {
+        let next = match focus.player.and_then(|p| chasing.iter().position(|c| *c == p)) {
+            Some(i) => chasing.get((i + 1) % chasing.len()),
+            None => chasing.first(),
+        };
+        focus.player = next.copied();
+        focus.remaining = FOCUS_TIME;
+    }
+
+    let everyone: Vec<Vec3> = order.iter().map(|(_, position, ..)| *position).collect();
+    let framed = if bounding_radius(&everyone) <= PACK_RADIUS * car_size {
+        everyone
+    } else {
+        order
+            .iter()
+            .enumerate()
+            .filter(|(i, (player, ..))| *i < LEADERS || Some(*player) == focus.player)
+            .map(|(_, (_, position, ..))| *position)
+            .collect()
+    };
+
+    // look along the track where the leader is
+    let heading = match (tragnet, leader_tether) {
+        (Some(tragnet), Tether::Along(distance)) => tragnet.point_at(*distance).1,
+        // or failing that, from the rest of the cars towards the leader
+        _ => framed.iter().map(|p| *leader_position - *p).sum::<Vec3>(),
+    };
+    let fov = PerspectiveProjection::default().fov;
+    let (eye, target) = frame(&framed, heading, MIN_FRAME_RADIUS * car_size, fov);
+    ease_towards(&mut camera, eye, target, time.delta_secs());
+}
+
+/// Give each phone player (up to 4) a chase camera in split mode, and lay them out in a grid
+pub fn assign_chase_cameras(
+    mut commands: Commands,
+    mode: Res<RaceCameraMode>,
+    cars: Query<(&Player, &GlobalTransform), (With<RaceGameMarker>, Without<AiDriver>)>,
+    chase_cameras: Query<(Entity, &ChaseCamera, &mut Camera)>,
+    director: Option<Single<&mut Camera, (With<DirectorCamera>, Without<ChaseCamera>)>>,
+    window: Option<Single<&Window, With<PrimaryWindow>>>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let car_size = cfloat![config, CAR_SIZE];
+    let wanted: Vec<_> = if *mode == RaceCameraMode::Split {
+        cars.iter()
+            .sorted_by_key(|(player, _)| player.0)
+            .take(MAX_SPLIT_PLAYERS)
+            .collect()
+    } else {
+        vec![]
+    };
+    let slots: Vec<PlayerNum> = wanted.iter().map(|(player, _)| player.0).collect();
+    let viewports = window
+        .map(|window| split_viewports(slots.len(), window.physical_size()))
+        .unwrap_or_default();
+    let viewport = |slot: usize| {
+        viewports.get(slot).map(|(position, size)| Viewport {
+            physical_position: *position,
+            physical_size: *size,
+            ..default()
+        })
+    };
+
+    let mut has_camera = vec![];
+    for (entity, chase, mut camera) in chase_cameras {
+        let Some(slot) = slots.iter().position(|p| *p == chase.player) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+        has_camera.push(chase.player);
+        let order = slot as isize + 1;
+        let moved = match (&camera.viewport, viewports.get(slot)) {
+            (Some(current), Some((position, size))) => {
+                current.physical_position != *position || current.physical_size != *size
+            }
+            (None, None) => false,
+            _ => true,
+        };
+        if camera.order != order || moved {
+            camera.order = order;
+            camera.viewport = viewport(slot);
+        }
+    }
+    for (slot, (player, car)) in wanted.into_iter().enumerate() {
+        if has_camera.contains(&player.0) {
+            continue;
+        }
+        let (eye, target) = chase_view(car, car_size);
+        commands.spawn((
+            RaceGameMarker,
+            ChaseCamera { player: player.0 },
+            Camera3d::default(),
+            Camera {
+                order: slot as isize + 1,
+                viewport: viewport(slot),
+                ..default()
+            },
+            Transform::from_translation(eye).looking_at(target, Vec3::Y),
+        ));
+    }
+
+    // the director fills in when nobody has a chase camera
+    if let Some(mut director) = director {
+        let active = slots.is_empty();
+        if director.is_active != active {
+            director.is_active = active;
+        }
+    }
+}
+
+/// Ease each chase camera in behind its car
+pub fn follow_chase_cameras(
+    chase_cameras: Query<(&ChaseCamera, &mut Transform)>,
+    cars: Query<(&Player, &GlobalTransform), With<RaceGameMarker>>,
+    time: Res<Time>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let car_size = cfloat![config, CAR_SIZE];
+    for (chase, mut transform) in chase_cameras {
+        let Some((_, car)) = cars.iter().find(|(player, _)| player.0 == chase.player) else {
+            continue;
+        };
+        let (eye, target) = chase_view(car, car_size);
+        ease_towards(&mut transform, eye, target, time.delta_secs());
+    }
+}
+
+/// Watch the replay on the results screen: the whole field, or one car from behind
+pub fn film_replay(
+    mut commands: Commands,
+    director: Option<Single<(&mut Transform, &mut Camera), With<DirectorCamera>>>,
+    chase_cameras: Query<Entity, With<ChaseCamera>>,
+    cars: Query<(&ReplayCar, &GlobalTransform, &Visibility)>,
+    viewer: Res<ReplayViewer>,
+    time: Res<Time>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let car_size = cfloat![config, CAR_SIZE];
+    // the replay gets the whole screen
+    for entity in chase_cameras {
+        commands.entity(entity).despawn();
+    }
+    let Some(director) = director else {
+        // flying around
+        return;
+    };
+    let (mut transform, mut camera) = director.into_inner();
+    if !camera.is_active {
+        camera.is_active = true;
+    }
+    let shown: Vec<_> = cars
+        .iter()
+        .filter(|(_, _, visibility)| **visibility != Visibility::Hidden)
+        .map(|(car, transform, _)| (car.0, transform))
+        .collect();
+    let followed = match viewer.view {
+        ReplayView::Car(i) => shown.iter().find(|(car, _)| *car == i),
+        ReplayView::Field => None,
+    };
+    let (eye, target) = match followed {
+        Some((_, car)) => chase_view(car, car_size),
+        None if shown.is_empty() => return,
+        None => {
+            let points: Vec<Vec3> = shown.iter().map(|(_, t)| t.translation()).collect();
+            let heading: Vec3 = shown
+                .iter()
+                .map(|(_, t)| t.rotation().mul_vec3(Vec3::Z))
+                .sum();
+            let fov = PerspectiveProjection::default().fov;
+            frame(&points, heading, MIN_FRAME_RADIUS * car_size, fov)
+        }
+    };
+    ease_towards(&mut transform, eye, target, time.delta_secs());
+}
+
+/// Where a chase camera wants to be, and what it looks at
+fn chase_view(car: &GlobalTransform, car_size: f32) -> (Vec3, Vec3) {
+    // cars drive towards their local +Z. Ignore any tilt, so bumps don't shake the camera
+    let forward = flat(car.rotation().mul_vec3(Vec3::Z)).normalize_or(Vec3::Z);
+    let position = car.translation();
+    let eye = position - forward * CHASE_BEHIND * car_size + Vec3::Y * CHASE_ABOVE * car_size;
+    (eye, position + forward * CHASE_LOOK_AHEAD * car_size)
+}
+
+/// Move a camera part of the way to where it wants to be, looking at `target`
+fn ease_towards(transform: &mut Transform, eye: Vec3, target: Vec3, dt: f32) {
+    let t = 1.0 - (-CAMERA_EASING * dt).exp();
+    let wanted = Transform::from_translation(eye).looking_at(target, Vec3::Y);
+    transform.translation = transform.translation.lerp(eye, t);
+    transform.rotation = transform.rotation.slerp(wanted.rotation, t);
+}
+
+/// Where to put a camera (and what to point it at) so every point is in shot,
+/// looking down on them along `heading`. `fov` is the narrowest angle the camera sees.
+fn frame(points: &[Vec3], heading: Vec3, min_radius: f32, fov: f32) -> (Vec3, Vec3) {
+    let (min, max) = points.iter().fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(min, max), p| (min.min(*p), max.max(*p)),
+    );
+    let centre = (min + max) / 2.0;
+    let radius = points
+        .iter()
+        .map(|p| p.distance(centre))
+        .fold(min_radius, f32::max);
+    // far enough back that a ball around all the points fits in the view
+    let distance = radius / (fov / 2.0).sin();
+    let heading = flat(heading).normalize_or(Vec3::Z);
+    let back = -heading * DIRECTOR_PITCH.cos() + Vec3::Y * DIRECTOR_PITCH.sin();
+    (centre + back * distance, centre)
+}
+
+/// How far the furthest point is from the middle of them all
+fn bounding_radius(points: &[Vec3]) -> f32 {
+    let centre = points.iter().sum::<Vec3>() / points.len().max(1) as f32;
+    points.iter().map(|p| p.distance(centre)).fold(0.0, f32::max)
+}
+
+/// Positions and sizes of the parts of a split screen: one player gets the whole
+/// screen, two are one above the other, and three or four share a 2x2 grid
+fn split_viewports(players: usize, size: UVec2) -> Vec<(UVec2, UVec2)> {
+    let (columns, rows) = match players {
+        0 => return vec![],
+        1 => (1, 1),
+        2 => (1, 2),
+        _ => (2, 2),
+    };
+    let cell = UVec2::new(size.x / columns, size.y / rows);
+    (0..players as u32)
+        .map(|i| (UVec2::new(i % columns * cell.x, i / columns * cell.y), cell))
+        .collect()
+}
+
+fn flat(v: Vec3) -> Vec3 {
+    Vec3::new(v.x, 0., v.z)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::games::racing::camera::{frame, split_viewports};
+    use bevy::math::{UVec2, Vec3};
+    use std::f32::consts::PI;
+
+    #[test]
+    fn every_car_is_in_shot() {
+        let cars = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(10.0, 0.0, 3.0),
+            Vec3::new(-4.0, 1.0, 8.0),
+        ];
+        let fov = PI / 4.0;
+        let (eye, target) = frame(&cars, Vec3::X, 1.0, fov);
+        assert!(eye.y > target.y, "the camera looks down on the cars");
+        assert!(eye.x < target.x, "the camera is behind the cars");
+        for car in cars {
+            let off_centre = (car - eye).angle_between(target - eye);
+            assert!(off_centre <= fov / 2.0 + 1e-4, "{car} is out of shot");
+        }
+    }
+
+    #[test]
+    fn split_screen_grid() {
+        let size = UVec2::new(1920, 1080);
+        assert_eq!(split_viewports(1, size), vec![(UVec2::ZERO, size)]);
+        assert_eq!(
+            split_viewports(2, size),
+            vec![
+                (UVec2::ZERO, UVec2::new(1920, 540)),
+                (UVec2::new(0, 540), UVec2::new(1920, 540)),
+            ]
+        );
+        let three = split_viewports(3, size);
+        assert_eq!(three.len(), 3);
+        assert_eq!(three[2], (UVec2::new(0, 540), UVec2::new(960, 540)));
+    }
+}
@@ -0,0 +1,189 @@
+use bevy::math::Vec3;
+use bevy::render::mesh::{Mesh, PrimitiveTopology, VertexAttributeValues};
+use serde_json::Value;
+use std::collections::HashMap;
+use thiserror::Error;
+
+// The tragnet is built from a single object in the track's glTF, named
+// "centreline" (or "centerline"). Either:
+//  - it's a mesh made of loose edges (a Blender curve converted to a mesh, exported
+//    with "Loose Edges" ticked), and the points are its vertices in edge order, or
+//  - it has a "centreline" custom property (glTF extras) holding the points, either
+//    as a list of [x, y, z] or as a flat list of numbers, in the object's own
+//    glTF space (Y up).
+// The race starts at the first point and goes towards the second.
+
+/// Fewer points than this can't make a loop
+pub const MIN_CENTRELINE_POINTS: usize = 3;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CentrelineError {
+    #[error("the centreline has {0} points, but needs at least {}", MIN_CENTRELINE_POINTS)]
+    TooFewPoints(usize),
+    #[error("the centreline mesh is {0:?}, but should be made of lines")]
+    NotLines(PrimitiveTopology),
+    #[error("the centreline mesh has no vertex positions")]
+    NoPositions,
+    #[error("the centreline branches at point {0}, which has {1} edges")]
+    Branches(usize, usize),
+    #[error("the centreline is in pieces: only {reached} of its {total} points are joined to the start")]
+    Disconnected { reached: usize, total: usize },
+    #[error("the centreline extras are not a list of points: {0}")]
+    BadExtras(String),
+}
+
+/// Is this the name of the object holding the centreline?
+pub fn is_centreline(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name == "centreline" || name == "centerline"
+}
+
+/// Points along a centreline mesh, in the mesh's own space
+pub fn points_from_mesh(mesh: &Mesh) -> Result<Vec<Vec3>, CentrelineError> {
+    let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return Err(CentrelineError::NoPositions);
+    };
+    let indices: Vec<usize> = match mesh.indices() {
+        Some(indices) => indices.iter().collect(),
+        None => (0..positions.len()).collect(),
+    };
+    // exporters often split vertices, so join up any that are in the same place
+    let mut welded = HashMap::new();
+    let mut points = vec![];
+    let ids: Vec<usize> = indices
+        .iter()
+        .map(|i| {
+            let p = positions[*i];
+            *welded.entry(p.map(f32::to_bits)).or_insert_with(|| {
+                points.push(Vec3::from_array(p));
+                points.len() - 1
+            })
+        })
+        .collect();
+    let edges: Vec<_> = match mesh.primitive_topology() {
+        PrimitiveTopology::LineList => ids.chunks_exact(2).map(|e| (e[0], e[1])).collect(),
+        PrimitiveTopology::LineStrip => ids.windows(2).map(|e| (e[0], e[1])).collect(),
+        other => return Err(CentrelineError::NotLines(other)),
+    };
+    let order = order_edges(points.len(), &edges)?;
+    Ok(order.into_iter().map(|i| points[i]).collect())
+}
+
+/// Points from a centreline custom property, if the extras have one
+pub fn points_from_extras(extras: &str) -> Result<Option<Vec<Vec3>>, CentrelineError> {
+    let bad = |e: &str| CentrelineError::BadExtras(e.to_string());
+    let extras: Value = serde_json::from_str(extras).map_err(|e| bad(&e.to_string()))?;
+    let Some(points) = extras.get("centreline").or(extras.get("centerline")) else {
+        return Ok(None);
+    };
+    let Some(points) = points.as_array() else {
+        return Err(bad("expected a list"));
+    };
+    let numbers: Option<Vec<f32>> = if points.iter().all(Value::is_array) {
+        points
+            .iter()
+            .flat_map(|p| p.as_array().cloned().unwrap_or_default())
+            .map(|n| n.as_f64().map(|n| n as f32))
+            .collect()
+    } else {
+        points.iter().map(|n| n.as_f64().map(|n| n as f32)).collect()
+    };
+    let Some(numbers) = numbers else {
+        return Err(bad("expected numbers"));
+    };
+    if numbers.len() % 3 != 0 {
+        return Err(bad("expected points with 3 coordinates each"));
+    }
+    let points: Vec<_> = numbers.chunks_exact(3).map(Vec3::from_slice).collect();
+    if points.len() < MIN_CENTRELINE_POINTS {
+        return Err(CentrelineError::TooFewPoints(points.len()));
+    }
+    Ok(Some(points))
+}
+
+/// Walk the edges from start to end, returning the points in order.
+/// The walk starts at an open end if there is one, otherwise at the lowest
+/// numbered point, heading to its lower numbered neighbour.
+pub fn order_edges(
+    num_points: usize,
+    edges: &[(usize, usize)],
+) -> Result<Vec<usize>, CentrelineError> {
+    let mut neighbours: Vec<Vec<usize>> = vec![vec![]; num_points];
+    for &(a, b) in edges {
+        if a != b && !neighbours[a].contains(&b) {
+            neighbours[a].push(b);
+            neighbours[b].push(a);
+        }
+    }
+    if let Some((point, n)) = neighbours.iter().enumerate().find(|(_, n)| n.len() > 2) {
+        return Err(CentrelineError::Branches(point, n.len()));
+    }
+    let total = neighbours.iter().filter(|n| !n.is_empty()).count();
+    if total < MIN_CENTRELINE_POINTS {
+        return Err(CentrelineError::TooFewPoints(total));
+    }
+    let start = neighbours
+        .iter()
+        .position(|n| n.len() == 1)
+        .or_else(|| neighbours.iter().position(|n| n.len() == 2))
+        .expect("there are points with edges");
+
+    let mut order = vec![start];
+    let mut previous = start;
+    let mut current = *neighbours[start].iter().min().expect("start has an edge");
+    while current != start {
+        order.push(current);
+        let Some(next) = neighbours[current].iter().find(|n| **n != previous) else {
+            // reached the other open end
+            break;
+        };
+        previous = current;
+        current = *next;
+    }
+    if order.len() < total {
+        return Err(CentrelineError::Disconnected {
+            reached: order.len(),
+            total,
+        });
+    }
+    Ok(order)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::games::racing::centreline::{CentrelineError, order_edges, points_from_extras};
+    use bevy::math::Vec3;
+
+    #[test]
+    fn edges_are_walked_in_order() {
+        // a loop, with the edges shuffled and backwards
+        let edges = [(2, 3), (1, 0), (3, 0), (2, 1)];
+        assert_eq!(order_edges(4, &edges), Ok(vec![0, 1, 2, 3]));
+        // an open line starts at its end
+        let edges = [(3, 1), (0, 2), (1, 0)];
+        assert_eq!(order_edges(4, &edges), Ok(vec![2, 0, 1, 3]));
+    }
+
+    #[test]
+    fn broken_centrelines_are_rejected() {
+        let branching = [(0, 1), (1, 2), (2, 0), (1, 3)];
+        assert_eq!(order_edges(4, &branching), Err(CentrelineError::Branches(1, 3)));
+        let two_loops = [(0, 1), (1, 2), (2, 0), (3, 4), (4, 5), (5, 3)];
+        assert_eq!(
+            order_edges(6, &two_loops),
+            Err(CentrelineError::Disconnected { reached: 3, total: 6 })
+        );
+    }
+
+    #[test]
+    fn points_in_extras() {
+        let nested = r#"{"centreline": [[0, 0, 0], [1, 0, 0], [1, 0, 1]]}"#;
+        let flat = r#"{"centreline": [0, 0, 0, 1, 0, 0, 1, 0, 1]}"#;
+        let expected = vec![Vec3::ZERO, Vec3::X, Vec3::new(1.0, 0.0, 1.0)];
+        assert_eq!(points_from_extras(nested), Ok(Some(expected.clone())));
+        assert_eq!(points_from_extras(flat), Ok(Some(expected)));
+        assert_eq!(points_from_extras(r#"{"other": 1}"#), Ok(None));
+        assert!(points_from_extras(r#"{"centreline": [0, 1]}"#).is_err());
+    }
+}
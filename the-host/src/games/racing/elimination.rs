@@ -0,0 +1,184 @@
+use crate::feedback::PhoneFeedback;
+use crate::games::racing::track::{Lap, LapCounter, Tether, Tragnet};
+use crate::games::racing::{GameInfo, RaceGameMarker, RaceMode, RaceResults};
+use crate::games::{GamePhase, Player};
+use crate::identity::PlayerIdentities;
+use bevy::color::{Alpha, Color};
+use bevy::prelude::{
+    AssetServer, BackgroundColor, Bundle, Commands, Component, Entity, EventWriter, JustifyText,
+    NextState, Node, PositionType, Query, Res, ResMut, Resource, Single, Text, TextColor, TextFont,
+    TextLayout, Time, Timer, TimerMode, UiRect, Val, With, default, info,
+};
+use game_42_net::feedback::Feedback;
+use itertools::Itertools;
+
+// Elimination races. With "race-mode" set to "elimination", whoever is in last
+// place when the leader finishes a lap is knocked out: their car is taken off
+// the track, the screen announces it, and their phone tells them they're out.
+// The race lasts as many laps as it takes to get down to one car, which wins.
+
+/// How long a knockout is announced for
+const ANNOUNCEMENT_SECONDS: f32 = 3.0;
+
+/// How far through the knockouts the race is
+#[derive(Resource, Default)]
+pub struct Elimination {
+    /// The leader's laps that someone has already been knocked out for
+    laps_done: Lap,
+}
+
+/// Says who was just knocked out, across the top of the screen
+#[derive(Component)]
+pub struct KnockoutAnnouncement(Timer);
+
+/// Who to knock out, given how far through the race each car still in it is: the
+/// car in last place for each of the leader's `laps` since the last knockout, but
+/// never the last car left
+fn knockouts<T: Copy>(racers: &[(T, f32)], laps: usize) -> Vec<T> {
+    let out = laps.min(racers.len().saturating_sub(1));
+    racers
+        .iter()
+        .sorted_by(|(_, a), (_, b)| a.total_cmp(b))
+        .take(out)
+        .map(|(racer, _)| *racer)
+        .collect()
+}
+
+/// Give the race one lap for each car that has to be knocked out, if it's an elimination race
+pub fn start_elimination(
+    mut commands: Commands,
+    race_mode: Res<RaceMode>,
+    mut game_info: ResMut<GameInfo>,
+    cars: Query<(), (With<Player>, With<LapCounter>)>,
+) {
+    if *race_mode != RaceMode::Elimination {
+        return;
+    }
+    game_info.laps = cars.iter().count().saturating_sub(1).max(1);
+    info!("Elimination race over {} lap(s)", game_info.laps);
+    commands.insert_resource(Elimination::default());
+}
+
+pub fn end_elimination(mut commands: Commands) {
+    commands.remove_resource::<Elimination>();
+}
+
+/// Knock out whoever is last when the leader finishes a lap, and end the race when
+/// there's one car left
+pub fn knock_out_last_place(
+    mut commands: Commands,
+    mut elimination: ResMut<Elimination>,
+    mut race_results: ResMut<RaceResults>,
+    mut game_phase: ResMut<NextState<GamePhase>>,
+    mut feedback: EventWriter<PhoneFeedback>,
+    cars: Query<(Entity, &Player, &Tether, &LapCounter)>,
+    announcements: Query<Entity, With<KnockoutAnnouncement>>,
+    tragnet: Single<&Tragnet>,
+    game_info: Res<GameInfo>,
+    identities: Res<PlayerIdentities>,
+    asset_server: Res<AssetServer>,
+) {
+    let Some(leader_lap) = cars.iter().map(|(_, _, _, counter)| counter.lap()).max() else {
+        // everyone has left
+        game_phase.set(GamePhase::PostGame);
+        return;
+    };
+    let mut racers: Vec<_> = cars
+        .iter()
+        .map(|(entity, player, tether, counter)| {
+            ((entity, player.0), tragnet.race_progress(tether, counter))
+        })
+        .collect();
+    if leader_lap > elimination.laps_done {
+        let out = knockouts(&racers, leader_lap - elimination.laps_done);
+        elimination.laps_done = leader_lap;
+        if !out.is_empty() {
+            for (entity, player) in &out {
+                info!("{} was knocked out", identities.nickname(*player));
+                let progress = racers
+                    .iter()
+                    .find(|((e, _), _)| e == entity)
+                    .map_or(0.0, |(_, progress)| *progress);
+                race_results.eliminated.push((*player, progress));
+                feedback.write(PhoneFeedback::new(
+                    *player,
+                    Feedback::new(&[300, 100, 300], None),
+                ));
+                commands.entity(*entity).despawn();
+            }
+            for old in announcements {
+                commands.entity(old).despawn();
+            }
+            let names = out.iter().map(|(_, p)| identities.nickname(*p)).join(", ");
+            commands.spawn(announcement(format!("{names} knocked out!"), &asset_server));
+            racers.retain(|(racer, _)| !out.contains(racer));
+        }
+    }
+
+    // the last car left wins. If nobody was ever knocked out (everyone else left
+    // early) they have to finish the laps first.
+    if let [((entity, player), _)] = racers.as_slice() {
+        let done_laps = cars
+            .get(*entity)
+            .is_ok_and(|(_, _, _, counter)| counter.lap() >= game_info.laps);
+        if !race_results.eliminated.is_empty() || done_laps {
+            info!("{} wins!", identities.nickname(*player));
+            race_results.finished.push(*player);
+            feedback.write(PhoneFeedback::new(*player, Feedback::finish()));
+            commands.entity(*entity).despawn();
+        }
+    }
+}
+
+fn announcement(text: String, asset_server: &AssetServer) -> impl Bundle {
+    (
+        RaceGameMarker,
+        KnockoutAnnouncement(Timer::from_seconds(ANNOUNCEMENT_SECONDS, TimerMode::Once)),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(15.0),
+            left: Val::Percent(20.0),
+            right: Val::Percent(20.0),
+            padding: UiRect::all(Val::Px(10.0)),
+            ..default()
+        },
+        BackgroundColor(Color::BLACK.with_alpha(0.6)),
+        Text::new(text),
+        TextColor(Color::WHITE),
+        TextLayout::new_with_justify(JustifyText::Center),
+        TextFont {
+            font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+            font_size: 40.0,
+            ..default()
+        },
+    )
+}
+
+pub fn expire_announcements(
+    mut commands: Commands,
+    announcements: Query<(Entity, &mut KnockoutAnnouncement)>,
+    time: Res<Time>,
+) {
+    for (entity, mut announcement) in announcements {
+        if announcement.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::games::racing::elimination::knockouts;
+
+    #[test]
+    fn last_place_is_knocked_out_each_lap() {
+        let racers = [(1, 2.4), (2, 1.9), (3, 2.1), (4, 1.95)];
+        assert_eq!(knockouts(&racers, 0), Vec::<u8>::new());
+        assert_eq!(knockouts(&racers, 1), [2]);
+        // the leader did two laps at once (say, everyone else was stuck)
+        assert_eq!(knockouts(&racers, 2), [2, 4]);
+        // someone always wins
+        assert_eq!(knockouts(&racers, 10), [2, 4, 3]);
+        assert_eq!(knockouts(&racers[..1], 1), Vec::<u8>::new());
+    }
+}
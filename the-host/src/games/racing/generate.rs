@@ -0,0 +1,292 @@
+use crate::config::Config;
+use crate::games::racing::track::{Tether, Tragnet, TragnetAnchor};
+use crate::games::racing::{
+    GAME, GENERATED_TRACK_SIZE, GROUND_FRICTION, GROUND_RESTITUTION, RACE_CHECKPOINTS,
+    RaceGameMarker, TRACK_RADIUS,
+};
+use avian3d::prelude::{Collider, Friction, Restitution, RigidBody};
+use bevy::asset::{Assets, RenderAssetUsages};
+use bevy::color::Color;
+use bevy::math::{Quat, Vec3};
+use bevy::pbr::{MeshMaterial3d, StandardMaterial};
+use bevy::prelude::{Commands, Mesh3d, Meshable, Plane3d, Transform, default, info, warn};
+use bevy::render::mesh::{Indices, Mesh, PrimitiveTopology};
+use rand::Rng;
+use std::f32::consts::TAU;
+
+// Generated tracks. With "track" set to "generated" in the config, every race is on
+// a new layout made up from the race's random numbers, so the same seed always
+// gives the same track. The centreline is a loop through corners scattered around
+// the middle of the ground, one per slice of a circle, each a random distance out.
+// Layouts that cross themselves, turn too tightly for the road, or run off the
+// ground are thrown away and another is tried.
+//
+// The road mesh's UVs follow racetrack.frag: u counts road widths along the track
+// from the start line, and v goes across the road from 0 on the left to 1 on the right.
+
+/// Name of the "track" that's made up for each race
+pub const GENERATED_TRACK: &str = "generated";
+/// How many corners a track can have
+const MIN_CORNERS: usize = 8;
+const MAX_CORNERS: usize = 12;
+/// Layouts to try before settling for a circle
+const MAX_ATTEMPTS: usize = 100;
+/// How far apart the rows of the road and rail meshes are
+const MESH_STEP: f32 = 0.2;
+const RAIL_HEIGHT: f32 = 0.15;
+const RAIL_THICKNESS: f32 = 0.05;
+/// Space between the edge of the track and the edge of the ground
+const GROUND_MARGIN: f32 = 2.0;
+
+/// Corners for a track that fits in a square `size` out from the middle each way,
+/// with a road `half_width` either side of the centreline
+pub fn generate_centreline(rng: &mut impl Rng, size: f32, half_width: f32) -> Vec<Vec3> {
+    for attempt in 1..=MAX_ATTEMPTS {
+        let corners = random_loop(rng, size);
+        let tragnet = Tragnet::new(anchors(&corners), RACE_CHECKPOINTS);
+        if fits(&tragnet, size, half_width) {
+            info!(
+                "Generated a track with {} corners on attempt {attempt}",
+                corners.len()
+            );
+            return corners;
+        }
+    }
+    warn!("Couldn't generate a track that fits, so racing round a circle");
+    (0..MAX_CORNERS)
+        .map(|i| corner(i as f32 / MAX_CORNERS as f32 * TAU, size * 0.8))
+        .collect()
+}
+
+/// One corner in each slice of a circle, a random distance out
+fn random_loop(rng: &mut impl Rng, size: f32) -> Vec<Vec3> {
+    let count = rng.gen_range(MIN_CORNERS..=MAX_CORNERS);
+    let slice = TAU / count as f32;
+    (0..count)
+        .map(|i| {
+            let angle = (i as f32 + rng.gen_range(-0.35..0.35)) * slice;
+            corner(angle, rng.gen_range(0.4..1.0) * size)
+        })
+        .collect()
+}
+
+fn corner(angle: f32, distance: f32) -> Vec3 {
+    Vec3::new(angle.cos(), 0.0, angle.sin()) * distance
+}
+
+fn anchors(corners: &[Vec3]) -> Vec<TragnetAnchor> {
+    corners
+        .iter()
+        .map(|p| TragnetAnchor {
+            transform: Transform::from_translation(*p),
+        })
+        .collect()
+}
+
+/// Does the road stay on the ground and clear of itself? Any two bits of centreline
+/// that aren't just round a bend from each other have to be more than a road width
+/// apart, with some grass between. That also rules out bends so tight that the
+/// inside edge of the road would fold over.
+fn fits(tragnet: &Tragnet, size: f32, half_width: f32) -> bool {
+    let clearance = 3.0 * half_width;
+    let points = rows(tragnet, half_width / 2.0);
+    let on_ground = points
+        .iter()
+        .all(|(_, p, _)| p.x.abs().max(p.z.abs()) + half_width + RAIL_THICKNESS <= size);
+    if !on_ground {
+        return false;
+    }
+    // a U-turn just wide enough is this long
+    let bend = clearance * TAU / 4.0;
+    let length = tragnet.length();
+    points.iter().enumerate().all(|(i, (a, p, _))| {
+        points[i + 1..].iter().all(|(b, q, _)| {
+            let apart = (b - a).min(length - (b - a));
+            apart <= bend || p.distance(*q) >= clearance
+        })
+    })
+}
+
+/// Distance, position and left direction at even steps round the centreline, about
+/// `step` apart. The last is back at the start line, a lap along.
+fn rows(tragnet: &Tragnet, step: f32) -> Vec<(f32, Vec3, Vec3)> {
+    let count = (tragnet.length() / step).ceil().max(1.0) as usize;
+    let step = tragnet.length() / count as f32;
+    (0..=count)
+        .map(|i| {
+            let distance = i as f32 * step;
+            let (position, tangent) = tragnet.point_at(distance);
+            let left = Vec3::Y.cross(tangent).normalize_or_zero();
+            (distance, position, left)
+        })
+        .collect()
+}
+
+/// A strip of quads along the track. Each row is a pair of points, how far along
+/// the track they are, and which way the strip faces there.
+fn strip_mesh(rows: &[((Vec3, Vec3), f32, Vec3)], width: f32) -> Mesh {
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    for ((first, second), distance, normal) in rows {
+        let u = distance / width;
+        positions.extend([first.to_array(), second.to_array()]);
+        normals.extend([normal.to_array(); 2]);
+        uvs.extend([[u, 0.0], [u, 1.0]]);
+    }
+    let indices = (0..rows.len().saturating_sub(1) as u32)
+        .flat_map(|i| {
+            let (a, b, c, d) = (2 * i, 2 * i + 1, 2 * i + 2, 2 * i + 3);
+            [a, b, c, b, d, c]
+        })
+        .collect();
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+    .with_inserted_indices(Indices::U32(indices))
+}
+
+/// The road surface, just above the ground
+fn road_mesh(tragnet: &Tragnet, half_width: f32) -> Mesh {
+    let lift = Vec3::Y * 0.005;
+    let rows: Vec<_> = rows(tragnet, MESH_STEP)
+        .into_iter()
+        .map(|(distance, p, left)| {
+            let edges = (p + left * half_width + lift, p - left * half_width + lift);
+            (edges, distance, Vec3::Y)
+        })
+        .collect();
+    strip_mesh(&rows, half_width * 2.0)
+}
+
+/// The rail on one side of the road (`side` is 1 for the left, -1 for the right),
+/// and a collider for it made of a box along each row
+fn rail(tragnet: &Tragnet, half_width: f32, side: f32) -> (Mesh, Collider) {
+    let out = half_width + RAIL_THICKNESS / 2.0;
+    let rows: Vec<_> = rows(tragnet, MESH_STEP)
+        .into_iter()
+        .map(|(distance, p, left)| (distance, p + left * side * out, -left * side))
+        .collect();
+    // facing the road
+    let mesh_rows: Vec<_> = rows
+        .iter()
+        .map(|(distance, p, facing)| ((*p, *p + Vec3::Y * RAIL_HEIGHT), *distance, *facing))
+        .collect();
+    let boxes = rows
+        .windows(2)
+        .map(|pair| {
+            let (from, to) = (pair[0].1, pair[1].1);
+            let centre = (from + to) / 2.0 + Vec3::Y * RAIL_HEIGHT / 2.0;
+            let rotation =
+                Quat::from_rotation_arc(Vec3::NEG_Z, (to - from).normalize_or(Vec3::NEG_Z));
+            // a bit longer than the gap, so that there's no crack on the outside of bends
+            let length = from.distance(to) + RAIL_THICKNESS;
+            (
+                centre,
+                rotation,
+                Collider::cuboid(RAIL_THICKNESS, RAIL_HEIGHT, length),
+            )
+        })
+        .collect();
+    (
+        strip_mesh(&mesh_rows, half_width * 2.0),
+        Collider::compound(boxes),
+    )
+}
+
+/// Make up a track from the random numbers and spawn its ground, road, rails and
+/// tragnet. Returns where the race starts.
+pub fn spawn_generated_track(
+    commands: &mut Commands,
+    rng: &mut impl Rng,
+    config: &Config,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) -> Transform {
+    let size = cfloat![config, GENERATED_TRACK_SIZE];
+    let half_width = cfloat![config, TRACK_RADIUS];
+    let corners = generate_centreline(rng, size, half_width);
+    let tragnet = Tragnet::new(anchors(&corners), RACE_CHECKPOINTS);
+    info!("The generated track is {:.1} long", tragnet.length());
+
+    let ground_size = (size + GROUND_MARGIN) * 2.0;
+    // the collider's top is at y = 0, level with the grass drawn on top of it
+    commands.spawn((
+        RaceGameMarker,
+        RigidBody::Static,
+        Friction::new(cfloat![config, GROUND_FRICTION]),
+        Restitution::new(cfloat![config, GROUND_RESTITUTION]),
+        Collider::cuboid(ground_size, 0.5, ground_size),
+        Transform::from_xyz(0., -0.25, 0.),
+    ));
+    commands.spawn((
+        RaceGameMarker,
+        Mesh3d(meshes.add(Plane3d::default().mesh().size(ground_size, ground_size))),
+        MeshMaterial3d(materials.add(Color::srgb(0.25, 0.5, 0.2))),
+    ));
+    commands.spawn((
+        RaceGameMarker,
+        Mesh3d(meshes.add(road_mesh(&tragnet, half_width))),
+        MeshMaterial3d(materials.add(Color::srgb(0.3, 0.3, 0.32))),
+    ));
+    let rail_material = materials.add(StandardMaterial {
+        base_color: Color::srgb(0.8, 0.1, 0.1),
+        cull_mode: None,
+        double_sided: true,
+        ..default()
+    });
+    for side in [1.0, -1.0] {
+        let (mesh, collider) = rail(&tragnet, half_width, side);
+        commands.spawn((
+            RaceGameMarker,
+            RigidBody::Static,
+            collider,
+            Mesh3d(meshes.add(mesh)),
+            MeshMaterial3d(rail_material.clone()),
+        ));
+    }
+    let race_start = tragnet.get_tether_transform(&Tether::Along(0.0));
+    commands.spawn((RaceGameMarker, tragnet));
+    race_start
+}
+
+#[cfg(test)]
+mod test {
+    use crate::games::racing::RACE_CHECKPOINTS;
+    use crate::games::racing::generate::{anchors, fits, generate_centreline};
+    use crate::games::racing::track::Tragnet;
+    use bevy::math::Vec3;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn generated_tracks_are_clear_loops() {
+        for seed in 0..20 {
+            let corners = generate_centreline(&mut ChaCha8Rng::seed_from_u64(seed), 8.0, 0.4);
+            let tragnet = Tragnet::new(anchors(&corners), RACE_CHECKPOINTS);
+            assert!(
+                fits(&tragnet, 8.0, 0.4),
+                "seed {seed} made a track that doesn't fit"
+            );
+            // the same seed makes the same track
+            let again = generate_centreline(&mut ChaCha8Rng::seed_from_u64(seed), 8.0, 0.4);
+            assert_eq!(corners, again);
+        }
+        // a figure of eight crosses itself
+        let eight: Vec<_> = (0..16)
+            .map(|i| {
+                let t = i as f32 / 16.0 * std::f32::consts::TAU;
+                Vec3::new(t.sin() * 6.0, 0.0, (2.0 * t).sin() * 3.0)
+            })
+            .collect();
+        assert!(!fits(
+            &Tragnet::new(anchors(&eight), RACE_CHECKPOINTS),
+            8.0,
+            0.4
+        ));
+    }
+}
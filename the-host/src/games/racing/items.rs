@@ -0,0 +1,476 @@
+use crate::config::{Config, ConfigAccessor};
+use crate::feedback::PhoneFeedback;
+use crate::games::Player;
+use crate::games::racing::ai::AiDriver;
+use crate::games::racing::recovery::{RaceLayer, Recovery};
+use crate::games::racing::track::{LapCounter, Tether, Tragnet};
+use crate::games::racing::{CAR_SIZE, ControlOutput, GAME, RaceGameMarker};
+use crate::{PlayerInputs, PlayerMapping, RandomSource};
+use avian3d::prelude::{
+    AngularVelocity, Collider, CollisionEventsEnabled, CollisionLayers, CollisionStarted,
+    ComputedMass, ExternalImpulse, LinearVelocity, RigidBody, Sensor,
+};
+use bevy::asset::Assets;
+use bevy::color::Color;
+use bevy::math::Vec3;
+use bevy::pbr::{MeshMaterial3d, StandardMaterial};
+use bevy::prelude::{
+    Bundle, Commands, Component, Cylinder, Entity, EventReader, EventWriter, Mesh, Mesh3d, Query,
+    Res, ResMut, Single, Sphere, Time, Transform, Visibility, With, Without,
+};
+use game_42_net::controls::ButtonType;
+use game_42_net::feedback::Feedback;
+use rand::Rng;
+use std::fmt::{Display, Formatter};
+
+// Power-ups. Driving through an item box (a mesh in the track with the
+// "item-box" material) gives a car an item, unless it already has one, and the
+// box comes back a few seconds later. Which item depends on race position: the
+// further back a car is, the better its chances of something that helps it
+// catch up. Items are used with the B button (the AI uses them after holding
+// them for a bit), and everything they do to cars is done with impulses,
+// velocities and colliders, so it plays out in the physics like a bump would.
+
+/// Which button players press to use their item
+pub const ITEM_BUTTON: ButtonType = ButtonType::B;
+/// How long an item box is gone for after someone drives through it
+const ITEM_BOX_RESPAWN_TIME: f32 = 3.0;
+/// How long the AI holds on to an item before using it
+const AI_ITEM_DELAY: f32 = 2.0;
+/// How long a boost lasts, and how much faster it makes the car each tick
+const BOOST_TIME: f32 = 1.5;
+const BOOST_ACCELERATION: f32 = 0.1;
+/// How long a shield lasts, if nothing hits it first
+const SHIELD_TIME: f32 = 8.0;
+/// How long a car spins for when it's hit, and how fast (radians per second)
+const SPIN_TIME: f32 = 1.2;
+const SPIN_RATE: f32 = 4.0 * std::f32::consts::PI;
+/// How long an oil slick stays on the track
+const OIL_LIFETIME: f32 = 30.0;
+/// Size of an oil slick, and how far behind the car it's dropped, in car sizes
+const OIL_RADIUS: f32 = 1.5;
+const OIL_DROP_DISTANCE: f32 = 3.0;
+/// How long a homing projectile flies for before fizzling out
+const PROJECTILE_LIFETIME: f32 = 6.0;
+/// How fast a homing projectile flies, in car sizes per second
+const PROJECTILE_SPEED: f32 = 40.0;
+/// Size of a homing projectile, in car sizes
+const PROJECTILE_RADIUS: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Item {
+    /// A push forwards for a moment
+    Boost,
+    /// Protection from the next oil slick or projectile
+    Shield,
+    /// Dropped behind, and spins out whoever drives into it
+    OilSlick,
+    /// Chases the car in front, and spins it out
+    Homing,
+}
+
+impl Item {
+    const ALL: [Item; 4] = [Item::Boost, Item::Shield, Item::OilSlick, Item::Homing];
+
+    /// How likely this item is, compared to the others, for a car that's `behind`
+    /// of the way down the field (0 is in the lead, 1 is last)
+    fn weight(self, behind: f32) -> f32 {
+        match self {
+            Item::Boost => 1.0 + 2.0 * behind,
+            Item::Shield => 2.0 - behind,
+            Item::OilSlick => 2.0 - 1.5 * behind,
+            // there's nobody to chase when you're in front
+            Item::Homing => 3.0 * behind,
+        }
+    }
+
+    /// Pick an item for a car in `position` (1 is first) out of `racers`,
+    /// with `roll` from 0 to 1
+    fn roll(position: usize, racers: usize, roll: f32) -> Item {
+        let behind = if racers > 1 {
+            (position.saturating_sub(1) as f32 / (racers - 1) as f32).min(1.0)
+        } else {
+            0.0
+        };
+        let total: f32 = Item::ALL.iter().map(|item| item.weight(behind)).sum();
+        let mut left = roll.clamp(0.0, 1.0) * total;
+        for item in Item::ALL {
+            left -= item.weight(behind);
+            if left < 0.0 {
+                return item;
+            }
+        }
+        Item::Boost
+    }
+}
+
+impl Display for Item {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Item::Boost => "Boost",
+            Item::Shield => "Shield",
+            Item::OilSlick => "Oil slick",
+            Item::Homing => "Homing shot",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A box in the track that gives out items
+#[derive(Component, Default)]
+pub struct ItemBox {
+    /// How long until it's back, if someone's taken it
+    respawn_in: Option<f32>,
+}
+
+/// The item a car is carrying
+#[derive(Component, Default, Debug)]
+pub struct HeldItem {
+    pub item: Option<Item>,
+    /// How long it's been carried for
+    held_for: f32,
+    /// Whether the item button was down last tick, so holding it only uses one item
+    button_down: bool,
+}
+
+/// What items are doing to a car right now. Each is how many seconds are left.
+#[derive(Component, Default, Debug)]
+pub struct ItemEffects {
+    boost: f32,
+    shield: f32,
+    spin: f32,
+}
+
+impl ItemEffects {
+    /// Spin the car out, unless its shield takes the hit. Returns whether it spun.
+    fn hit(&mut self, angular_velocity: &mut AngularVelocity) -> bool {
+        if self.shield > 0.0 {
+            self.shield = 0.0;
+            return false;
+        }
+        self.spin = SPIN_TIME;
+        self.boost = 0.0;
+        angular_velocity.0 = Vec3::Y * SPIN_RATE;
+        true
+    }
+}
+
+#[derive(Component)]
+pub struct OilSlick {
+    remaining: f32,
+}
+
+#[derive(Component)]
+pub struct HomingProjectile {
+    owner: Entity,
+    target: Option<Entity>,
+    remaining: f32,
+}
+
+/// Items only touch cars, and not ghosts or the track
+fn item_layers() -> CollisionLayers {
+    CollisionLayers::new(RaceLayer::Item, RaceLayer::Car)
+}
+
+/// What every car needs to carry and use items
+pub fn car_items() -> impl Bundle {
+    (
+        HeldItem::default(),
+        ItemEffects::default(),
+        ExternalImpulse::default(),
+    )
+}
+
+/// What an item box mesh in the track needs, given a collider made from it
+pub fn item_box(collider: Collider) -> impl Bundle {
+    (
+        ItemBox::default(),
+        RigidBody::Static,
+        collider,
+        Sensor,
+        item_layers(),
+    )
+}
+
+/// Cars ordered from first to last, by how far along the race they are
+fn race_order<'a>(
+    tragnet: &Tragnet,
+    cars: impl Iterator<Item = (Entity, &'a Tether, &'a LapCounter)>,
+) -> Vec<Entity> {
+    let mut cars: Vec<_> = cars
+        .map(|(entity, tether, counter)| (entity, tragnet.race_progress(tether, counter)))
+        .collect();
+    cars.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    cars.into_iter().map(|(entity, _)| entity).collect()
+}
+
+/// Give out items to cars that drive through item boxes
+pub fn pick_up_items(
+    mut collisions: EventReader<CollisionStarted>,
+    mut boxes: Query<(&mut ItemBox, &mut Visibility)>,
+    mut cars: Query<(Entity, &mut HeldItem, Option<&Player>)>,
+    racers: Query<(Entity, &Tether, &LapCounter), With<HeldItem>>,
+    tragnet: Single<&Tragnet>,
+    mut random_source: ResMut<RandomSource>,
+    mut feedback: EventWriter<PhoneFeedback>,
+) {
+    for CollisionStarted(a, b) in collisions.read() {
+        let (box_entity, car_entity) = if boxes.contains(*a) { (*a, *b) } else { (*b, *a) };
+        let (Ok((mut item_box, mut visibility)), Ok((car, mut held, player))) =
+            (boxes.get_mut(box_entity), cars.get_mut(car_entity))
+        else {
+            continue;
+        };
+        if item_box.respawn_in.is_some() || held.item.is_some() {
+            continue;
+        }
+        let order = race_order(&tragnet, racers.iter());
+        let position = order.iter().position(|e| *e == car).unwrap_or(0) + 1;
+        let item = Item::roll(position, order.len(), random_source.0.gen_range(0.0..1.0));
+        held.item = Some(item);
+        held.held_for = 0.0;
+        item_box.respawn_in = Some(ITEM_BOX_RESPAWN_TIME);
+        *visibility = Visibility::Hidden;
+        if let Some(player) = player {
+            feedback.write(PhoneFeedback::new(player.0, Feedback::new(&[40], None)));
+        }
+    }
+}
+
+/// Bring item boxes back after a while
+pub fn respawn_item_boxes(boxes: Query<(&mut ItemBox, &mut Visibility)>, time: Res<Time>) {
+    for (mut item_box, mut visibility) in boxes {
+        let Some(remaining) = item_box.respawn_in else {
+            continue;
+        };
+        let remaining = remaining - time.delta_secs();
+        if remaining > 0.0 {
+            item_box.respawn_in = Some(remaining);
+        } else {
+            item_box.respawn_in = None;
+            *visibility = Visibility::Inherited;
+        }
+    }
+}
+
+/// Use items when players press the item button (or the AI decides to)
+pub fn use_items(
+    mut commands: Commands,
+    mut cars: Query<(
+        Entity,
+        &Transform,
+        &mut HeldItem,
+        &mut ItemEffects,
+        &Recovery,
+        Option<&Player>,
+        Option<&AiDriver>,
+    )>,
+    racers: Query<(Entity, &Tether, &LapCounter), With<HeldItem>>,
+    tragnet: Single<&Tragnet>,
+    player_inputs: Res<PlayerInputs>,
+    player_mapping: Res<PlayerMapping>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    time: Res<Time>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let car_size = cfloat![config, CAR_SIZE];
+    let dt = time.delta_secs();
+    let mut order = None;
+    for (car, transform, mut held, mut effects, recovery, player, ai) in cars.iter_mut() {
+        held.held_for += dt;
+        let pressed = player
+            .and_then(|p| player_mapping.0.get(&p.0))
+            .and_then(|user| player_inputs.0.get(user))
+            .is_some_and(|pi| pi.is_pressed(ITEM_BUTTON));
+        let just_pressed = pressed && !held.button_down;
+        held.button_down = pressed;
+        let wants_to_use = match ai {
+            Some(_) => held.held_for > AI_ITEM_DELAY,
+            None => just_pressed,
+        };
+        if !wants_to_use || recovery.is_recovering() || effects.spin > 0.0 {
+            continue;
+        }
+        let Some(item) = held.item.take() else {
+            continue;
+        };
+        let forward = transform.rotation.mul_vec3(Vec3::Z);
+        match item {
+            Item::Boost => effects.boost = BOOST_TIME,
+            Item::Shield => effects.shield = SHIELD_TIME,
+            Item::OilSlick => {
+                let behind = transform.translation - forward * OIL_DROP_DISTANCE * car_size;
+                let radius = OIL_RADIUS * car_size;
+                let height = car_size * 0.05;
+                commands.spawn((
+                    RaceGameMarker,
+                    OilSlick {
+                        remaining: OIL_LIFETIME,
+                    },
+                    RigidBody::Static,
+                    Collider::cylinder(radius, car_size),
+                    Sensor,
+                    item_layers(),
+                    Mesh3d(meshes.add(Cylinder::new(radius, height))),
+                    MeshMaterial3d(materials.add(StandardMaterial {
+                        base_color: Color::srgb(0.05, 0.05, 0.08),
+                        perceptual_roughness: 0.1,
+                        ..Default::default()
+                    })),
+                    Transform::from_translation(behind),
+                ));
+            }
+            Item::Homing => {
+                // chase whoever's one place ahead
+                let order = order.get_or_insert_with(|| race_order(&tragnet, racers.iter()));
+                let target = order
+                    .iter()
+                    .position(|e| *e == car)
+                    .and_then(|i| i.checked_sub(1))
+                    .map(|i| order[i]);
+                let radius = PROJECTILE_RADIUS * car_size;
+                commands.spawn((
+                    RaceGameMarker,
+                    HomingProjectile {
+                        owner: car,
+                        target,
+                        remaining: PROJECTILE_LIFETIME,
+                    },
+                    RigidBody::Kinematic,
+                    Collider::sphere(radius),
+                    Sensor,
+                    CollisionEventsEnabled,
+                    item_layers(),
+                    LinearVelocity(forward * PROJECTILE_SPEED * car_size),
+                    Mesh3d(meshes.add(Sphere::new(radius))),
+                    MeshMaterial3d(materials.add(StandardMaterial {
+                        base_color: Color::srgb(1.0, 0.1, 0.1),
+                        emissive: Color::srgb(1.0, 0.1, 0.1).into(),
+                        ..Default::default()
+                    })),
+                    Transform::from_translation(transform.translation + forward * car_size * 2.0),
+                ));
+            }
+        }
+    }
+}
+
+/// Boost, shield and spin out cars, as their items (or other people's) say
+pub fn apply_item_effects(
+    cars: Query<(
+        &Transform,
+        &mut ItemEffects,
+        &mut ControlOutput,
+        &mut ExternalImpulse,
+        &ComputedMass,
+    )>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    for (transform, mut effects, mut co, mut impulse, mass) in cars {
+        if effects.spin > 0.0 {
+            // nobody can drive while they're spinning
+            *co = ControlOutput::default();
+        } else if effects.boost > 0.0 {
+            let forward = transform.rotation.mul_vec3(Vec3::Z);
+            impulse.apply_impulse(forward * BOOST_ACCELERATION * mass.value());
+        }
+        effects.boost = (effects.boost - dt).max(0.0);
+        effects.shield = (effects.shield - dt).max(0.0);
+        effects.spin = (effects.spin - dt).max(0.0);
+    }
+}
+
+/// Point homing projectiles at their targets, and fizzle them out after a while
+pub fn steer_projectiles(
+    mut commands: Commands,
+    projectiles: Query<(Entity, &mut HomingProjectile, &Transform, &mut LinearVelocity)>,
+    cars: Query<&Transform, (With<ItemEffects>, Without<HomingProjectile>)>,
+    time: Res<Time>,
+) {
+    for (entity, mut projectile, transform, mut lv) in projectiles {
+        projectile.remaining -= time.delta_secs();
+        if projectile.remaining <= 0.0 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let Some(target) = projectile.target.and_then(|t| cars.get(t).ok()) else {
+            // nobody to chase, so carry on in a straight line
+            continue;
+        };
+        let towards = (target.translation - transform.translation).normalize_or_zero();
+        lv.0 = towards * lv.0.length();
+    }
+}
+
+/// Spin out cars that hit oil slicks or homing projectiles
+pub fn item_hits(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionStarted>,
+    slicks: Query<(), With<OilSlick>>,
+    projectiles: Query<&HomingProjectile>,
+    mut cars: Query<(&mut ItemEffects, &mut AngularVelocity, Option<&Player>)>,
+    mut feedback: EventWriter<PhoneFeedback>,
+) {
+    for CollisionStarted(a, b) in collisions.read() {
+        for (hazard, car) in [(*a, *b), (*b, *a)] {
+            let Ok((mut effects, mut angular_velocity, player)) = cars.get_mut(car) else {
+                continue;
+            };
+            if slicks.contains(hazard) {
+                // slicks stay where they are, for the next car
+            } else if let Ok(projectile) = projectiles.get(hazard) {
+                if projectile.owner == car {
+                    continue;
+                }
+                commands.entity(hazard).try_despawn();
+            } else {
+                continue;
+            }
+            if effects.hit(&mut angular_velocity) {
+                if let Some(player) = player {
+                    feedback.write(PhoneFeedback::new(player.0, Feedback::bump()));
+                }
+            }
+        }
+    }
+}
+
+/// Clean up oil slicks that have been there a while
+pub fn dry_up_oil(
+    mut commands: Commands,
+    slicks: Query<(Entity, &mut OilSlick)>,
+    time: Res<Time>,
+) {
+    for (entity, mut slick) in slicks {
+        slick.remaining -= time.delta_secs();
+        if slick.remaining <= 0.0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::games::racing::items::Item;
+
+    #[test]
+    fn items_depend_on_position() {
+        let rolls: Vec<f32> = (0..100).map(|i| i as f32 / 100.0).collect();
+        let count = |position, item| {
+            rolls
+                .iter()
+                .filter(|roll| Item::roll(position, 8, **roll) == item)
+                .count()
+        };
+        assert_eq!(count(1, Item::Homing), 0, "the leader has nobody to chase");
+        assert!(count(8, Item::Homing) > count(4, Item::Homing));
+        assert!(count(8, Item::Boost) > count(1, Item::Boost));
+        assert!(count(1, Item::Shield) > count(8, Item::Shield));
+        // racing alone counts as being in the lead
+        assert_eq!(Item::roll(1, 1, 0.999), Item::OilSlick);
+    }
+}
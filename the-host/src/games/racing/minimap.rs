@@ -0,0 +1,241 @@
+use crate::PlayerNum;
+use crate::games::Player;
+use crate::games::racing::RaceGameMarker;
+use crate::games::racing::style::CarStyle;
+use crate::games::racing::track::{LapCounter, Tether, Tragnet};
+use bevy::color::{Alpha, Color};
+use bevy::math::{Vec2, Vec3, Vec3Swizzles};
+use bevy::prelude::{
+    BackgroundColor, BorderRadius, Commands, Component, Entity, GlobalTransform, Node, Outline,
+    PositionType, Query, Single, Val, With, ZIndex, default,
+};
+use itertools::Itertools;
+use std::collections::HashSet;
+
+// A bird's eye view of the track in the top right corner while racing, with a
+// dot for each car in its colour. The track is drawn as a line of small dots
+// along the centreline, shaded by sector, with the start/finish line and each
+// checkpoint marked. The leader's dot is bigger and outlined.
+
+/// Width and height of the minimap, as a percentage of the shorter side of the window
+const MINIMAP_SIZE: f32 = 28.0;
+/// Space left around the track inside the minimap, as a fraction of its size
+const MINIMAP_MARGIN: f32 = 0.08;
+/// How many dots the track is drawn with
+const TRACK_DOTS: usize = 120;
+/// Sizes of things on the minimap, as a percentage of its size
+const TRACK_DOT_SIZE: f32 = 1.5;
+const CHECKPOINT_SIZE: f32 = 3.5;
+const CAR_DOT_SIZE: f32 = 4.0;
+const LEADER_DOT_SIZE: f32 = 6.0;
+
+#[derive(Component)]
+pub struct Minimap {
+    projection: MinimapProjection,
+}
+
+#[derive(Component)]
+pub struct MinimapCar(PlayerNum);
+
+/// Turns positions on the track into positions on the minimap, looking down
+/// from above with +X to the right and +Z down
+#[derive(Debug, Clone, Copy)]
+struct MinimapProjection {
+    centre: Vec2,
+    scale: f32,
+}
+
+impl MinimapProjection {
+    /// Fit all the points into the minimap, keeping the track's shape
+    fn fitting(points: &[Vec3]) -> Self {
+        let (min, max) = points.iter().map(|p| p.xz()).fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), p| (min.min(p), max.max(p)),
+        );
+        let extent = (max - min).max_element().max(f32::EPSILON);
+        MinimapProjection {
+            centre: (min + max) / 2.0,
+            scale: (1.0 - 2.0 * MINIMAP_MARGIN) / extent,
+        }
+    }
+
+    /// Where a point is on the minimap, from (0, 0) at the top left to (1, 1) at the bottom right
+    fn to_map(self, point: Vec3) -> Vec2 {
+        Vec2::splat(0.5) + (point.xz() - self.centre) * self.scale
+    }
+}
+
+/// A square dot on the minimap, centred on `at`
+fn dot(at: Vec2, size: f32) -> Node {
+    Node {
+        position_type: PositionType::Absolute,
+        left: Val::Percent(at.x * 100.0 - size / 2.0),
+        top: Val::Percent(at.y * 100.0 - size / 2.0),
+        width: Val::Percent(size),
+        height: Val::Percent(size),
+        ..default()
+    }
+}
+
+/// Draw the track in the corner when the race starts
+pub fn spawn_minimap(mut commands: Commands, tragnet: Single<&Tragnet>) {
+    let step = tragnet.length() / TRACK_DOTS as f32;
+    let track: Vec<_> = (0..TRACK_DOTS)
+        .map(|i| {
+            let distance = i as f32 * step;
+            let sector = tragnet.get_current_sector(&Tether::Along(distance));
+            (tragnet.point_at(distance).0, sector)
+        })
+        .collect();
+    let positions: Vec<_> = track.iter().map(|(position, _)| *position).collect();
+    let projection = MinimapProjection::fitting(&positions);
+
+    let minimap = commands
+        .spawn((
+            RaceGameMarker,
+            Minimap { projection },
+            Node {
+                position_type: PositionType::Absolute,
+                right: Val::Px(10.0),
+                top: Val::Px(10.0),
+                width: Val::VMin(MINIMAP_SIZE),
+                height: Val::VMin(MINIMAP_SIZE),
+                ..default()
+            },
+            BackgroundColor(Color::BLACK.with_alpha(0.4)),
+            BorderRadius::all(Val::Percent(5.0)),
+        ))
+        .id();
+
+    let mut children = vec![];
+    for (i, (position, sector)) in track.iter().enumerate() {
+        // alternate shades, so you can see where each sector is
+        let shade = if sector % 2 == 0 { 0.85 } else { 0.55 };
+        children.push(
+            commands
+                .spawn((
+                    dot(projection.to_map(*position), TRACK_DOT_SIZE),
+                    BackgroundColor(Color::srgb(shade, shade, shade)),
+                ))
+                .id(),
+        );
+        // a checkpoint wherever the sector changes, and the finish line where the lap wraps round
+        let previous = track[(i + TRACK_DOTS - 1) % TRACK_DOTS].1;
+        if previous != *sector {
+            let color = if *sector == 0 {
+                Color::WHITE
+            } else {
+                Color::srgb(1.0, 0.8, 0.0)
+            };
+            children.push(
+                commands
+                    .spawn((
+                        dot(projection.to_map(*position), CHECKPOINT_SIZE),
+                        BackgroundColor(color),
+                        Outline::new(Val::Px(1.0), Val::ZERO, Color::BLACK),
+                        ZIndex(1),
+                    ))
+                    .id(),
+            );
+        }
+    }
+    commands.entity(minimap).add_children(&children);
+}
+
+/// Move each car's dot to where it is, adding and removing dots as cars come and go
+pub fn update_minimap(
+    mut commands: Commands,
+    minimap: Single<(Entity, &Minimap)>,
+    cars: Query<
+        (&Player, &GlobalTransform, &Tether, &LapCounter, &CarStyle),
+        With<RaceGameMarker>,
+    >,
+    dots: Query<(
+        Entity,
+        &MinimapCar,
+        &mut Node,
+        &mut BackgroundColor,
+        &mut Outline,
+        &mut ZIndex,
+    )>,
+    tragnet: Single<&Tragnet>,
+) {
+    let (minimap_entity, minimap) = minimap.into_inner();
+    let leader = cars
+        .iter()
+        .max_by(|(_, _, a_tether, a_counter, _), (_, _, b_tether, b_counter, _)| {
+            let a = tragnet.race_progress(a_tether, a_counter);
+            let b = tragnet.race_progress(b_tether, b_counter);
+            a.total_cmp(&b)
+        })
+        .map(|(player, ..)| player.0);
+    let mut has_dot = HashSet::new();
+    for (entity, car_dot, mut node, mut background, mut outline, mut z_index) in dots {
+        let car = cars.iter().find(|(player, ..)| player.0 == car_dot.0);
+        let Some((_, transform, _, _, style)) = car else {
+            // finished or gone
+            commands.entity(entity).despawn();
+            continue;
+        };
+        has_dot.insert(car_dot.0);
+        let is_leader = leader == Some(car_dot.0);
+        let size = if is_leader { LEADER_DOT_SIZE } else { CAR_DOT_SIZE };
+        *node = dot(minimap.projection.to_map(transform.translation()), size);
+        background.0 = style.color;
+        outline.color = if is_leader { Color::WHITE } else { Color::BLACK };
+        // the leader is drawn over everyone else
+        *z_index = ZIndex(if is_leader { 3 } else { 2 });
+    }
+    let new_dots = cars
+        .iter()
+        .filter(|(player, ..)| !has_dot.contains(&player.0))
+        .sorted_by_key(|(player, ..)| player.0)
+        .map(|(player, transform, _, _, style)| {
+            commands
+                .spawn((
+                    MinimapCar(player.0),
+                    dot(minimap.projection.to_map(transform.translation()), CAR_DOT_SIZE),
+                    BackgroundColor(style.color),
+                    BorderRadius::MAX,
+                    Outline::new(Val::Px(1.0), Val::ZERO, Color::BLACK),
+                    ZIndex(2),
+                ))
+                .id()
+        })
+        .collect_vec();
+    commands.entity(minimap_entity).add_children(&new_dots);
+}
+
+pub fn despawn_minimap(mut commands: Commands, minimap: Query<Entity, With<Minimap>>) {
+    for entity in minimap {
+        commands.entity(entity).despawn();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::games::racing::minimap::{MINIMAP_MARGIN, MinimapProjection};
+    use bevy::math::{Vec2, Vec3};
+
+    #[test]
+    fn track_fits_in_the_minimap() {
+        // a long thin track, off to one side
+        let track = [
+            Vec3::new(100.0, 5.0, 10.0),
+            Vec3::new(140.0, 0.0, 10.0),
+            Vec3::new(140.0, 0.0, 20.0),
+            Vec3::new(100.0, 2.0, 20.0),
+        ];
+        let projection = MinimapProjection::fitting(&track);
+        let on_map: Vec<Vec2> = track.iter().map(|p| projection.to_map(*p)).collect();
+        for p in &on_map {
+            assert!(p.min_element() >= MINIMAP_MARGIN - 1e-5);
+            assert!(p.max_element() <= 1.0 - MINIMAP_MARGIN + 1e-5);
+        }
+        // the long side fills the map, and the shape isn't stretched
+        assert!((on_map[1].x - on_map[0].x - (1.0 - 2.0 * MINIMAP_MARGIN)).abs() < 1e-5);
+        assert!((on_map[2].y - on_map[1].y - (1.0 - 2.0 * MINIMAP_MARGIN) / 4.0).abs() < 1e-5);
+        // +Z is down the map
+        assert!(on_map[2].y > on_map[1].y);
+    }
+}
@@ -1,45 +1,104 @@
-mod scene;
+mod ai;
+mod centreline;
+mod minimap;
 mod style;
-mod track;
+pub mod track;
 mod ui;
+pub mod validate;
 
 use crate::config::{
     Config, ConfigAccessor, ConfigPath, ConfigPathElem, ConfigType, ConfigValue, ConfigValueMap,
     GetConfig,
 };
 use crate::debug_input::{DebugPlayer, DebugPlayerInput};
+use crate::games::racing::ai::FIRST_AI_PLAYER;
+use crate::games::racing::camera::{
+    DirectorFocus, RaceCameraMode, assign_chase_cameras, direct_camera, film_replay,
+    follow_chase_cameras, spawn_race_cameras,
+};
+use crate::games::racing::elimination::{
+    Elimination, end_elimination, expire_announcements, knock_out_last_place, start_elimination,
+};
+use crate::games::racing::generate::{GENERATED_TRACK, spawn_generated_track};
+use crate::games::racing::items::{
+    HeldItem, ITEM_BUTTON, apply_item_effects, car_items, dry_up_oil, item_hits, pick_up_items,
+    respawn_item_boxes, steer_projectiles, use_items,
+};
+use crate::games::racing::minimap::{despawn_minimap, spawn_minimap, update_minimap};
+use crate::games::racing::recovery::{
+    RESPAWN_BUTTON, RaceLayer, Recovery, car_layers, detect_stuck_cars, recover_cars,
+};
+pub use crate::games::racing::ai::{AiDifficulty, AiDriver};
+pub use crate::games::racing::camera::watch_player;
+pub use crate::games::racing::relay::RelayTeams;
+use crate::games::racing::relay::{
+    end_relay, hand_over, pick_teams, player_color, player_hex, start_relay,
+    start_relay_legs,
+};
+use crate::games::racing::replay::{
+    CLIP_BUTTON, PLAY_BUTTON, Replay, ReplayViewer, SavedReplay, control_replay, end_replay,
+    load_saved_replay, play_replay, record_race, save_replay, seed_saved_replay, start_recording,
+    start_viewer, update_replay_status,
+};
 use crate::games::racing::scene::on_scene_load;
 use crate::games::racing::style::CarStyle;
-use crate::games::racing::track::{LapCounter, Tether, Tragnet, TragnetAnchor};
+use crate::games::racing::surface::{OnSurface, apply_surfaces, car_surface, detect_surfaces};
+use crate::games::racing::track::{
+    LapCounter, SectorChange, Tether, TrackDirection, Tragnet, TragnetAnchor,
+};
 use crate::games::racing::ui::{
-    roster_join_leave, start_pregame_ui, update_indicators, update_table_ui,
+    group_roster_by_team, roster_join_leave, start_pregame_ui, update_indicators,
+    update_table_ui,
+};
+use crate::games::racing::time_trial::{
+    LapRecorder, TimeTrial, end_time_trial, format_lap_time, record_lap_frames, record_laps,
+    replay_ghosts, start_lap_recorders, start_time_trial, update_leaderboard,
+};
+use crate::games::racing::vehicle::{
+    CarBody, DRIFT_BUTTON, GroundHit, Vehicle, VehicleParams, vehicle_bundle,
 };
 use crate::games::{ConfigLoadState, CurrentGame, GamePhase, Player};
-use crate::{PlayerInputs, PlayerMapping, RandomSource, is_debug_mode};
+use crate::feedback::PhoneFeedback;
+use crate::identity::PlayerIdentities;
+use crate::remote::to_brp_result;
+use crate::cli::{HostOptions, is_debug_mode, is_deterministic};
+use crate::{MessageNet, PlayerInputs, PlayerMapping, PlayerNum, RandomSource};
 use avian3d::PhysicsPlugins;
 use avian3d::prelude::{
-    Collider, Friction, Gravity, LinearVelocity, LockedAxes, MaxLinearSpeed, Physics,
-    PhysicsDebugPlugin, Restitution, RigidBody, RigidBodyDisabled,
+    AngularVelocity, Collider, CollisionEventsEnabled, CollisionStarted, ComputedCenterOfMass, ComputedMass,
+    ExternalImpulse, Friction, Gravity, LinearVelocity, LockedAxes, MaxLinearSpeed, Physics,
+    PhysicsDebugPlugin, Restitution, RigidBody, RigidBodyDisabled, Sensor, SpatialQuery, SpatialQueryFilter,
 };
 use bevy::app::{App, FixedUpdate, Startup};
+use bevy::ecs::schedule::ScheduleConfigs;
+use bevy::ecs::system::ScheduleSystem;
 use bevy::asset::Handle;
 use bevy::color::palettes::css::{ORANGE_RED, WHITE};
 use bevy::gltf::{GltfAssetLabel, GltfMaterialName};
-use bevy::math::{Quat, ShapeSample, vec3};
-use bevy::pbr::{MaterialPlugin, MeshMaterial3d};
+use bevy::math::{Dir3, Quat, ShapeSample, vec3};
+use bevy::pbr::{MaterialPlugin, MeshMaterial3d, StandardMaterial};
 use bevy::prelude::{
-    AlphaMode, AmbientLight, AppExtStates, AssetServer, Assets, Bundle, Camera2d, Camera3d,
-    Children, Circle, Color, Commands, Component, ComputedStates, Condition, DefaultUiCamera,
-    DirectionalLight, Entity, Fixed, GlobalTransform, Hsla, IntoScheduleConfigs, LinearRgba, Local,
+    AlphaMode, AmbientLight, AppExtStates, AssetServer, Assets, Bundle, Camera2d,
+    Children, Circle, Color, Commands, Component, ComputedStates, Condition, DefaultUiCamera, DetectChanges,
+    DirectionalLight, Entity, EventReader, EventWriter, Fixed, GlobalTransform, Hsla, In, IntoScheduleConfigs, LinearRgba, Local,
     Mesh, Mesh3d, Meshable, Name, NextState, OnEnter, OnExit, Or, Query, Res, ResMut, Resource,
-    Scene, SceneRoot, Single, Sphere, Time, Timer, TimerMode, Transform, TransformHelper, Trigger,
-    Update, Vec3, With, Without, default, in_state, info,
+    Scene, SceneRoot, Single, Sphere, State, Time, Timer, TimerMode, Transform, TransformHelper, Trigger,
+    Update, Vec3, With, Without, default, in_state, info, not, resource_exists, warn,
 };
-use bevy_fly_camera::{FlyCamera, FlyCameraPlugin};
+use bevy::remote::BrpResult;
+use bevy_fly_camera::FlyCameraPlugin;
+use game_42_net::controls::layout::{ControllerLayout, LayoutElement};
 use game_42_net::controls::{ButtonType, PlayerInput};
+use game_42_net::feedback::Feedback;
+use game_42_net::protocol::{HostPacket, PlayerStatus};
 use itertools::Itertools;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Serialize;
+use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::f32::consts::PI;
+use std::iter;
 // The first few sections before the "GAME" section should probably
 // be replicated for other games. I haven't figured out how to abstract
 // them yet and I don't care (yet).
@@ -50,8 +109,14 @@ use std::f32::consts::PI;
 const RACE_CHECKPOINTS: usize = 3;
 const RACE_LAPS: usize = 1;
 const GRAVITY: f32 = 20.0;
+/// The tether can't move further in a tick than this many times how far the car went
+/// (plus the track radius), so it can't jump to a bit of track the car isn't really on
+const TETHER_JUMP_SLACK: f32 = 2.0;
+/// Cars sent back for missing a checkpoint land this far before it
+const SEND_BACK_DISTANCE: f32 = 1.0;
 const COLLISION_MAT_NAME: &str = "collision";
 const TRAGNET_MAT_NAME: &str = "tragnet";
+const ITEM_BOX_MAT_NAME: &str = "item-box";
 pub const CAR_BODY_MAT_NAME: &str = "body";
 
 // --- CONFIG FILE CONSTANTS ---
@@ -65,12 +130,25 @@ const CAR_FRICTION: &str = "car-friction";
 const GROUND_FRICTION: &str = "ground-friction";
 const GROUND_RESTITUTION: &str = "ground-restitution";
 const MAX_SPEED: &str = "max-speed";
-const ACC_SPEED: &str = "acc-speed";
-const TURN_SPEED: &str = "turn-speed";
 const TRACK_RADIUS: &str = "track-radius";
 const TRAGNET_STRENGTH: &str = "tragnet-strength";
 const TRAGNET_STRENGTH_EXP: &str = "tragnet-strength-exp";
 const TRAGNET_K: &str = "tragnet-k";
+const AI_OPPONENTS: &str = "ai-opponents";
+const AI_DIFFICULTY: &str = "ai-difficulty";
+const RECOVERY_STUCK_TIME: &str = "recovery-stuck-time";
+const RECOVERY_HOLD_TIME: &str = "recovery-hold-time";
+const RECOVERY_GHOST_TIME: &str = "recovery-ghost-time";
+const MISSED_CHECKPOINT_PENALTY: &str = "missed-checkpoint-penalty";
+const CAMERA_MODE: &str = "camera-mode";
+const SURFACES: &str = "surfaces";
+const RACE_MODE: &str = "race-mode";
+const TIME_TRIAL_LAPS: &str = "time-trial-laps";
+const RELAY_TEAMS: &str = "relay-teams";
+/// Which track is raced on, from assets/gltf, or "generated" for a new one each race
+const TRACK: &str = "track";
+const GENERATED_TRACK_SIZE: &str = "generated-track-size";
+const VEHICLE: &str = "vehicle";
 
 // --- CONFIG FILE MACROS ---
 
@@ -104,6 +182,27 @@ macro_rules! cusize {
     };
 }
 
+macro_rules! cstr {
+    ($config:expr, $i:expr) => {
+        $config[GAME][$i]
+            .as_str()
+            .expect(format!("Config value {} does not exist or isn't a string.", $i).as_str())
+    };
+}
+
+// declared after the macros so that they can use them
+mod camera;
+mod elimination;
+mod generate;
+mod items;
+mod recovery;
+mod relay;
+mod replay;
+mod scene;
+mod surface;
+mod time_trial;
+mod vehicle;
+
 // --- GAME STATE ---
 
 // I will use these computed states instead of the global states
@@ -165,12 +264,61 @@ struct SceneInfo {
     scene_handle: Handle<Scene>,
     car_handle: Handle<Scene>,
     race_start: Transform,
+    /// Name of the track, for saving records and replays under
+    track: String,
 }
 
 #[derive(Resource)]
 struct GameInfo {
     /// Checkpoints per lap. Must be >= 1
     checkpoints: usize,
+    /// Laps to finish the race. Must be >= 1
+    laps: usize,
+}
+
+/// The seed this race's random numbers come from, so that it can be raced again
+/// with `--deterministic --seed`
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct RaceSeed(pub u64);
+
+/// Players that have finished the race, in the order they finished
+#[derive(Resource, Default)]
+struct RaceResults {
+    finished: Vec<PlayerNum>,
+    /// Players knocked out of an elimination race, and how far they'd got, in the
+    /// order they went out
+    eliminated: Vec<(PlayerNum, f32)>,
+}
+
+impl RaceResults {
+    /// Everyone who's done racing, best first: the finishers, then whoever was
+    /// knocked out last
+    fn placings(&self) -> impl Iterator<Item = PlayerNum> + '_ {
+        self.finished
+            .iter()
+            .copied()
+            .chain(self.eliminated.iter().rev().map(|(player, _)| *player))
+    }
+
+    fn is_eliminated(&self, player: PlayerNum) -> bool {
+        self.eliminated.iter().any(|(p, _)| *p == player)
+    }
+}
+
+/// Last status sent to each phone, so they're only updated on change
+#[derive(Resource, Default)]
+struct SentPhoneStatus(HashMap<PlayerNum, PlayerStatus>);
+
+#[derive(Serialize, Debug)]
+pub struct Standing {
+    pub position: usize,
+    pub player: PlayerNum,
+    pub name: String,
+    /// Progress through the race, in laps
+    pub laps: f32,
+    pub finished: bool,
+    /// Knocked out of an elimination race
+    pub eliminated: bool,
 }
 
 struct EverySecondTimer(Timer);
@@ -191,19 +339,45 @@ pub fn init_app(app: &mut App) {
         .insert_resource(Gravity(Vec3::NEG_Y * GRAVITY))
         .insert_resource(GameInfo {
             checkpoints: RACE_CHECKPOINTS,
+            laps: RACE_LAPS,
         })
         .insert_resource(SceneInfo::default())
+        .init_resource::<RaceResults>()
+        .init_resource::<SentPhoneStatus>()
         // states
         .add_computed_state::<PreRacing>()
         .add_computed_state::<PlayingRacing>()
         .add_computed_state::<PostRacing>()
         // systems & observers
-        .add_systems(OnEnter(PreRacing), (start_game, start_pregame_ui))
+        .init_resource::<RaceCameraMode>()
+        .init_resource::<DirectorFocus>()
+        .init_resource::<RaceMode>()
+        .add_systems(
+            OnEnter(PreRacing),
+            (
+                choose_race_mode,
+                seed_race,
+                (
+                    start_game,
+                    spawn_race_cameras,
+                    start_pregame_ui,
+                    start_time_trial.after(start_game),
+                    start_relay,
+                )
+                    .after(choose_race_mode)
+                    .after(seed_race),
+            ),
+        )
         .add_observer(on_scene_load)
         .add_systems(
             Update,
-            (everyone_ready, roster_join_leave).run_if(in_state(PreRacing)),
+            (everyone_ready, roster_join_leave, sync_car_styles).run_if(in_state(PreRacing)),
         ) // this actually starts the game
+        .add_systems(
+            Update,
+            (pick_teams, group_roster_by_team.after(roster_join_leave))
+                .run_if(in_state(PreRacing).and(resource_exists::<RelayTeams>)),
+        )
         .add_systems(
             Update,
             (update_indicators, update_table_ui)
@@ -214,35 +388,203 @@ pub fn init_app(app: &mut App) {
             arrange_cars_pre_race.run_if(in_state(PreRacing).and(schedule_1hz)),
         )
         // teardown pregame UI and replace with during game UI
-        .add_systems(OnEnter(PlayingRacing), ui::ui_to_playing_transition)
+        .add_systems(
+            OnEnter(PlayingRacing),
+            (
+                ui::ui_to_playing_transition,
+                spawn_minimap,
+                start_lap_recorders.run_if(resource_exists::<TimeTrial>),
+                start_elimination.before(start_recording),
+                start_relay_legs
+                    .before(start_recording)
+                    .run_if(resource_exists::<RelayTeams>),
+                start_recording,
+                (arrange_cars_pre_race, reset_race_start)
+                    .chain()
+                    .run_if(is_deterministic),
+            ),
+        )
+        .add_systems(OnExit(PlayingRacing), despawn_minimap)
+        .add_systems(
+            FixedUpdate,
+            (
+                (read_player_controls, drive_ai_cars).before(recover_cars),
+                tragnet_players,
+                detect_stuck_cars.after(tragnet_players),
+                recover_cars.before(control_cars),
+                use_items.after(recover_cars),
+                apply_item_effects.after(use_items).before(control_cars),
+                detect_surfaces,
+                apply_surfaces
+                    .after(detect_surfaces)
+                    .after(apply_item_effects)
+                    .before(control_cars),
+                steer_projectiles,
+                control_cars,
+                orient_cars,
+                (record_lap_frames, replay_ghosts).run_if(resource_exists::<TimeTrial>),
+                record_race
+                    .after(orient_cars)
+                    .run_if(resource_exists::<Replay>),
+            )
+                .run_if(in_state(PlayingRacing)),
+        )
         .add_systems(
             FixedUpdate,
-            (tragnet_players, control_cars, orient_cars).run_if(in_state(PlayingRacing)),
+            (
+                race_rules(),
+                someone_finished
+                    .after(count_laps)
+                    .after(record_laps)
+                    .run_if(not(resource_exists::<Elimination>)),
+            )
+                .after(orient_cars)
+                .run_if(in_state(PlayingRacing).and(is_deterministic)),
         )
         .add_systems(
             FixedUpdate,
-            (print_debug_information, control_debug_car).run_if(is_debug_mode),
+            (
+                print_debug_information,
+                control_debug_car.before(control_cars),
+            )
+                .run_if(is_debug_mode),
         )
         .add_systems(
             Update,
-            (despawn_disconnected_players, spawn_new_players)
+            (despawn_disconnected_players, spawn_new_players, spawn_ai_players)
                 .run_if(in_state(PreRacing).and(schedule_1hz)),
         )
         .add_systems(
             Update,
-            (step_physics, count_laps).run_if(in_state(PlayingRacing)),
+            (bump_feedback, update_minimap).run_if(in_state(PlayingRacing)),
+        )
+        .add_systems(
+            Update,
+            race_rules().run_if(in_state(PlayingRacing).and(not(is_deterministic))),
+        )
+        .add_systems(
+            Update,
+            push_phone_status.run_if(in_state(PreRacing).or(in_state(PlayingRacing))),
         )
         .add_systems(
             Update,
-            someone_finished.run_if(in_state(PlayingRacing).and(schedule_1hz)),
+            (
+                direct_camera,
+                assign_chase_cameras,
+                follow_chase_cameras.after(assign_chase_cameras),
+            )
+                .run_if(in_state(PreRacing).or(in_state(PlayingRacing))),
+        )
+        .add_systems(
+            Update,
+            // after the final lap's recorded, as finished cars are despawned
+            someone_finished.after(record_laps).run_if(
+                in_state(PlayingRacing)
+                    .and(schedule_1hz)
+                    .and(not(is_deterministic))
+                    .and(not(resource_exists::<Elimination>)),
+            ),
         )
-        .add_systems(OnExit(PostRacing), shutdown_game);
+        .add_systems(Update, update_leaderboard.run_if(resource_exists::<TimeTrial>))
+        .add_systems(Update, expire_announcements.run_if(resource_exists::<Elimination>))
+        .add_systems(Startup, load_saved_replay)
+        .add_systems(
+            OnEnter(PostRacing),
+            (
+                // a saved replay skips the race, so its track is set up here
+                (
+                    seed_saved_replay,
+                    start_game.after(seed_saved_replay),
+                    spawn_race_cameras,
+                )
+                    .run_if(resource_exists::<SavedReplay>),
+                save_replay.run_if(not(resource_exists::<SavedReplay>)),
+                start_viewer.after(start_game),
+            )
+                .run_if(resource_exists::<Replay>),
+        )
+        .add_systems(
+            Update,
+            (
+                control_replay,
+                play_replay.after(control_replay),
+                update_replay_status.after(control_replay),
+                film_replay.after(play_replay),
+            )
+                .run_if(in_state(PostRacing).and(resource_exists::<ReplayViewer>)),
+        )
+        .add_systems(
+            OnExit(PostRacing),
+            (
+                shutdown_game,
+                clear_phone_status,
+                remove_ai_identities,
+                end_time_trial,
+                end_replay,
+                end_elimination,
+                end_relay,
+            ),
+        );
 
-    if is_debug_mode() {
+    if app.world().resource::<HostOptions>().debug {
         app.add_plugins(PhysicsDebugPlugin::default()); // to be removed
     }
 }
 
+/// What happens to the cars: physics, laps, items, knockouts and handovers. Normally these run every
+/// frame, but a deterministic host runs them every fixed tick instead, so that
+/// they see exactly the same thing every time a race is run.
+fn race_rules() -> ScheduleConfigs<ScheduleSystem> {
+    (
+        step_physics,
+        count_laps,
+        pick_up_items,
+        item_hits,
+        respawn_item_boxes,
+        dry_up_oil,
+        knock_out_last_place
+            .after(count_laps)
+            .run_if(resource_exists::<Elimination>),
+        hand_over
+            .after(count_laps)
+            .run_if(resource_exists::<RelayTeams>),
+        record_laps
+            .after(count_laps)
+            .run_if(resource_exists::<TimeTrial>),
+    )
+        .into_configs()
+}
+
+/// What the phones show during each phase of the race. Relay races add a team
+/// picker before the race.
+pub fn controller_layout(phase: GamePhase, relay_teams: Option<&RelayTeams>) -> ControllerLayout {
+    match phase {
+        GamePhase::PreGame => {
+            let mut layout = ControllerLayout::new("Get ready!")
+                .with(LayoutElement::Identity)
+                .with(LayoutElement::Break);
+            if let Some(teams) = relay_teams {
+                layout = layout.with(teams.picker()).with(LayoutElement::Break);
+            }
+            layout.with(LayoutElement::button(ButtonType::A, "READY").color("#2e7d32"))
+        }
+        GamePhase::PlayingGame => ControllerLayout::new("Race!")
+            .with_background("#222")
+            .with_arrows()
+            .with(LayoutElement::Break)
+            .with(LayoutElement::button(DRIFT_BUTTON, "DRIFT").color("#1565c0"))
+            .with(LayoutElement::button(ITEM_BUTTON, "ITEM").color("#f9a825"))
+            .with(LayoutElement::button(RESPAWN_BUTTON, "HOLD TO RESPAWN").color("#555")),
+        GamePhase::PostGame => ControllerLayout::new("Replay")
+            .with_background("#222")
+            .with_arrows()
+            .with(LayoutElement::Break)
+            .with(LayoutElement::button(PLAY_BUTTON, "PLAY/PAUSE").color("#2e7d32"))
+            .with(LayoutElement::button(CLIP_BUTTON, "FINAL LAP/WHOLE RACE").color("#555")),
+        _ => ControllerLayout::new("Racing"),
+    }
+}
+
 /// Marks that it belongs to this mini-game, so that it can be
 /// despawned later easily.
 #[derive(Component)]
@@ -258,22 +600,23 @@ fn start_game(
     configs: Res<Assets<Config>>,
     config_resource: Res<ConfigAccessor>,
     mut scene_info: ResMut<SceneInfo>,
+    mut race_results: ResMut<RaceResults>,
+    options: Res<HostOptions>,
+    mut random_source: ResMut<RandomSource>,
+    race_seed: Res<RaceSeed>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    saved_replay: Option<Res<SavedReplay>>,
 ) {
     info!("Starting racing game!");
+    race_results.finished.clear();
+    race_results.eliminated.clear();
     let config = configs
         .get(&config_resource.handle)
         .expect("Config does not exist");
     let ground_friction = cfloat![config, GROUND_FRICTION];
     let ground_restitution = cfloat![config, GROUND_RESTITUTION];
 
-    // debug camera
-    commands.spawn((
-        RaceGameMarker,
-        Camera3d::default(),
-        FlyCamera::default(),
-        Transform::from_xyz(0., 3., 0.),
-    ));
-
     // sun as a light
     commands.spawn((
         RaceGameMarker,
@@ -286,29 +629,45 @@ fn start_game(
         Transform::from_xyz(0., 5., 0.).looking_at(vec3(-2., -2., 0.), vec3(0., 1., 0.)),
     ));
 
-    // spawn the actual scene
-    let scene_handle =
-        asset_server.load(GltfAssetLabel::Scene(0).from_asset("gltf/race-1/race-1.glb"));
-    commands.spawn((
-        RaceGameMarker,
-        RacingSceneMarker,
-        SceneRoot(scene_handle.clone()),
-    ));
-    scene_info.scene_handle = scene_handle;
+    let track = saved_replay
+        .as_deref()
+        .map_or(cstr![config, TRACK], |saved| saved.track.as_str());
+    if track == GENERATED_TRACK {
+        // named after the seed, so that the same track is recognised again
+        scene_info.track = format!("{GENERATED_TRACK}-{}", race_seed.0);
+        scene_info.race_start = spawn_generated_track(
+            &mut commands,
+            &mut random_source.0,
+            config,
+            &mut meshes,
+            &mut materials,
+        );
+    } else {
+        // spawn the actual scene
+        scene_info.track = track.to_string();
+        let scene_handle = asset_server
+            .load(GltfAssetLabel::Scene(0).from_asset(format!("gltf/{track}/{track}.glb")));
+        commands.spawn((
+            RaceGameMarker,
+            RacingSceneMarker,
+            SceneRoot(scene_handle.clone()),
+        ));
+        scene_info.scene_handle = scene_handle;
 
-    // ground collider
-    commands.spawn((
-        RigidBody::Static,
-        Friction::new(ground_friction),
-        Restitution::new(ground_restitution),
-        Collider::cuboid(15., 0.5, 15.),
-        Transform::from_xyz(0., -0.5, 0.),
-    ));
+        // ground collider (generated tracks bring their own)
+        commands.spawn((
+            RigidBody::Static,
+            Friction::new(ground_friction),
+            Restitution::new(ground_restitution),
+            Collider::cuboid(15., 0.5, 15.),
+            Transform::from_xyz(0., -0.5, 0.),
+        ));
+    }
 
     let car_handle = asset_server.load(GltfAssetLabel::Scene(0).from_asset("gltf/car/car.glb"));
     scene_info.as_mut().car_handle = car_handle.clone();
 
-    if is_debug_mode() {
+    if options.debug {
         // debug car
         commands.spawn((
             DebugPlayer,
@@ -340,20 +699,211 @@ fn everyone_ready(mut game_phase: ResMut<NextState<GamePhase>>, player_inputs: R
 fn someone_finished(
     mut commands: Commands,
     mut game_phase: ResMut<NextState<GamePhase>>,
+    mut race_results: ResMut<RaceResults>,
+    identities: Res<PlayerIdentities>,
+    mut feedback: EventWriter<PhoneFeedback>,
     lap_counters: Query<(Entity, &LapCounter, &Player)>,
+    game_info: Res<GameInfo>,
 ) {
     if lap_counters.is_empty() {
         info!("All players finished!");
         game_phase.set(GamePhase::PostGame);
     }
     for (entity, lap_counter, player) in lap_counters {
-        if lap_counter.lap() >= RACE_LAPS {
-            info!("Player {} finished!", player.0);
+        if lap_counter.lap() >= game_info.laps {
+            info!("{} finished!", identities.nickname(player.0));
+            race_results.finished.push(player.0);
+            feedback.write(PhoneFeedback::new(player.0, Feedback::finish()));
             commands.entity(entity).despawn();
         }
     }
 }
 
+/// Finished players (in finishing order) followed by everyone still racing,
+/// ordered by how far along the track they are
+fn compute_standings<'a>(
+    tragnet: Option<&Tragnet>,
+    cars: impl Iterator<Item = (&'a Player, &'a Tether, &'a LapCounter)>,
+    race_results: &RaceResults,
+    identities: &PlayerIdentities,
+    laps: usize,
+) -> Vec<Standing> {
+    let mut racing: Vec<_> = cars
+        .filter(|(player, _, _)| {
+            !race_results.finished.contains(&player.0) && !race_results.is_eliminated(player.0)
+        })
+        .map(|(player, tether, counter)| {
+            let laps = tragnet
+                .map(|t| t.race_progress(tether, counter))
+                .unwrap_or(counter.lap() as f32);
+            (player.0, laps)
+        })
+        .collect();
+    racing.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    let finished = race_results.finished.iter().map(|p| (*p, laps as f32, true, false));
+    let racing = racing.into_iter().map(|(p, laps)| (p, laps, false, false));
+    // whoever went out last did best
+    let eliminated = race_results
+        .eliminated
+        .iter()
+        .rev()
+        .map(|(p, laps)| (*p, *laps, false, true));
+    finished
+        .chain(racing)
+        .chain(eliminated)
+        .enumerate()
+        .map(|(i, (player, laps, finished, eliminated))| Standing {
+            position: i + 1,
+            player,
+            name: identities.nickname(player),
+            laps,
+            finished,
+            eliminated,
+        })
+        .collect()
+}
+
+/// Tell each phone its colour, position, lap and sector whenever those change
+fn push_phone_status(
+    phase: Res<State<GamePhase>>,
+    cars: Query<(&Player, &Tether, &LapCounter)>,
+    directions: Query<(&Player, &TrackDirection)>,
+    held_items: Query<(&Player, &HeldItem)>,
+    lap_recorders: Query<(&Player, &LapRecorder)>,
+    tragnet: Option<Single<&Tragnet>>,
+    race_results: Res<RaceResults>,
+    game_info: Res<GameInfo>,
+    identities: Res<PlayerIdentities>,
+    player_inputs: Res<PlayerInputs>,
+    player_mapping: Res<PlayerMapping>,
+    relay_teams: Option<Res<RelayTeams>>,
+    message_net: Res<MessageNet>,
+    mut sent: ResMut<SentPhoneStatus>,
+) {
+    let playing = *phase.get() == GamePhase::PlayingGame;
+    let relay_teams = relay_teams.as_deref();
+    let counters: HashMap<_, _> = cars.iter().map(|(p, _t, c)| (p.0, c)).collect();
+    let wrong_way: HashSet<_> = directions
+        .iter()
+        .filter(|(_, d)| d.is_wrong_way())
+        .map(|(p, _)| p.0)
+        .collect();
+    let items: HashMap<_, _> = held_items
+        .iter()
+        .filter_map(|(p, held)| held.item.map(|item| (p.0, item)))
+        .collect();
+    let last_laps: HashMap<_, _> = lap_recorders
+        .iter()
+        .filter_map(|(p, recorder)| recorder.last_lap().map(|seconds| (p.0, seconds)))
+        .collect();
+    let standings = compute_standings(
+        tragnet.as_deref().copied(),
+        cars.iter(),
+        &race_results,
+        &identities,
+        game_info.laps,
+    );
+    let num_racers = standings.len();
+    let drivers: HashSet<_> = standings.iter().map(|s| s.player).collect();
+    let ready = |player: PlayerNum| {
+        player_mapping
+            .0
+            .get(&player)
+            .and_then(|user_id| player_inputs.0.get(user_id))
+            .is_some_and(|pi| pi.is_pressed(ButtonType::A))
+    };
+    let here = |player: PlayerNum| player_mapping.0.contains_key(&player);
+    sent.0.retain(|player, _| here(*player));
+    for standing in standings {
+        let counter = counters.get(&standing.player);
+        let status = PlayerStatus {
+            player: standing.player,
+            color: player_hex(standing.player, &identities, relay_teams),
+            ready: ready(standing.player),
+            position: playing.then_some((standing.position, num_racers)),
+            lap: counter
+                .filter(|_| playing)
+                .map(|c| ((c.lap() + 1).min(game_info.laps), game_info.laps)),
+            sector: counter
+                .filter(|_| playing)
+                .map(|c| (c.sector() + 1, RACE_CHECKPOINTS)),
+            message: if standing.finished {
+                Some("Finished!".to_string())
+            } else if standing.eliminated {
+                Some("Eliminated".to_string())
+            } else if playing && wrong_way.contains(&standing.player) {
+                Some("WRONG WAY!".to_string())
+            } else if playing {
+                items
+                    .get(&standing.player)
+                    .map(|item| format!("Item: {item}"))
+                    .or_else(|| {
+                        let seconds = last_laps.get(&standing.player)?;
+                        Some(format!("Last lap: {}", format_lap_time(*seconds)))
+                    })
+            } else {
+                None
+            },
+        };
+        // in a relay, whoever is waiting for their turn sees how their team is doing
+        let waiting = relay_teams
+            .map(|teams| teams.teammates(standing.player))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|teammate| !drivers.contains(teammate))
+            .map(|teammate| PlayerStatus {
+                player: teammate,
+                color: player_hex(teammate, &identities, relay_teams),
+                ready: ready(teammate),
+                message: if standing.finished {
+                    status.message.clone()
+                } else if relay_teams
+                    .and_then(|teams| teams.next_driver(standing.player, here))
+                    .is_some_and(|next| next == teammate)
+                {
+                    Some("You're up next!".to_string())
+                } else {
+                    Some(format!("{} is driving", standing.name))
+                },
+                ..status.clone()
+            })
+            .collect::<Vec<_>>();
+        for status in iter::once(status).chain(waiting) {
+            let Some(user_id) = player_mapping.0.get(&status.player) else {
+                continue;
+            };
+            if sent.0.get(&status.player) != Some(&status) {
+                message_net.send_to(*user_id, HostPacket::Status(Some(status.clone())));
+                sent.0.insert(status.player, status);
+            }
+        }
+    }
+}
+
+fn clear_phone_status(message_net: Res<MessageNet>, mut sent: ResMut<SentPhoneStatus>) {
+    sent.0.clear();
+    message_net.broadcast(HostPacket::Status(None));
+}
+
+/// `game42/race_standings` remote method
+pub fn race_standings(
+    In(_params): In<Option<Value>>,
+    tragnet: Option<Single<&Tragnet>>,
+    cars: Query<(&Player, &Tether, &LapCounter)>,
+    race_results: Res<RaceResults>,
+    game_info: Res<GameInfo>,
+    identities: Res<PlayerIdentities>,
+) -> BrpResult {
+    let standings = compute_standings(
+        tragnet.as_deref().copied(),
+        cars.iter(),
+        &race_results,
+        &identities,
+        game_info.laps,
+    );
+    to_brp_result(standings)
+}
+
 /// Arrange the cars into lines behind the starting line
 fn arrange_cars_pre_race(
     players: Query<(&mut Transform, &Player)>,
@@ -401,22 +951,53 @@ fn arrange_cars_pre_race(
     }
 }
 
+/// Pick (and log) the seed for this race. A deterministic host always uses the one
+/// it was started with.
+fn seed_race(
+    mut commands: Commands,
+    mut random_source: ResMut<RandomSource>,
+    options: Res<HostOptions>,
+) {
+    let seed = if options.deterministic {
+        options.seed
+    } else {
+        random_source.0.next_u64()
+    };
+    info!("Race seed: {seed}");
+    random_source.0 = ChaCha8Rng::seed_from_u64(seed);
+    commands.insert_resource(RaceSeed(seed));
+}
+
+/// Start a deterministic race from the same place however long everyone took to get
+/// ready: cars still on the grid, and random numbers from the top of the seed
+fn reset_race_start(
+    cars: Query<(&mut LinearVelocity, &mut AngularVelocity), With<Player>>,
+    mut random_source: ResMut<RandomSource>,
+    race_seed: Res<RaceSeed>,
+) {
+    for (mut linear, mut angular) in cars {
+        linear.0 = Vec3::ZERO;
+        angular.0 = Vec3::ZERO;
+    }
+    random_source.0 = ChaCha8Rng::seed_from_u64(race_seed.0);
+}
+
 fn step_physics(mut physics_time: ResMut<Time<Physics>>, fixed_time: Res<Time<Fixed>>) {
     physics_time.advance_by(fixed_time.delta());
 }
 
 /// Keep cars on the track
-
 fn tragnet_players(
     tragnet: Single<&Tragnet>,
     cars: Query<
-        (&GlobalTransform, &mut LinearVelocity, &mut Tether),
+        (&GlobalTransform, &mut LinearVelocity, &mut Tether, &mut TrackDirection),
         (With<RaceGameMarker>, With<Player>),
     >,
     debug_car: Query<
-        (&GlobalTransform, &mut LinearVelocity, &mut Tether),
+        (&GlobalTransform, &mut LinearVelocity, &mut Tether, &mut TrackDirection),
         (With<RaceGameMarker>, With<DebugPlayer>, Without<Player>),
     >,
+    time: Res<Time>,
     configs: Res<Assets<Config>>,
     config_resource: Res<ConfigAccessor>,
 ) {
@@ -425,36 +1006,41 @@ fn tragnet_players(
     let tragnet_strength = cfloat![config, TRAGNET_STRENGTH];
     let tragnet_exp = cfloat![config, TRAGNET_STRENGTH_EXP];
     let tragnet_k = cusize![config, TRAGNET_K];
+    let dt = time.delta_secs();
+    // tragnet-k is how many anchors either side of the tether to look for the car
+    let max_window = tragnet_k as f32 * tragnet.anchor_spacing();
     let all_cars = cars.into_iter().chain(debug_car);
-    for (i, (transform, mut lv, mut tether)) in all_cars.enumerate() {
-        tragnet.update_tether(tether.as_mut(), transform.translation(), tragnet_k);
-        let target = tragnet.get_tether_transform(tether.as_ref());
-        let to_target = target.translation - transform.translation();
-        let to_target_dist = to_target.length();
-        let strength = f32::max((to_target_dist - track_radius).signum(), 0.0);
-        let tragnet_pull =
-            to_target.normalize() * f32::powf(strength, tragnet_exp) * tragnet_strength;
+    for (transform, mut lv, mut tether, mut direction) in all_cars {
+        let before = match *tether {
+            Tether::Along(distance) => Some(distance),
+            Tether::Lost => None,
+        };
+        // don't believe the tether moving further than the car could have
+        let plausible = lv.0.length() * dt * TETHER_JUMP_SLACK + track_radius;
+        let window = max_window.min(plausible);
+        let point = tragnet.update_tether(tether.as_mut(), transform.translation(), window);
+        let moved = before.map_or(0.0, |before| tragnet.signed_gap(before, point.distance));
+        direction.update(moved, lv.0.dot(point.tangent), dt);
+
+        // only push sideways, so cars aren't tugged back and forth along the track
+        let inwards = -point.left() * point.lateral.signum();
+        let strength = f32::max((point.lateral.abs() - track_radius).signum(), 0.0);
+        let tragnet_pull = inwards * f32::powf(strength, tragnet_exp) * tragnet_strength;
         if strength > 0.0 {
-            lv.0 *= tragnet_strength;
+            let sideways = point.left() * lv.0.dot(point.left());
+            lv.0 -= sideways * (1.0 - tragnet_strength);
         }
         lv.0 += tragnet_pull;
     }
 }
 
-/// Keep the cars from tipping over and make them move in the direction
-/// consistent with their wheels.
+/// Keep the cars from tipping over
 fn orient_cars(
-    cars: Query<
-        (&mut Transform, &mut LinearVelocity),
-        (With<RaceGameMarker>, Without<DebugPlayer>, With<Player>),
-    >,
-    debug_car: Query<
-        (&mut Transform, &mut LinearVelocity),
-        (With<RaceGameMarker>, With<DebugPlayer>, Without<Player>),
-    >,
+    cars: Query<&mut Transform, (With<RaceGameMarker>, Without<DebugPlayer>, With<Player>)>,
+    debug_car: Query<&mut Transform, (With<RaceGameMarker>, With<DebugPlayer>, Without<Player>)>,
 ) {
     let all_cars = cars.into_iter().chain(debug_car);
-    for (mut transform, mut lv) in all_cars {
+    for mut transform in all_cars {
         // rotate up y, and see how far it is from actual upright
         let car_up = transform.rotation.mul_vec3(Vec3::Y);
         let upright_angle = car_up.angle_between(Vec3::Y);
@@ -463,10 +1049,6 @@ fn orient_cars(
             let correction = Quat::slerp(Quat::IDENTITY, rotation, 0.1);
             transform.rotation = correction * transform.rotation;
         }
-
-        // make it actually go in the direction it's travelling
-        let speed = lv.0.dot(transform.forward().as_vec3());
-        lv.0 = speed * transform.forward().as_vec3();
     }
 }
 
@@ -481,17 +1063,24 @@ fn car_bundle(
     let car_friction = cfloat![config, CAR_FRICTION];
     let car_size = cfloat![config, CAR_SIZE];
     let car_restitution = cfloat![config, CAR_RESTITUTION];
+    let max_speed = cfloat![config, MAX_SPEED];
+    let vehicle = VehicleParams::from_config(config);
     (
         RaceGameMarker,
         RigidBody::Dynamic,
         Friction::new(car_friction),
         Restitution::new(car_restitution),
         Collider::cuboid(0.5, 0.5, 1.),
+        CollisionEventsEnabled,
         transform.with_scale(Vec3::splat(car_size)),
         SceneRoot(scene_info.car_handle.clone()),
         Tether::Lost,
         LapCounter::at_start(RACE_CHECKPOINTS),
+        TrackDirection::default(),
         CarStyle::new(color),
+        ControlOutput::default(),
+        (Recovery::default(), car_layers()),
+        (car_items(), car_surface(max_speed), vehicle_bundle(vehicle)),
     )
 }
 
@@ -501,6 +1090,7 @@ fn spawn_new_players(
     mut random_source: ResMut<RandomSource>,
     cars: Query<&Player, With<RaceGameMarker>>,
     player_mapping: Res<PlayerMapping>,
+    identities: Res<PlayerIdentities>,
     configs: Res<Assets<Config>>,
     config_resource: Res<ConfigAccessor>,
     scene_info: Res<SceneInfo>,
@@ -509,7 +1099,8 @@ fn spawn_new_players(
     let track_radius = cfloat![config, TRACK_RADIUS];
     let spawn_area = Circle::new(track_radius);
     let spawned_cars: HashSet<_> = cars.into_iter().map(|p| p.0).collect();
-    for player in player_mapping.0.keys() {
+    // in player order, so that the same seed always puts players in the same places
+    for player in player_mapping.0.keys().sorted() {
         if !spawned_cars.contains(player) {
             let pos = spawn_area.sample_interior(&mut random_source.0);
             let mut spawn_transform = scene_info.race_start;
@@ -521,16 +1112,109 @@ fn spawn_new_players(
                     scene_info.as_ref(),
                     &configs,
                     &config_resource,
-                    Color::Hsla(Hsla::hsl((65. * *player as f32) % 360., 1.0, 0.5)),
+                    identities.color(*player),
                 ),
             ));
         }
     }
 }
 
+/// Fill the grid up with AI cars, as many as the config asks for
+fn spawn_ai_players(
+    mut commands: Commands,
+    mut random_source: ResMut<RandomSource>,
+    ai_cars: Query<(Entity, &Player), With<AiDriver>>,
+    mut identities: ResMut<PlayerIdentities>,
+    message_net: Res<MessageNet>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+    scene_info: Res<SceneInfo>,
+    race_mode: Res<RaceMode>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let track_radius = cfloat![config, TRACK_RADIUS];
+    let opponents = if race_mode.has_ai() {
+        cusize![config, AI_OPPONENTS]
+    } else {
+        0
+    };
+    let difficulty_name = cstr![config, AI_DIFFICULTY];
+    let difficulty = AiDifficulty::from_name(difficulty_name).unwrap_or_else(|| {
+        warn!("Unknown AI difficulty {difficulty_name}, using the default");
+        AiDifficulty::default()
+    });
+    let wanted: Vec<PlayerNum> = (FIRST_AI_PLAYER..=PlayerNum::MAX).take(opponents).collect();
+    let mut roster_changed = false;
+    let mut spawned_cars = HashSet::new();
+    for (entity, player) in ai_cars {
+        if wanted.contains(&player.0) {
+            spawned_cars.insert(player.0);
+        } else {
+            // the config asks for fewer opponents now
+            commands.entity(entity).despawn();
+            identities.remove(player.0);
+            roster_changed = true;
+        }
+    }
+    let spawn_area = Circle::new(track_radius);
+    for player in wanted {
+        if spawned_cars.contains(&player) {
+            continue;
+        }
+        let color = identities.assign_default(player).color;
+        let nickname = format!("CPU {}", player - FIRST_AI_PLAYER + 1);
+        if let Err(e) = identities.set(player, &nickname, color) {
+            warn!("Could not name AI player {player}: {e}");
+        }
+        let pos = spawn_area.sample_interior(&mut random_source.0);
+        let mut spawn_transform = scene_info.race_start;
+        spawn_transform.translation += vec3(pos.x, 0.0, pos.y);
+        commands.spawn((
+            Player(player),
+            AiDriver::new(difficulty),
+            car_bundle(
+                spawn_transform,
+                scene_info.as_ref(),
+                &configs,
+                &config_resource,
+                identities.color(player),
+            ),
+        ));
+        roster_changed = true;
+    }
+    if roster_changed {
+        message_net.broadcast(identities.palette_packet());
+    }
+}
+
+/// AI players only exist for one race, so give their colours back afterwards
+fn remove_ai_identities(mut identities: ResMut<PlayerIdentities>, message_net: Res<MessageNet>) {
+    identities.0.retain(|player, _| *player < FIRST_AI_PLAYER);
+    message_net.broadcast(identities.palette_packet());
+}
+
+/// Players can change colour (or team) on their phone before the race starts
+fn sync_car_styles(
+    cars: Query<(&Player, &mut CarStyle)>,
+    identities: Res<PlayerIdentities>,
+    relay_teams: Option<Res<RelayTeams>>,
+    mut material_assets: ResMut<Assets<StandardMaterial>>,
+) {
+    if !identities.is_changed() && !relay_teams.as_ref().is_some_and(|t| t.is_changed()) {
+        return;
+    }
+    for (player, mut style) in cars {
+        let color = player_color(player.0, &identities, relay_teams.as_deref());
+        if style.color != color {
+            style.color = color;
+            style.apply_style(material_assets.as_mut());
+        }
+    }
+}
+
 fn despawn_disconnected_players(
     mut commands: Commands,
-    cars: Query<(Entity, &Player), With<RaceGameMarker>>,
+    cars: Query<(Entity, &Player), (With<RaceGameMarker>, Without<AiDriver>)>,
     player_mapping: Res<PlayerMapping>,
 ) {
     for (entity, player) in cars {
@@ -540,83 +1224,280 @@ fn despawn_disconnected_players(
     }
 }
 
-struct ControlOutput {
-    acceleration: f32,
-    turn: f32,
+/// What a car's driver (a phone, the AI or the debug keyboard) wants it to do this tick
+#[derive(Component, Default, Debug, Clone, Copy, PartialEq)]
+pub struct ControlOutput {
+    /// Forwards is positive, and backwards (braking, then reversing) is negative
+    throttle: f32,
+    /// Positive is left
+    steer: f32,
+    handbrake: bool,
 }
-fn get_control_acc(pi: &PlayerInput, acc_speed: f32, turn_speed: f32) -> ControlOutput {
-    let mut co = ControlOutput {
-        acceleration: 0.,
-        turn: 0.,
-    };
-    if pi.is_pressed(ButtonType::Up) {
-        co.acceleration += acc_speed;
-    }
-    if pi.is_pressed(ButtonType::Down) {
-        co.acceleration -= acc_speed;
-    }
-    if pi.is_pressed(ButtonType::Right) {
-        co.turn -= turn_speed;
+
+impl ControlOutput {
+    /// `throttle` and `steer` go from -1 to 1. Positive steer is left.
+    pub fn from_axes(throttle: f32, steer: f32) -> Self {
+        ControlOutput {
+            throttle: throttle.clamp(-1., 1.),
+            steer: steer.clamp(-1., 1.),
+            handbrake: false,
+        }
     }
-    if pi.is_pressed(ButtonType::Left) {
-        co.turn += turn_speed;
+
+    pub fn with_handbrake(self, handbrake: bool) -> Self {
+        ControlOutput { handbrake, ..self }
     }
-    co
 }
 
-/// Use player inputs to control car based on the player number
-pub fn control_cars(
-    cars: Query<(&mut Transform, &Player, &mut LinearVelocity), With<RaceGameMarker>>,
+fn get_controls(pi: &PlayerInput) -> ControlOutput {
+    let axis = |positive, negative| {
+        pi.is_pressed(positive) as i32 as f32 - pi.is_pressed(negative) as i32 as f32
+    };
+    ControlOutput::from_axes(
+        axis(ButtonType::Up, ButtonType::Down),
+        axis(ButtonType::Left, ButtonType::Right),
+    )
+    .with_handbrake(pi.is_pressed(DRIFT_BUTTON))
+}
+
+/// Turn player inputs into control outputs, based on the player number
+fn read_player_controls(
+    cars: Query<(&Player, &mut ControlOutput), (With<RaceGameMarker>, Without<AiDriver>)>,
     configs: Res<Assets<Config>>,
     config_resource: Res<ConfigAccessor>,
     player_inputs: Res<PlayerInputs>,
     player_mapping: Res<PlayerMapping>,
 ) {
-    let config = configs.get(&config_resource.handle).expect("no config!");
-    let acc_speed = config[GAME][ACC_SPEED].as_f64().expect("beep beep") as f32;
-    let turn_speed = cfloat![config, TURN_SPEED];
-    for (mut pos, player, mut linear_velocity) in cars {
+    for (player, mut co) in cars {
         // get the player number mapping (first connection is player 1)
         // and then get the input state for that player
-        if let Some(pi) = player_mapping
+        *co = player_mapping
             .0
             .get(&player.0)
             .and_then(|pn| player_inputs.0.get(pn))
-        {
-            let co = get_control_acc(pi, acc_speed, turn_speed);
-            let rotate_right = Quat::from_rotation_y(PI / 2.);
-            let forward = pos.rotation.mul_vec3(Vec3::Z);
-            linear_velocity.0 += forward * co.acceleration;
-            pos.rotation = pos
-                .rotation
-                .rotate_towards(pos.rotation.mul_quat(rotate_right), co.turn);
+            .map(get_controls)
+            .unwrap_or_default();
+    }
+}
+
+/// Let the AI pick control outputs for its cars
+fn drive_ai_cars(
+    tragnet: Single<&Tragnet>,
+    cars: Query<
+        (&Player, &AiDriver, &Transform, &LinearVelocity, &Tether, &mut ControlOutput),
+        With<RaceGameMarker>,
+    >,
+    time: Res<Time>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let max_speed = cfloat![config, MAX_SPEED];
+    for (player, driver, transform, lv, tether, mut co) in cars {
+        let speed = lv.0.dot(transform.rotation.mul_vec3(Vec3::Z));
+        let clock = time.elapsed_secs() + player.0 as f32;
+        let (throttle, steer) = driver.drive(transform, speed, tether, &tragnet, max_speed, clock);
+        *co = ControlOutput::from_axes(throttle, steer);
+    }
+}
+
+/// Drive every car (human, AI or debug) with its control output, through its wheels
+pub fn control_cars(
+    cars: Query<
+        (
+            Entity,
+            &Transform,
+            &ControlOutput,
+            &Vehicle,
+            &LinearVelocity,
+            &AngularVelocity,
+            &ComputedMass,
+            &ComputedCenterOfMass,
+            &mut ExternalImpulse,
+            Option<&OnSurface>,
+        ),
+        With<RaceGameMarker>,
+    >,
+    solid: Query<(), Without<Sensor>>,
+    spatial_query: SpatialQuery,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    for (entity, transform, co, vehicle, lv, av, mass, com, mut impulse, surface) in cars {
+        let body = CarBody {
+            transform: *transform,
+            velocity: lv.0,
+            angular_velocity: av.0,
+            mass: mass.value(),
+            centre_of_mass: transform.translation + transform.rotation.mul_vec3(com.0),
+            grip: surface.map_or(1.0, |on| on.params.friction),
+        };
+        // wheels only touch the track, and drive through boost pads
+        let filter = SpatialQueryFilter::from_mask(RaceLayer::Track).with_excluded_entities([entity]);
+        let ground = |origin: Vec3, direction: Vec3, max_distance: f32| {
+            let direction = Dir3::new(direction).ok()?;
+            spatial_query
+                .cast_ray_predicate(origin, direction, max_distance, true, &filter, &|e| {
+                    solid.contains(e)
+                })
+                .map(|hit| GroundHit {
+                    distance: hit.distance,
+                    normal: hit.normal,
+                })
+        };
+        for (wheel_impulse, point) in vehicle.wheel_impulses(&body, co, ground, dt) {
+            impulse.apply_impulse_at_point(wheel_impulse, point, body.centre_of_mass);
         }
     }
 }
 
 fn control_debug_car(
-    mut debug_car: Single<(&mut Transform, &mut LinearVelocity), With<DebugPlayer>>,
+    mut debug_car: Single<&mut ControlOutput, With<DebugPlayer>>,
     dpi: Res<DebugPlayerInput>,
+) {
+    **debug_car = get_controls(&dpi.player_input);
+}
+
+fn count_laps(
+    tethered_things: Query<(
+        &Tether,
+        &mut LapCounter,
+        &TrackDirection,
+        Option<&Player>,
+        Option<&mut Recovery>,
+    )>,
+    tragnet: Single<&Tragnet>,
+    identities: Res<PlayerIdentities>,
+    mut feedback: EventWriter<PhoneFeedback>,
+    game_info: Res<GameInfo>,
     configs: Res<Assets<Config>>,
     config_resource: Res<ConfigAccessor>,
 ) {
     let config = configs.get(&config_resource.handle).expect("no config!");
-    let acc_speed = cfloat![config, ACC_SPEED];
-    let turn_speed = cfloat![config, TURN_SPEED];
-    let (mut pos, mut linear_velocity) = debug_car.into_inner();
-    let co = get_control_acc(&dpi.player_input, acc_speed, turn_speed);
-    let rotate_right = Quat::from_rotation_y(PI / 2.);
-    let forward = pos.rotation.mul_vec3(Vec3::Z);
-    linear_velocity.0 += forward * co.acceleration;
-    pos.rotation = pos
-        .rotation
-        .rotate_towards(pos.rotation.mul_quat(rotate_right), co.turn);
-}
-
-fn count_laps(tethered_things: Query<(&Tether, &mut LapCounter)>, tragnet: Single<&Tragnet>) {
-    for (tether, mut counter) in tethered_things {
+    let penalty_name = cstr![config, MISSED_CHECKPOINT_PENALTY];
+    let penalty = MissedCheckpointPenalty::from_name(penalty_name).unwrap_or_else(|| {
+        warn!("Unknown missed checkpoint penalty {penalty_name}, sending cars back");
+        MissedCheckpointPenalty::Respawn
+    });
+    for (tether, mut counter, direction, player, recovery) in tethered_things {
         let current_sector = tragnet.get_current_sector(tether);
-        counter.update_sector(current_sector);
+        match counter.update_sector(current_sector, direction.is_forwards()) {
+            SectorChange::Same => {}
+            SectorChange::Advanced => {
+                let Some(player) = player else {
+                    continue;
+                };
+                if counter.sector() != 0 {
+                    feedback.write(PhoneFeedback::new(player.0, Feedback::checkpoint()));
+                } else if counter.lap() < game_info.laps {
+                    // the final lap gets the finish feedback instead (see someone_finished)
+                    feedback.write(PhoneFeedback::new(player.0, Feedback::lap()));
+                }
+            }
+            SectorChange::Missed(skipped) => {
+                if let Some(player) = player {
+                    info!("{} missed {skipped} checkpoint(s)", identities.nickname(player.0));
+                    feedback.write(PhoneFeedback::new(player.0, Feedback::bump()));
+                }
+                match (penalty, recovery) {
+                    (MissedCheckpointPenalty::Forgive, _) => counter.advance_to(current_sector),
+                    (MissedCheckpointPenalty::Respawn, Some(mut recovery)) => {
+                        let missed = tragnet.sector_start(counter.sector() + 1);
+                        recovery.send_back((missed - SEND_BACK_DISTANCE).rem_euclid(tragnet.length()));
+                    }
+                    // they'll have to go round again to get it
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// What happens to a car that skips a checkpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MissedCheckpointPenalty {
+    /// Count it anyway
+    Forgive,
+    /// It doesn't count, so they have to go all the way round again to get it
+    Lap,
+    /// Put them back just before the checkpoint they missed
+    Respawn,
+}
+
+impl MissedCheckpointPenalty {
+    fn from_name(name: &str) -> Option<MissedCheckpointPenalty> {
+        match name.to_lowercase().as_str() {
+            "forgive" => Some(MissedCheckpointPenalty::Forgive),
+            "lap" => Some(MissedCheckpointPenalty::Lap),
+            "respawn" => Some(MissedCheckpointPenalty::Respawn),
+            _ => None,
+        }
+    }
+}
+
+/// What kind of race it is
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum RaceMode {
+    /// Everyone races everyone else (and the AI)
+    #[default]
+    Race,
+    /// Last place is knocked out every lap until one car is left (see elimination.rs)
+    Elimination,
+    /// Teams take turns driving one car each (see relay.rs)
+    Relay,
+    /// No AI, just the clock and a ghost of the best lap on this track (see time_trial.rs)
+    TimeTrial,
+}
+
+impl RaceMode {
+    fn from_name(name: &str) -> Option<RaceMode> {
+        match name.to_lowercase().as_str() {
+            "race" => Some(RaceMode::Race),
+            "elimination" => Some(RaceMode::Elimination),
+            "relay" => Some(RaceMode::Relay),
+            "time-trial" => Some(RaceMode::TimeTrial),
+            _ => None,
+        }
+    }
+
+    fn has_ai(self) -> bool {
+        matches!(self, RaceMode::Race | RaceMode::Elimination)
+    }
+}
+
+/// Pick the race mode from the config, before anything else is set up for the race
+fn choose_race_mode(
+    mut race_mode: ResMut<RaceMode>,
+    mut game_info: ResMut<GameInfo>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let name = cstr![config, RACE_MODE];
+    *race_mode = RaceMode::from_name(name).unwrap_or_else(|| {
+        warn!("Unknown race mode {name}, using the default");
+        RaceMode::default()
+    });
+    game_info.laps = match *race_mode {
+        // elimination and relay races work this out when they start (see
+        // start_elimination and start_relay_legs)
+        RaceMode::Race | RaceMode::Elimination | RaceMode::Relay => RACE_LAPS,
+        RaceMode::TimeTrial => cusize![config, TIME_TRIAL_LAPS].max(1),
+    };
+    info!("Race mode: {:?}, {} lap(s)", *race_mode, game_info.laps);
+}
+
+/// Rumble both phones when two cars bump into each other
+fn bump_feedback(
+    mut collisions: EventReader<CollisionStarted>,
+    players: Query<&Player>,
+    mut feedback: EventWriter<PhoneFeedback>,
+) {
+    for CollisionStarted(a, b) in collisions.read() {
+        if let (Ok(a), Ok(b)) = (players.get(*a), players.get(*b)) {
+            feedback.write(PhoneFeedback::new(a.0, Feedback::bump()));
+            feedback.write(PhoneFeedback::new(b.0, Feedback::bump()));
+        }
     }
 }
 
@@ -0,0 +1,194 @@
+use crate::config::{Config, ConfigAccessor};
+use crate::games::Player;
+use crate::games::racing::style::CarStyle;
+use crate::games::racing::track::{Tether, Tragnet};
+use crate::games::racing::{
+    CAR_SIZE, ControlOutput, GAME, RECOVERY_GHOST_TIME, RECOVERY_HOLD_TIME, RECOVERY_STUCK_TIME,
+};
+use crate::{PlayerInputs, PlayerMapping};
+use avian3d::prelude::{AngularVelocity, CollisionLayers, LayerMask, LinearVelocity, PhysicsLayer};
+use bevy::asset::Assets;
+use bevy::math::Vec3;
+use bevy::pbr::StandardMaterial;
+use bevy::prelude::{Component, Query, Res, ResMut, Single, Time, Transform};
+use game_42_net::controls::ButtonType;
+
+// Getting unstuck. A car that hasn't made progress along the track for a while
+// (or whose player holds the respawn button) fades out, reappears on the
+// centreline facing the right way, and drives through other cars for a moment
+// so it doesn't land on top of anyone. Its lap counter is left alone.
+
+/// How long a car takes to fade out before it's moved
+const FADE_TIME: f32 = 0.5;
+/// How see-through a car is while it can't hit anything
+const GHOST_OPACITY: f32 = 0.4;
+/// Moving less than this far along the track doesn't count as progress
+const MIN_PROGRESS: f32 = 0.5;
+/// Which button players hold to respawn
+pub const RESPAWN_BUTTON: ButtonType = ButtonType::X;
+
+/// What a car can collide with. Everything in the track is on the default layer.
+#[derive(PhysicsLayer, Default, Clone, Copy, Debug)]
+pub enum RaceLayer {
+    #[default]
+    Track,
+    Car,
+    /// Respawning cars only touch the track
+    Ghost,
+    /// Item boxes, oil slicks and projectiles, which only touch cars
+    Item,
+}
+
+pub fn car_layers() -> CollisionLayers {
+    CollisionLayers::new(RaceLayer::Car, LayerMask::ALL)
+}
+
+fn ghost_layers() -> CollisionLayers {
+    CollisionLayers::new(RaceLayer::Ghost, RaceLayer::Track)
+}
+
+#[derive(Component, Default, Debug)]
+pub struct Recovery {
+    /// Where along the track the car last made progress from
+    progress_mark: Option<f32>,
+    /// How long since the car last made progress
+    stuck_for: f32,
+    /// How long the respawn button has been held
+    held_for: f32,
+    /// Somewhere else on the track to put the car, instead of where it is
+    respawn_at: Option<f32>,
+    state: RecoveryState,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+enum RecoveryState {
+    #[default]
+    Driving,
+    /// Fading out, with this many seconds to go
+    FadingOut(f32),
+    /// Back on the track but not colliding with other cars, for this many seconds
+    Ghost(f32),
+}
+
+impl Recovery {
+    pub fn is_recovering(&self) -> bool {
+        self.state != RecoveryState::Driving
+    }
+
+    /// Respawn the car at a distance along the track
+    pub fn send_back(&mut self, distance: f32) {
+        self.respawn_at = Some(distance);
+        if !matches!(self.state, RecoveryState::FadingOut(_)) {
+            self.state = RecoveryState::FadingOut(FADE_TIME);
+        }
+    }
+}
+
+/// Notice cars that are stuck, or whose players want to respawn
+pub fn detect_stuck_cars(
+    cars: Query<(&Tether, &mut Recovery, Option<&Player>)>,
+    tragnet: Single<&Tragnet>,
+    player_inputs: Res<PlayerInputs>,
+    player_mapping: Res<PlayerMapping>,
+    time: Res<Time>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let stuck_time = cfloat![config, RECOVERY_STUCK_TIME];
+    let hold_time = cfloat![config, RECOVERY_HOLD_TIME];
+    let dt = time.delta_secs();
+    let lap = tragnet.length();
+    for (tether, mut recovery, player) in cars {
+        if recovery.is_recovering() {
+            continue;
+        }
+        let held = player
+            .and_then(|p| player_mapping.0.get(&p.0))
+            .and_then(|user| player_inputs.0.get(user))
+            .is_some_and(|pi| pi.is_pressed(RESPAWN_BUTTON));
+        recovery.held_for = if held { recovery.held_for + dt } else { 0.0 };
+
+        if let Tether::Along(distance) = tether {
+            let mark = *recovery.progress_mark.get_or_insert(*distance);
+            // going forwards, allowing for crossing the start line
+            let ahead = (distance - mark).rem_euclid(lap);
+            if ahead > MIN_PROGRESS && ahead < lap / 2.0 {
+                recovery.progress_mark = Some(*distance);
+                recovery.stuck_for = 0.0;
+            } else {
+                recovery.stuck_for += dt;
+            }
+        }
+
+        if recovery.stuck_for > stuck_time || recovery.held_for > hold_time {
+            recovery.state = RecoveryState::FadingOut(FADE_TIME);
+            recovery.stuck_for = 0.0;
+            recovery.held_for = 0.0;
+        }
+    }
+}
+
+/// Fade stuck cars out, put them back on the track, and let them drive through
+/// other cars for a bit
+pub fn recover_cars(
+    cars: Query<(
+        &mut Recovery,
+        &mut Transform,
+        &mut LinearVelocity,
+        &mut AngularVelocity,
+        &mut CollisionLayers,
+        &mut ControlOutput,
+        &mut Tether,
+        &CarStyle,
+    )>,
+    tragnet: Single<&Tragnet>,
+    mut material_assets: ResMut<Assets<StandardMaterial>>,
+    time: Res<Time>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let ghost_time = cfloat![config, RECOVERY_GHOST_TIME];
+    let car_size = cfloat![config, CAR_SIZE];
+    let dt = time.delta_secs();
+    for (mut recovery, mut transform, mut lv, mut av, mut layers, mut co, mut tether, style) in cars {
+        match recovery.state {
+            RecoveryState::Driving => {}
+            RecoveryState::FadingOut(remaining) => {
+                // hold the car still while it fades
+                *co = ControlOutput::default();
+                lv.0 = Vec3::ZERO;
+                let remaining = remaining - dt;
+                if remaining > 0.0 {
+                    style.set_opacity(remaining / FADE_TIME, material_assets.as_mut());
+                    recovery.state = RecoveryState::FadingOut(remaining);
+                    continue;
+                }
+                if let Some(distance) = recovery.respawn_at.take() {
+                    *tether = Tether::Along(distance);
+                }
+                if let Tether::Along(distance) = *tether {
+                    let target = tragnet.get_tether_transform(&tether);
+                    transform.translation = target.translation + Vec3::Y * car_size;
+                    transform.rotation = target.rotation;
+                    recovery.progress_mark = Some(distance);
+                }
+                av.0 = Vec3::ZERO;
+                *layers = ghost_layers();
+                style.set_opacity(GHOST_OPACITY, material_assets.as_mut());
+                recovery.state = RecoveryState::Ghost(ghost_time);
+            }
+            RecoveryState::Ghost(remaining) => {
+                let remaining = remaining - dt;
+                if remaining > 0.0 {
+                    recovery.state = RecoveryState::Ghost(remaining);
+                    continue;
+                }
+                *layers = car_layers();
+                style.set_opacity(1.0, material_assets.as_mut());
+                recovery.state = RecoveryState::Driving;
+            }
+        }
+    }
+}
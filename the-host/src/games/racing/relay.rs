@@ -0,0 +1,281 @@
+use crate::config::{Config, ConfigAccessor};
+use crate::feedback::PhoneFeedback;
+use crate::games::Player;
+use crate::games::racing::track::{Lap, LapCounter};
+use crate::games::racing::{GAME, GameInfo, RELAY_TEAMS, RaceMode};
+use crate::identity::{PALETTE, PlayerIdentities, palette_color};
+use crate::{PlayerInputs, PlayerMapping, PlayerNum};
+use bevy::color::Color;
+use bevy::prelude::{
+    Assets, Commands, Component, DetectChangesMut, Entity, EventWriter, Query, Res, ResMut,
+    Resource, With, info,
+};
+use game_42_net::controls::layout::LayoutElement;
+use game_42_net::feedback::Feedback;
+use itertools::Itertools;
+use std::collections::BTreeMap;
+use std::iter;
+
+// Team relay races. With "race-mode" set to "relay", players are split into
+// "relay-teams" teams before the race: evenly, unless they pick a team on their
+// phone. Each team has one car (and so one lap counter) on the track. The first
+// driver takes it round, and each time it crosses the start/finish line the next
+// teammate takes over, until everyone has driven a lap. Teams show in their
+// team's colour, on the track and in the roster.
+
+/// Name of each team, and its colour as an index into the identity [PALETTE]
+const TEAMS: [(&str, usize); 4] = [("Red", 0), ("Blue", 3), ("Green", 1), ("Yellow", 2)];
+/// Id of the team picker on the phones
+pub const TEAM_CHOICE: &str = "team";
+
+/// Who is on which team
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct RelayTeams {
+    count: usize,
+    teams: BTreeMap<PlayerNum, usize>,
+}
+
+/// The car a team races, and which leg it's on
+#[derive(Component, Debug)]
+pub struct RelayCar {
+    team: usize,
+    /// Laps done when the current driver took over
+    leg: Lap,
+}
+
+impl RelayTeams {
+    fn new(count: usize) -> RelayTeams {
+        RelayTeams {
+            count: count.clamp(2, TEAMS.len()),
+            teams: BTreeMap::new(),
+        }
+    }
+
+    /// Put everyone on a team. Players who picked one go on it, and everyone else
+    /// joins whichever team is smallest at the time, in player order.
+    fn assign(&mut self, players: &[(PlayerNum, Option<usize>)]) {
+        self.teams.clear();
+        for (player, picked) in players {
+            if let Some(team) = picked.filter(|team| *team < self.count) {
+                self.teams.insert(*player, team);
+            }
+        }
+        for (player, _) in players {
+            if !self.teams.contains_key(player) {
+                let smallest = (0..self.count)
+                    .min_by_key(|team| self.members(*team).len())
+                    .unwrap_or_default();
+                self.teams.insert(*player, smallest);
+            }
+        }
+    }
+
+    pub fn team(&self, player: PlayerNum) -> Option<usize> {
+        self.teams.get(&player).copied()
+    }
+
+    /// A team's players, in the order they drive
+    fn members(&self, team: usize) -> Vec<PlayerNum> {
+        self.teams
+            .iter()
+            .filter(|(_, t)| **t == team)
+            .map(|(player, _)| *player)
+            .collect()
+    }
+
+    /// Whoever drives after `driver`: their next teammate that's still here
+    pub fn next_driver(
+        &self,
+        driver: PlayerNum,
+        here: impl Fn(PlayerNum) -> bool,
+    ) -> Option<PlayerNum> {
+        let members = self.members(self.team(driver)?);
+        let position = members.iter().position(|player| *player == driver)?;
+        members
+            .iter()
+            .cycle()
+            .skip(position + 1)
+            .take(members.len())
+            .copied()
+            .find(|player| here(*player))
+    }
+
+    /// Everyone on `player`'s team but them
+    pub fn teammates(&self, player: PlayerNum) -> Vec<PlayerNum> {
+        self.team(player).map_or(vec![], |team| {
+            let mut members = self.members(team);
+            members.retain(|member| *member != player);
+            members
+        })
+    }
+
+    pub fn name(&self, team: usize) -> String {
+        format!("{} team", TEAMS[team].0)
+    }
+
+    pub fn color(&self, team: usize) -> Color {
+        palette_color(TEAMS[team].1)
+    }
+
+    pub fn hex(&self, team: usize) -> String {
+        PALETTE[TEAMS[team].1].to_string()
+    }
+
+    /// The team picker for the phones. The first option leaves it up to the host.
+    pub fn picker(&self) -> LayoutElement {
+        LayoutElement::Choice {
+            id: TEAM_CHOICE.to_string(),
+            label: "Team".to_string(),
+            options: iter::once("Any".to_string())
+                .chain(TEAMS[..self.count].iter().map(|(name, _)| name.to_string()))
+                .collect(),
+        }
+    }
+}
+
+/// The colour a player shows up in: their team's in a relay, otherwise their own
+pub fn player_color(
+    player: PlayerNum,
+    identities: &PlayerIdentities,
+    teams: Option<&RelayTeams>,
+) -> Color {
+    teams
+        .and_then(|teams| Some(teams.color(teams.team(player)?)))
+        .unwrap_or_else(|| identities.color(player))
+}
+
+/// Same as [player_color], as CSS for the phones
+pub fn player_hex(
+    player: PlayerNum,
+    identities: &PlayerIdentities,
+    teams: Option<&RelayTeams>,
+) -> String {
+    teams
+        .and_then(|teams| Some(teams.hex(teams.team(player)?)))
+        .unwrap_or_else(|| identities.hex(player))
+}
+
+/// Set up the teams, if it's a relay
+pub fn start_relay(
+    mut commands: Commands,
+    race_mode: Res<RaceMode>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    if *race_mode != RaceMode::Relay {
+        return;
+    }
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let teams = RelayTeams::new(cusize![config, RELAY_TEAMS]);
+    info!("Relay race with {} teams", teams.count);
+    commands.insert_resource(teams);
+}
+
+pub fn end_relay(mut commands: Commands) {
+    commands.remove_resource::<RelayTeams>();
+}
+
+/// Keep the teams up to date with who's here and which team they picked
+pub fn pick_teams(
+    mut teams: ResMut<RelayTeams>,
+    player_mapping: Res<PlayerMapping>,
+    player_inputs: Res<PlayerInputs>,
+) {
+    let players: Vec<_> = player_mapping
+        .0
+        .iter()
+        .map(|(player, user_id)| {
+            let picked = player_inputs
+                .0
+                .get(user_id)
+                .and_then(|pi| pi.choice(TEAM_CHOICE))
+                .and_then(|option| option.checked_sub(1));
+            (*player, picked)
+        })
+        .sorted()
+        .collect();
+    let mut picked = teams.clone();
+    picked.assign(&players);
+    teams.set_if_neq(picked);
+}
+
+/// Keep each team's first driver's car for the team, and put everyone else's away
+/// until it's their turn. Everyone drives at least one lap.
+pub fn start_relay_legs(
+    mut commands: Commands,
+    teams: Res<RelayTeams>,
+    cars: Query<(Entity, &Player), With<LapCounter>>,
+    mut game_info: ResMut<GameInfo>,
+) {
+    game_info.laps = (0..teams.count)
+        .map(|team| teams.members(team).len())
+        .max()
+        .unwrap_or_default()
+        .max(1);
+    for (entity, player) in cars {
+        match teams.team(player.0) {
+            Some(team) if teams.members(team).first() == Some(&player.0) => {
+                info!("Player {} starts for the {}", player.0, teams.name(team));
+                commands.entity(entity).insert(RelayCar { team, leg: 0 });
+            }
+            _ => commands.entity(entity).despawn(),
+        }
+    }
+}
+
+/// Hand each team's car to the next driver when it crosses the line, or straight
+/// away if its driver has left
+pub fn hand_over(
+    cars: Query<(&mut Player, &mut RelayCar, &LapCounter)>,
+    teams: Res<RelayTeams>,
+    player_mapping: Res<PlayerMapping>,
+    game_info: Res<GameInfo>,
+    identities: Res<PlayerIdentities>,
+    mut feedback: EventWriter<PhoneFeedback>,
+) {
+    let here = |player: PlayerNum| player_mapping.0.contains_key(&player);
+    for (mut driver, mut relay, counter) in cars {
+        let crossed_line = counter.lap() > relay.leg && counter.lap() < game_info.laps;
+        if !crossed_line && here(driver.0) {
+            continue;
+        }
+        relay.leg = counter.lap();
+        let Some(next) = teams.next_driver(driver.0, here) else {
+            continue;
+        };
+        if next != driver.0 {
+            info!(
+                "{} hands over to {} for the {}",
+                identities.nickname(driver.0),
+                identities.nickname(next),
+                teams.name(relay.team)
+            );
+            feedback.write(PhoneFeedback::new(next, Feedback::lap()));
+            driver.0 = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::games::racing::relay::RelayTeams;
+
+    #[test]
+    fn teams_are_even_unless_picked() {
+        let mut teams = RelayTeams::new(2);
+        teams.assign(&[(1, None), (2, None), (3, None), (4, Some(0)), (5, None)]);
+        // player 4 picked red, so the rest even it out around them
+        assert_eq!(teams.members(0), [2, 4, 5]);
+        assert_eq!(teams.members(1), [1, 3]);
+        // someone picking a team that doesn't exist gets one anyway
+        teams.assign(&[(1, Some(7)), (2, Some(0))]);
+        assert_eq!(teams.team(1), Some(1));
+
+        teams.assign(&[(1, None), (2, None), (3, None), (5, None)]);
+        assert_eq!(teams.next_driver(1, |_| true), Some(3));
+        assert_eq!(teams.next_driver(3, |_| true), Some(1));
+        // player 3 left, so 1 goes again
+        assert_eq!(teams.next_driver(1, |p| p != 3), Some(1));
+        assert_eq!(teams.next_driver(4, |_| true), None);
+    }
+}
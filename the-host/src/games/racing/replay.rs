@@ -0,0 +1,781 @@
+use crate::cli::HostOptions;
+use crate::config::{Config, ConfigAccessor};
+use crate::debug_input::DebugPlayerInput;
+use crate::games::Player;
+use crate::games::racing::generate::GENERATED_TRACK;
+use crate::games::racing::relay::RelayTeams;
+use crate::games::racing::style::CarStyle;
+use crate::games::racing::track::{Lap, LapCounter, Sector};
+use crate::games::racing::{
+    CAR_SIZE, ControlOutput, GAME, GameInfo, RaceGameMarker, RaceResults, RaceSeed, SceneInfo,
+};
+use crate::identity::PlayerIdentities;
+use crate::{PlayerInputs, PlayerNum, RandomSource};
+use bevy::asset::{AssetServer, Assets};
+use bevy::color::{Alpha, Color};
+use bevy::math::{Quat, Vec3};
+use bevy::prelude::{
+    AppExit, BackgroundColor, Commands, Component, Entity, EventWriter, Fixed, Local, Node,
+    PositionType, Query, Res, ResMut, Resource, SceneRoot, Single, Text, TextColor, TextFont, Time,
+    Transform, UiRect, Val, Visibility, With, default, error, info, warn,
+};
+use game_42_net::controls::ButtonType;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+// Race replays. Every fixed tick of a race, each car's transform and controls
+// are recorded, along with every change to its lap counter. When the race is
+// over the recording is saved in the save directory and played back on the
+// results screen: the final lap on a loop, or the whole race. Anyone can play,
+// pause and seek through it from their phone, and switch between watching the
+// whole field and following one car (see camera.rs). A saved replay can be
+// watched again with `--replay`, which goes straight to the results screen.
+//
+// Replay files are little-endian binary:
+//   "G42R", version (u8), timestep (f32), laps (u8), seed (u64), track name
+//   car count (u8), then for each car: player (u8), name, colour (4 x u8)
+//   tick count (u32), then for each tick: frame count (u8), then for each frame:
+//     car (u8), translation (3 x f32), rotation (4 x i16), throttle (i8),
+//     steer (i8), flags (u8, 1 is the handbrake)
+//   lap change count (u32), then for each: tick (u32), car (u8), lap (u16), sector (u16)
+// Names are a length (u8) followed by that many bytes of UTF-8.
+
+const REPLAY_MAGIC: &[u8; 4] = b"G42R";
+const REPLAY_VERSION: u8 = 2;
+const HANDBRAKE_FLAG: u8 = 1;
+/// How far one press of left or right seeks
+const SEEK_SECONDS: f32 = 5.0;
+/// How long the final lap highlight keeps going after the winner crosses the line
+const HIGHLIGHT_RUN_OUT: f32 = 2.0;
+
+/// Which button plays and pauses the replay
+pub const PLAY_BUTTON: ButtonType = ButtonType::A;
+/// Which button switches between the final lap and the whole race
+pub const CLIP_BUTTON: ButtonType = ButtonType::B;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ReplayError {
+    #[error("Not a replay file")]
+    NotAReplay,
+    #[error("Replay version {0} isn't supported")]
+    UnsupportedVersion(u8),
+    #[error("Replay file ends early")]
+    Truncated,
+    #[error("Name in replay isn't UTF-8")]
+    BadName,
+    #[error("Replay refers to car {0}, which it doesn't have")]
+    UnknownCar(u8),
+    #[error("Replay timestep {0} isn't a positive number")]
+    BadTimestep(f32),
+}
+
+/// A whole race, tick by tick
+#[derive(Resource, Debug, PartialEq)]
+pub struct Replay {
+    timestep: f32,
+    laps: u8,
+    /// The race's seed (see [RaceSeed])
+    seed: u64,
+    track: String,
+    cars: Vec<RecordedCar>,
+    ticks: Vec<Vec<CarFrame>>,
+    lap_changes: Vec<LapChange>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct RecordedCar {
+    player: PlayerNum,
+    name: String,
+    color: [u8; 4],
+}
+
+/// Where one car was and what its driver was doing, for one tick
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CarFrame {
+    /// Index into the replay's cars
+    car: u8,
+    translation: Vec3,
+    rotation: Quat,
+    controls: ControlOutput,
+}
+
+/// A car reaching a new lap or sector
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LapChange {
+    tick: u32,
+    car: u8,
+    lap: u16,
+    sector: u16,
+}
+
+impl Replay {
+    fn new(timestep: f32, laps: usize, seed: u64, track: &str) -> Replay {
+        Replay {
+            timestep,
+            laps: laps as u8,
+            seed,
+            track: track.to_string(),
+            cars: vec![],
+            ticks: vec![],
+            lap_changes: vec![],
+        }
+    }
+
+    /// The final lap: from when the leader started it until just after they finished
+    fn highlight(&self) -> Range<usize> {
+        let first_tick_on = |lap: usize| {
+            self.lap_changes
+                .iter()
+                .filter(|change| change.lap as usize >= lap)
+                .map(|change| change.tick as usize)
+                .min()
+        };
+        let final_lap = (self.laps as usize).saturating_sub(1);
+        let start = if final_lap == 0 {
+            0
+        } else {
+            first_tick_on(final_lap).unwrap_or(0)
+        };
+        let run_out = (HIGHLIGHT_RUN_OUT / self.timestep) as usize;
+        let end = first_tick_on(self.laps as usize).map_or(self.ticks.len(), |finish| {
+            finish.saturating_add(run_out).min(self.ticks.len())
+        });
+        start..end.max(start)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = REPLAY_MAGIC.to_vec();
+        bytes.push(REPLAY_VERSION);
+        bytes.extend(self.timestep.to_le_bytes());
+        bytes.push(self.laps);
+        bytes.extend(self.seed.to_le_bytes());
+        write_name(&mut bytes, &self.track);
+        bytes.push(self.cars.len() as u8);
+        for car in &self.cars {
+            bytes.push(car.player);
+            write_name(&mut bytes, &car.name);
+            bytes.extend(car.color);
+        }
+        bytes.extend((self.ticks.len() as u32).to_le_bytes());
+        for frames in &self.ticks {
+            bytes.push(frames.len() as u8);
+            for frame in frames {
+                bytes.push(frame.car);
+                for v in frame.translation.to_array() {
+                    bytes.extend(v.to_le_bytes());
+                }
+                for v in frame.rotation.to_array() {
+                    bytes.extend(((v * i16::MAX as f32) as i16).to_le_bytes());
+                }
+                bytes.push((frame.controls.throttle * i8::MAX as f32) as i8 as u8);
+                bytes.push((frame.controls.steer * i8::MAX as f32) as i8 as u8);
+                bytes.push(if frame.controls.handbrake {
+                    HANDBRAKE_FLAG
+                } else {
+                    0
+                });
+            }
+        }
+        bytes.extend((self.lap_changes.len() as u32).to_le_bytes());
+        for change in &self.lap_changes {
+            bytes.extend(change.tick.to_le_bytes());
+            bytes.push(change.car);
+            bytes.extend(change.lap.to_le_bytes());
+            bytes.extend(change.sector.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Replay, ReplayError> {
+        let mut reader = ByteReader(bytes);
+        if reader.take(REPLAY_MAGIC.len())? != REPLAY_MAGIC {
+            return Err(ReplayError::NotAReplay);
+        }
+        let version = reader.u8()?;
+        if version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+        let timestep = reader.f32()?;
+        if !(timestep.is_finite() && timestep > 0.0) {
+            return Err(ReplayError::BadTimestep(timestep));
+        }
+        let mut replay = Replay::new(
+            timestep,
+            reader.u8()? as usize,
+            reader.u64()?,
+            &reader.name()?,
+        );
+        for _ in 0..reader.u8()? {
+            replay.cars.push(RecordedCar {
+                player: reader.u8()?,
+                name: reader.name()?,
+                color: reader.array()?,
+            });
+        }
+        let known_car = |car: u8| {
+            if (car as usize) < replay.cars.len() {
+                Ok(car)
+            } else {
+                Err(ReplayError::UnknownCar(car))
+            }
+        };
+        for _ in 0..reader.u32()? {
+            let mut frames = vec![];
+            for _ in 0..reader.u8()? {
+                let car = known_car(reader.u8()?)?;
+                let translation = Vec3::new(reader.f32()?, reader.f32()?, reader.f32()?);
+                let rotation: [i16; 4] =
+                    [reader.i16()?, reader.i16()?, reader.i16()?, reader.i16()?];
+                let rotation = Quat::from_array(rotation.map(|v| v as f32 / i16::MAX as f32));
+                let throttle = reader.u8()? as i8 as f32 / i8::MAX as f32;
+                let steer = reader.u8()? as i8 as f32 / i8::MAX as f32;
+                let handbrake = reader.u8()? & HANDBRAKE_FLAG != 0;
+                frames.push(CarFrame {
+                    car,
+                    translation,
+                    rotation: rotation.normalize(),
+                    controls: ControlOutput::from_axes(throttle, steer).with_handbrake(handbrake),
+                });
+            }
+            replay.ticks.push(frames);
+        }
+        for _ in 0..reader.u32()? {
+            let tick = reader.u32()?;
+            let car = known_car(reader.u8()?)?;
+            replay.lap_changes.push(LapChange {
+                tick,
+                car,
+                lap: reader.u16()?,
+                sector: reader.u16()?,
+            });
+        }
+        Ok(replay)
+    }
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    // names are short, but don't cut a character in half if one isn't
+    let mut end = name.len().min(u8::MAX as usize);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    bytes.push(end as u8);
+    bytes.extend(&name.as_bytes()[..end]);
+}
+
+struct ByteReader<'a>(&'a [u8]);
+
+impl ByteReader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], ReplayError> {
+        if self.0.len() < n {
+            return Err(ReplayError::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ReplayError> {
+        Ok(self.take(N)?.try_into().expect("took the right length"))
+    }
+
+    fn u8(&mut self) -> Result<u8, ReplayError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ReplayError> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn i16(&mut self) -> Result<i16, ReplayError> {
+        self.array().map(i16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, ReplayError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, ReplayError> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> Result<f32, ReplayError> {
+        self.array().map(f32::from_le_bytes)
+    }
+
+    fn name(&mut self) -> Result<String, ReplayError> {
+        let len = self.u8()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| ReplayError::BadName)
+    }
+}
+
+pub fn start_recording(
+    mut commands: Commands,
+    fixed_time: Res<Time<Fixed>>,
+    game_info: Res<GameInfo>,
+    race_seed: Res<RaceSeed>,
+    scene_info: Res<SceneInfo>,
+) {
+    let timestep = fixed_time.timestep().as_secs_f32();
+    commands.insert_resource(Replay::new(
+        timestep,
+        game_info.laps,
+        race_seed.0,
+        &scene_info.track,
+    ));
+}
+
+/// Record every car for this tick
+pub fn record_race(
+    cars: Query<
+        (
+            Entity,
+            &Player,
+            &Transform,
+            &ControlOutput,
+            &LapCounter,
+            &CarStyle,
+        ),
+        With<RaceGameMarker>,
+    >,
+    mut replay: ResMut<Replay>,
+    // by car rather than by player, as relay cars change drivers
+    mut last_laps: Local<HashMap<Entity, (Lap, Sector)>>,
+    identities: Res<PlayerIdentities>,
+) {
+    if replay.ticks.is_empty() {
+        // a new race
+        last_laps.clear();
+    }
+    let tick = replay.ticks.len() as u32;
+    let mut frames = vec![];
+    for (entity, player, transform, controls, counter, style) in cars {
+        let car = replay
+            .cars
+            .iter()
+            .position(|c| c.player == player.0)
+            .unwrap_or_else(|| {
+                replay.cars.push(RecordedCar {
+                    player: player.0,
+                    name: identities.nickname(player.0),
+                    color: style.color.to_srgba().to_u8_array(),
+                });
+                replay.cars.len() - 1
+            }) as u8;
+        frames.push(CarFrame {
+            car,
+            translation: transform.translation,
+            rotation: transform.rotation,
+            controls: *controls,
+        });
+        let lap = (counter.lap(), counter.sector());
+        if last_laps
+            .insert(entity, lap)
+            .is_some_and(|last| last != lap)
+        {
+            replay.lap_changes.push(LapChange {
+                tick,
+                car,
+                lap: lap.0 as u16,
+                sector: lap.1 as u16,
+            });
+        }
+    }
+    replay.ticks.push(frames);
+}
+
+/// Save the race to a file named after the track and when it finished
+pub fn save_replay(replay: Res<Replay>, options: Res<HostOptions>) {
+    let finished = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let path = Path::new(&options.save_dir)
+        .join("replays")
+        .join(format!("{}-{finished}.replay", replay.track));
+    let saved = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&path, replay.encode()));
+    match saved {
+        Ok(()) => info!(
+            "Saved the replay (seed {}) to {}",
+            replay.seed,
+            path.display()
+        ),
+        Err(e) => warn!("Could not save the replay to {}: {e}", path.display()),
+    }
+}
+
+/// Set when the replay being watched was loaded with `--replay`, rather than just raced
+#[derive(Resource)]
+pub struct SavedReplay {
+    /// The track to load for it, as it would be set in the config
+    pub track: String,
+}
+
+/// Read the replay file from `--replay`, if there is one
+pub fn load_saved_replay(
+    mut commands: Commands,
+    options: Res<HostOptions>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Some(path) = &options.replay else {
+        return;
+    };
+    let loaded = std::fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| Replay::decode(&bytes).map_err(|e| e.to_string()));
+    match loaded {
+        Ok(replay) => {
+            info!(
+                "Watching the replay in {path}: {} cars on {} (seed {})",
+                replay.cars.len(),
+                replay.track,
+                replay.seed
+            );
+            // generated tracks are named after the seed they're made from
+            let track = if replay.track == format!("{GENERATED_TRACK}-{}", replay.seed) {
+                GENERATED_TRACK.to_string()
+            } else {
+                replay.track.clone()
+            };
+            commands.insert_resource(SavedReplay { track });
+            commands.insert_resource(replay);
+        }
+        Err(e) => {
+            error!("Could not load the replay {path}: {e}");
+            exit.write(AppExit::error());
+        }
+    }
+}
+
+/// Use the saved replay's seed for the race's random numbers, so that a generated
+/// track comes out the same as it was raced on
+pub fn seed_saved_replay(
+    mut commands: Commands,
+    replay: Res<Replay>,
+    mut random_source: ResMut<RandomSource>,
+) {
+    random_source.0 = ChaCha8Rng::seed_from_u64(replay.seed);
+    commands.insert_resource(RaceSeed(replay.seed));
+}
+
+pub fn end_replay(mut commands: Commands) {
+    commands.remove_resource::<Replay>();
+    commands.remove_resource::<ReplayViewer>();
+    commands.remove_resource::<SavedReplay>();
+}
+
+/// Which part of the race is being watched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Clip {
+    FinalLap,
+    WholeRace,
+}
+
+/// What the camera is watching in a replay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayView {
+    Field,
+    /// Index into the replay's cars
+    Car(usize),
+}
+
+#[derive(Resource)]
+pub struct ReplayViewer {
+    clip: Clip,
+    range: Range<usize>,
+    /// Fractional, so playback runs at the right speed whatever the frame rate
+    tick: f32,
+    playing: bool,
+    pub view: ReplayView,
+}
+
+/// A car in the replay, with its index into the replay's cars
+#[derive(Component)]
+pub struct ReplayCar(pub usize);
+
+#[derive(Component)]
+pub struct ReplayStatus;
+
+/// Put the replay's cars on the track, show the results, and start the final lap highlight
+pub fn start_viewer(
+    mut commands: Commands,
+    replay: Res<Replay>,
+    race_results: Res<RaceResults>,
+    identities: Res<PlayerIdentities>,
+    relay_teams: Option<Res<RelayTeams>>,
+    scene_info: Res<SceneInfo>,
+    asset_server: Res<AssetServer>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let car_size = cfloat![config, CAR_SIZE];
+    for (i, car) in replay.cars.iter().enumerate() {
+        let [r, g, b, a] = car.color;
+        commands.spawn((
+            RaceGameMarker,
+            ReplayCar(i),
+            SceneRoot(scene_info.car_handle.clone()),
+            CarStyle::new(Color::srgba_u8(r, g, b, a)),
+            Transform::from_scale(Vec3::splat(car_size)),
+            Visibility::Hidden,
+        ));
+    }
+    let range = replay.highlight();
+    commands.insert_resource(ReplayViewer {
+        clip: Clip::FinalLap,
+        tick: range.start as f32,
+        range,
+        playing: true,
+        view: ReplayView::Field,
+    });
+
+    let font = TextFont {
+        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+        ..default()
+    };
+    let mut results = "Results".to_string();
+    for (i, player) in race_results.placings().enumerate() {
+        let mut name = identities.nickname(player);
+        // relays are won by teams, brought home by their last driver
+        if let Some(team) = relay_teams
+            .as_deref()
+            .and_then(|teams| Some(teams.name(teams.team(player)?)))
+        {
+            name = format!("{team} ({name})");
+        }
+        results += &format!("\n{}. {name}", i + 1);
+    }
+    let panel = Node {
+        position_type: PositionType::Absolute,
+        padding: UiRect::all(Val::Px(8.0)),
+        ..default()
+    };
+    commands.spawn((
+        RaceGameMarker,
+        Node {
+            left: Val::Px(10.0),
+            top: Val::Px(10.0),
+            ..panel.clone()
+        },
+        BackgroundColor(Color::BLACK.with_alpha(0.5)),
+        Text::new(results),
+        TextColor(Color::WHITE),
+        font.clone(),
+    ));
+    commands.spawn((
+        RaceGameMarker,
+        ReplayStatus,
+        Node {
+            left: Val::Px(10.0),
+            bottom: Val::Px(10.0),
+            ..panel
+        },
+        BackgroundColor(Color::BLACK.with_alpha(0.5)),
+        Text::new(""),
+        TextColor(Color::WHITE),
+        font,
+    ));
+}
+
+/// Play, pause, seek, change clip and change camera, from any phone (or the debug keyboard)
+pub fn control_replay(
+    mut viewer: ResMut<ReplayViewer>,
+    replay: Res<Replay>,
+    player_inputs: Res<PlayerInputs>,
+    debug_input: Res<DebugPlayerInput>,
+    mut held: Local<Vec<ButtonType>>,
+) {
+    let inputs: Vec<_> = player_inputs
+        .0
+        .values()
+        .chain([&debug_input.player_input])
+        .collect();
+    let buttons = [
+        PLAY_BUTTON,
+        CLIP_BUTTON,
+        ButtonType::Left,
+        ButtonType::Right,
+        ButtonType::Up,
+        ButtonType::Down,
+    ];
+    let down: Vec<_> = buttons
+        .into_iter()
+        .filter(|b| inputs.iter().any(|pi| pi.is_pressed(*b)))
+        .collect();
+    let pressed: Vec<_> = down
+        .iter()
+        .filter(|b| !held.contains(*b))
+        .copied()
+        .collect();
+    *held = down;
+
+    let seek = SEEK_SECONDS / replay.timestep;
+    let views = replay.cars.len() + 1;
+    let view_index = match viewer.view {
+        ReplayView::Field => 0,
+        ReplayView::Car(i) => i + 1,
+    };
+    for button in pressed {
+        match button {
+            PLAY_BUTTON => viewer.playing = !viewer.playing,
+            CLIP_BUTTON => {
+                viewer.clip = match viewer.clip {
+                    Clip::FinalLap => Clip::WholeRace,
+                    Clip::WholeRace => Clip::FinalLap,
+                };
+                viewer.range = match viewer.clip {
+                    Clip::FinalLap => replay.highlight(),
+                    Clip::WholeRace => 0..replay.ticks.len(),
+                };
+                viewer.tick = viewer.range.start as f32;
+            }
+            ButtonType::Left => viewer.tick -= seek,
+            ButtonType::Right => viewer.tick += seek,
+            ButtonType::Up | ButtonType::Down => {
+                let step = if button == ButtonType::Down {
+                    1
+                } else {
+                    views - 1
+                };
+                viewer.view = match (view_index + step) % views {
+                    0 => ReplayView::Field,
+                    i => ReplayView::Car(i - 1),
+                };
+            }
+            _ => {}
+        }
+    }
+    let last = viewer.range.end.saturating_sub(1).max(viewer.range.start) as f32;
+    viewer.tick = viewer.tick.clamp(viewer.range.start as f32, last);
+}
+
+/// Move the replay's cars to where they were, and move the replay on if it's playing
+pub fn play_replay(
+    mut viewer: ResMut<ReplayViewer>,
+    replay: Res<Replay>,
+    cars: Query<(&ReplayCar, &mut Transform, &mut Visibility)>,
+    time: Res<Time>,
+) {
+    if viewer.playing {
+        viewer.tick += time.delta_secs() / replay.timestep;
+        if viewer.tick >= viewer.range.end as f32 {
+            // go round again
+            viewer.tick = viewer.range.start as f32;
+        }
+    }
+    let frames = replay.ticks.get(viewer.tick as usize);
+    for (car, mut transform, mut visibility) in cars {
+        let frame = frames.and_then(|f| f.iter().find(|frame| frame.car as usize == car.0));
+        let Some(frame) = frame else {
+            // not racing at this point, because it hadn't joined or had already finished
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        transform.translation = frame.translation;
+        transform.rotation = frame.rotation;
+        *visibility = Visibility::Inherited;
+    }
+}
+
+pub fn update_replay_status(
+    mut status: Single<&mut Text, With<ReplayStatus>>,
+    viewer: Res<ReplayViewer>,
+    replay: Res<Replay>,
+) {
+    let seconds = |tick: f32| tick * replay.timestep;
+    let clip = match viewer.clip {
+        Clip::FinalLap => "Final lap",
+        Clip::WholeRace => "Whole race",
+    };
+    let watching = match viewer.view {
+        ReplayView::Field => "everyone".to_string(),
+        ReplayView::Car(i) => replay
+            .cars
+            .get(i)
+            .map_or_else(String::new, |c| c.name.clone()),
+    };
+    let text = format!(
+        "{clip}{}  {:.1}s / {:.1}s  watching {watching}",
+        if viewer.playing { "" } else { " (paused)" },
+        seconds(viewer.tick - viewer.range.start as f32),
+        seconds(viewer.range.len() as f32),
+    );
+    if status.0 != text {
+        status.0 = text;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::games::racing::ControlOutput;
+    use crate::games::racing::replay::{CarFrame, LapChange, RecordedCar, Replay, ReplayError};
+    use bevy::math::{Quat, Vec3};
+
+    #[test]
+    fn replays_survive_saving() {
+        let mut replay = Replay::new(0.5, 2, 1029301923, "race-1");
+        replay.cars.push(RecordedCar {
+            player: 3,
+            name: "Zoë".to_string(),
+            color: [255, 0, 128, 255],
+        });
+        let frame = |x: f32| CarFrame {
+            car: 0,
+            translation: Vec3::new(x, 0.25, -3.0),
+            rotation: Quat::from_rotation_y(1.0),
+            controls: ControlOutput::from_axes(1.0, -1.0).with_handbrake(true),
+        };
+        replay.ticks = (0..20).map(|i| vec![frame(i as f32)]).collect();
+        replay.lap_changes = vec![
+            LapChange {
+                tick: 4,
+                car: 0,
+                lap: 1,
+                sector: 0,
+            },
+            LapChange {
+                tick: 12,
+                car: 0,
+                lap: 2,
+                sector: 0,
+            },
+        ];
+
+        let bytes = replay.encode();
+        let decoded = Replay::decode(&bytes).unwrap();
+        assert_eq!(decoded.cars, replay.cars);
+        assert_eq!(decoded.lap_changes, replay.lap_changes);
+        assert_eq!(decoded.ticks.len(), 20);
+        let (original, loaded) = (replay.ticks[7][0], decoded.ticks[7][0]);
+        assert_eq!(loaded.translation, original.translation);
+        assert!(loaded.rotation.angle_between(original.rotation) < 1e-3);
+        assert_eq!(loaded.controls, original.controls);
+
+        // the final lap, and a couple of seconds after the finish
+        assert_eq!(decoded.highlight(), 4..16);
+
+        assert_eq!(
+            Replay::decode(&bytes[..bytes.len() - 1]),
+            Err(ReplayError::Truncated)
+        );
+        assert_eq!(Replay::decode(b"nope"), Err(ReplayError::NotAReplay));
+        for timestep in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let mut bytes = bytes.clone();
+            let at = REPLAY_MAGIC.len() + 1;
+            bytes[at..at + 4].copy_from_slice(&timestep.to_le_bytes());
+            assert!(matches!(
+                Replay::decode(&bytes),
+                Err(ReplayError::BadTimestep(_))
+            ));
+        }
+    }
+}
@@ -1,14 +1,21 @@
 use crate::games::racing::style::{CarStyle};
-use crate::games::racing::track::{Tragnet, TragnetAnchor};
-use crate::games::racing::{COLLISION_MAT_NAME, RACE_CHECKPOINTS, RaceGameMarker, SceneInfo, TRAGNET_MAT_NAME, RacingSceneMarker, CAR_BODY_MAT_NAME};
-use avian3d::prelude::{Collider, RigidBody};
+use crate::games::racing::centreline::{
+    CentrelineError, is_centreline, points_from_extras, points_from_mesh,
+};
+use crate::games::racing::track::{Tether, Tragnet, TragnetAnchor};
+use crate::games::racing::items::item_box;
+use crate::games::racing::surface::{Surface, SurfaceParams, surface_bundle};
+use crate::config::{Config, ConfigAccessor};
+use crate::games::racing::{GAME, CAR_SIZE, GROUND_FRICTION, COLLISION_MAT_NAME, ITEM_BOX_MAT_NAME, RACE_CHECKPOINTS, RaceGameMarker, SceneInfo, TRAGNET_MAT_NAME, RacingSceneMarker, CAR_BODY_MAT_NAME};
+use avian3d::prelude::{Collider, RigidBody, Sensor};
 use bevy::asset::Assets;
-use bevy::gltf::GltfMaterialName;
-use bevy::log::{info, warn};
+use bevy::gltf::{GltfExtras, GltfMaterialName};
+use bevy::log::{error, info, warn};
 use bevy::pbr::StandardMaterial;
-use bevy::prelude::{Children, Commands, Mesh, Mesh3d, MeshMaterial3d, Name, NextState, Query, Res, ResMut, Transform, TransformHelper, Trigger};
+use bevy::prelude::{Children, Commands, Entity, Mesh, Mesh3d, MeshMaterial3d, Name, NextState, Query, Res, ResMut, Transform, TransformHelper, Trigger};
 use bevy::scene::SceneInstanceReady;
 use regex::Regex;
+use bevy::math::Vec3;
 use std::collections::HashMap;
 use crate::games::GamePhase;
 // Steps for updating/exporting the scene:
@@ -24,20 +31,16 @@ use crate::games::GamePhase;
 //              ✅ Apply Modifiers
 
 // Steps for updating the *TRACK*
-// 1. Update Bézier curves as desired, to your liking
-//      Note: the curve may be hidden
-// 2. Go to outliner, search for "tragnet." ("." is important)
-// 3. Select all, delete.
-// 4. Select "tragnet", duplicate with Shift + D
-// 4a. Ensure that it has the material "tragnet"
-// 5. Apply all modifiers.
-// 6. Tab to Edit Mode.
-// 7. Select all.
-// 8. Separate by loose parts (P > L).
-// 9. Tab back to Object Mode.
-// A. With everything selected, Set Origin to Center (Surface) (under Object)
-// B. Hide the "tragnet" object.
-// C. Export with instructions above.
+// 1. Update the centre line curve ("centreline") as desired, to your liking
+// 2. Export with the instructions above, also ticking
+//      Data:
+//          Mesh:
+//              ✅ Loose Edges
+// The curve is exported as a line mesh, and the tragnet is built from it when the
+// scene loads (see centreline.rs). The race starts at the curve's first point.
+//
+// Older tracks made of numbered "tragnet.NNN" meshes (with the material "tragnet")
+// still load, but new tracks shouldn't need them.
 
 /// This runs whenever a GLTF asset is loaded, such as the car or the track
 pub fn on_scene_load(
@@ -46,6 +49,8 @@ pub fn on_scene_load(
     mut car_style: Query<&mut CarStyle>,
     mut next_state: ResMut<NextState<GamePhase>>,
     gltf_children: Query<(&GltfMaterialName, &Mesh3d, &Name)>,
+    named: Query<(&Name, Option<&GltfExtras>)>,
+    meshes_3d: Query<&Mesh3d>,
     mesh_materials: Query<&MeshMaterial3d<StandardMaterial>>,
     mut material_assets: ResMut<Assets<StandardMaterial>>,
     meshes: Res<Assets<Mesh>>,
@@ -53,17 +58,37 @@ pub fn on_scene_load(
     transform_helper: TransformHelper,
     mut scene_info: ResMut<SceneInfo>,
     racing_scene_marker: Query<&RacingSceneMarker>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
 ) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let ground_friction = cfloat![config, GROUND_FRICTION];
+    let car_size = cfloat![config, CAR_SIZE];
     let mut current_style = car_style.get_mut(trigger.target()).ok();
     info!("Scene Instance Ready: {:?}", trigger.target());
-    let re = Regex::new(r"\d+").ok().unwrap();
-    let get_index_from_name = |name| re.find(name).and_then(|m| m.as_str().parse::<usize>().ok());
     let mut anchors = HashMap::new();
+    let mut centreline: Option<Vec<Vec3>> = None;
     for descendant in children
         .iter_descendants(trigger.target())
         .collect::<Vec<_>>()
         .into_iter()
     {
+        if let Ok((name, extras)) = named.get(descendant) {
+            if centreline.is_none() && is_centreline(name.as_str()) {
+                match centreline_points(descendant, extras, &children, &meshes_3d, &meshes) {
+                    Ok((source, points)) => match transform_helper.compute_global_transform(source) {
+                        Ok(global) => {
+                            centreline = Some(
+                                points.into_iter().map(|p| global.transform_point(p)).collect(),
+                            );
+                            commands.entity(source).remove::<Mesh3d>();
+                        }
+                        Err(e) => error!("Could not place the centreline {name}: {e}"),
+                    },
+                    Err(e) => error!("Could not build the tragnet from {name}: {e}"),
+                }
+            }
+        }
         if let Ok((gltf_name, mesh, name)) = gltf_children.get(descendant) {
             // add collider to it
             if gltf_name.0 == COLLISION_MAT_NAME {
@@ -79,13 +104,39 @@ pub fn on_scene_load(
                         warn!("Unable to generate collider for {name}!",)
                     }
                 }
+            } else if gltf_name.0 == ITEM_BOX_MAT_NAME {
+                // stays visible, but can be driven through
+                match meshes.get(&mesh.0).and_then(Collider::convex_hull_from_mesh) {
+                    Some(collider) => {
+                        commands.entity(descendant).insert(item_box(collider));
+                    }
+                    None => warn!("Unable to generate item box collider for {name}!"),
+                }
+            } else if let Some(surface) = Surface::from_material_name(&gltf_name.0) {
+                let params = SurfaceParams::from_config(config, surface);
+                let bundle = meshes
+                    .get(&mesh.0)
+                    .and_then(|m| surface_bundle(surface, m, ground_friction, &params, car_size));
+                match bundle {
+                    Some(bundle) => {
+                        let mut entity = commands.entity(descendant);
+                        entity.insert(bundle);
+                        if surface.is_sensor() {
+                            entity.insert(Sensor);
+                        }
+                    }
+                    None => warn!("Unable to generate {} collider for {name}!", gltf_name.0),
+                }
             } else if gltf_name.0 == TRAGNET_MAT_NAME {
-                // add it to the tragnet
-                let index = get_index_from_name(name.as_str()).unwrap_or(0);
-                let transform = transform_helper
-                    .compute_global_transform(descendant)
-                    .unwrap()
-                    .compute_transform();
+                // an old style tragnet anchor
+                let index = anchor_index(name.as_str()).unwrap_or(0);
+                let transform = match transform_helper.compute_global_transform(descendant) {
+                    Ok(global) => global.compute_transform(),
+                    Err(e) => {
+                        warn!("Skipping tragnet anchor {name}: {e}");
+                        continue;
+                    }
+                };
                 anchors.insert(
                     index,
                     TragnetAnchor {
@@ -111,14 +162,58 @@ pub fn on_scene_load(
         }
     }
     if racing_scene_marker.get(trigger.target()).is_ok() {
-        let mut pts: Vec<_> = anchors.into_iter().collect();
-        pts.sort_by_key(|(i, _a)| *i);
-        let scene_info = scene_info.as_mut();
-        scene_info.race_start = pts[0].1.transform;
-        let new_tragnet =
-            Tragnet::new(pts.into_iter().map(|(_i, a)| a).collect(), RACE_CHECKPOINTS);
+        let points = match centreline {
+            Some(points) => points,
+            None if !anchors.is_empty() => {
+                warn!("No centreline in the track, so using the old numbered tragnet meshes");
+                let mut pts: Vec<_> = anchors.into_iter().collect();
+                pts.sort_by_key(|(i, _a)| *i);
+                pts.into_iter().map(|(_i, a)| a.transform.translation).collect()
+            }
+            None => {
+                error!("The track has no centreline, so there's no tragnet!");
+                return;
+            }
+        };
+        let new_tragnet = Tragnet::new(
+            points
+                .into_iter()
+                .map(|p| TragnetAnchor {
+                    transform: Transform::from_translation(p),
+                })
+                .collect(),
+            RACE_CHECKPOINTS,
+        );
+        scene_info.race_start = new_tragnet.get_tether_transform(&Tether::Along(0.0));
         commands.spawn((RaceGameMarker, new_tragnet));
         // start the game
         // next_state.set(GamePhase::PlayingGame);
     }
 }
+
+/// The number in an old style tragnet anchor's name ("tragnet.012" is 12)
+pub(super) fn anchor_index(name: &str) -> Option<usize> {
+    let re = Regex::new(r"\d+").ok().unwrap();
+    re.find(name).and_then(|m| m.as_str().parse::<usize>().ok())
+}
+
+/// Centreline points from an object's extras, or else its line mesh, along with
+/// the entity whose space they're in. glTF puts a mesh on a child of the object,
+/// so look there too.
+pub(super) fn centreline_points(
+    entity: Entity,
+    extras: Option<&GltfExtras>,
+    children: &Query<&Children>,
+    meshes_3d: &Query<&Mesh3d>,
+    meshes: &Assets<Mesh>,
+) -> Result<(Entity, Vec<Vec3>), CentrelineError> {
+    if let Some(points) = extras.map(|e| points_from_extras(&e.value)).transpose()?.flatten() {
+        return Ok((entity, points));
+    }
+    let (source, mesh) = std::iter::once(entity)
+        .chain(children.iter_descendants(entity))
+        .find_map(|e| meshes_3d.get(e).ok().map(|mesh| (e, mesh)))
+        .and_then(|(e, mesh)| meshes.get(&mesh.0).map(|mesh| (e, mesh)))
+        .ok_or(CentrelineError::NoPositions)?;
+    Ok((source, points_from_mesh(mesh)?))
+}
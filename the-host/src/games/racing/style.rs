@@ -1,6 +1,6 @@
 use bevy::asset::Assets;
 use bevy::pbr::StandardMaterial;
-use bevy::prelude::{Color, Component, Handle};
+use bevy::prelude::{AlphaMode, Alpha, Color, Component, Handle};
 
 #[derive(Component, Debug)]
 pub struct CarStyle {
@@ -25,4 +25,16 @@ impl CarStyle {
             material.base_color = self.color;
         }
     }
+
+    /// Fade the car in or out (1 is solid)
+    pub fn set_opacity(&self, opacity: f32, material_assets: &mut Assets<StandardMaterial>) {
+        if let Some(material) = material_assets.get_mut(self.handle.id()) {
+            material.base_color = self.color.with_alpha(opacity);
+            material.alpha_mode = if opacity < 1.0 {
+                AlphaMode::Blend
+            } else {
+                AlphaMode::Opaque
+            };
+        }
+    }
 }
\ No newline at end of file
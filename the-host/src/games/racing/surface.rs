@@ -0,0 +1,223 @@
+use crate::config::{Config, ConfigAccessor};
+use crate::games::racing::{ControlOutput, GAME, MAX_SPEED, SURFACES};
+use avian3d::prelude::{Collider, CollidingEntities, Friction, MaxLinearSpeed, RigidBody};
+use bevy::asset::Assets;
+use bevy::math::Vec3;
+use bevy::prelude::{Bundle, Component, Mesh, Query, Res, Time, warn};
+use bevy::render::mesh::VertexAttributeValues;
+use serde::Deserialize;
+
+// What the car is driving on. Track meshes with one of the surface material
+// names ("boost", "grass", "ice" or "mud") change how cars drive while they're
+// touching them: how hard they accelerate, how fast they can go, how well
+// their tyres grip and how quickly they turn. Grass, ice and mud are solid
+// ground. Boost pads are usually a thin decal sitting on the road, so they're
+// sensors instead, stretched upwards so cars pass through them. Anywhere else
+// is road, which drives normally. The numbers for each surface are in the
+// config under "surfaces", as multiples of how the car drives on road.
+
+/// How quickly the top speed comes back down after leaving a faster surface
+/// (higher is quicker), so cars coming off a boost pad aren't stopped dead
+const TOP_SPEED_EASING: f32 = 1.5;
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Surface {
+    #[default]
+    Road,
+    Boost,
+    Grass,
+    Ice,
+    Mud,
+}
+
+impl Surface {
+    const SPECIAL: [Surface; 4] = [Surface::Boost, Surface::Grass, Surface::Ice, Surface::Mud];
+
+    /// The surface a track mesh's material name stands for, if it's one
+    pub fn from_material_name(name: &str) -> Option<Surface> {
+        Surface::SPECIAL.into_iter().find(|s| s.name() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Surface::Road => "road",
+            Surface::Boost => "boost",
+            Surface::Grass => "grass",
+            Surface::Ice => "ice",
+            Surface::Mud => "mud",
+        }
+    }
+
+    /// When a car touches more than one surface, the one with the highest priority wins
+    fn priority(self) -> u8 {
+        match self {
+            Surface::Road => 0,
+            Surface::Ice => 1,
+            Surface::Grass => 2,
+            Surface::Mud => 3,
+            Surface::Boost => 4,
+        }
+    }
+
+    /// Boost pads are driven through, and everything else is driven on
+    pub fn is_sensor(self) -> bool {
+        self == Surface::Boost
+    }
+}
+
+/// How a surface changes the way cars drive, compared to the road
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct SurfaceParams {
+    pub acceleration: f32,
+    pub top_speed: f32,
+    /// How well the tyres grip, as a multiple of how well they grip the road
+    pub friction: f32,
+    pub turning: f32,
+}
+
+impl Default for SurfaceParams {
+    fn default() -> Self {
+        SurfaceParams {
+            acceleration: 1.0,
+            top_speed: 1.0,
+            friction: 1.0,
+            turning: 1.0,
+        }
+    }
+}
+
+impl SurfaceParams {
+    pub fn from_config(config: &Config, surface: Surface) -> SurfaceParams {
+        if surface == Surface::Road {
+            return SurfaceParams::default();
+        }
+        let name = surface.name();
+        serde_json::from_value(config[GAME][SURFACES][name].clone()).unwrap_or_else(|e| {
+            warn!("Config for the {name} surface is missing or wrong ({e}), so it drives like road");
+            SurfaceParams::default()
+        })
+    }
+}
+
+/// The surface a car is on at the moment, and how it drives there
+#[derive(Component, Debug, Default)]
+pub struct OnSurface {
+    pub surface: Surface,
+    pub params: SurfaceParams,
+}
+
+/// What every car needs to notice the surfaces it's on
+pub fn car_surface(max_speed: f32) -> impl Bundle {
+    (
+        OnSurface::default(),
+        CollidingEntities::default(),
+        MaxLinearSpeed(max_speed),
+    )
+}
+
+/// What a surface mesh in the track needs, or None if a collider can't be made
+/// from it. Sensor surfaces also need a Sensor.
+pub fn surface_bundle(
+    surface: Surface,
+    mesh: &Mesh,
+    ground_friction: f32,
+    params: &SurfaceParams,
+    height: f32,
+) -> Option<impl Bundle> {
+    let collider = if surface.is_sensor() {
+        raised_hull(mesh, height)?
+    } else {
+        Collider::trimesh_from_mesh(mesh)?
+    };
+    Some((
+        surface,
+        RigidBody::Static,
+        collider,
+        Friction::new(ground_friction * params.friction),
+    ))
+}
+
+/// A convex hull around the mesh and a copy of it `height` higher up, so that a
+/// flat pad becomes a block that cars are inside while they drive over it
+fn raised_hull(mesh: &Mesh, height: f32) -> Option<Collider> {
+    let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return None;
+    };
+    let points = positions
+        .iter()
+        .map(|p| Vec3::from_array(*p))
+        .flat_map(|p| [p, p + Vec3::Y * height])
+        .collect();
+    Collider::convex_hull(points)
+}
+
+/// Work out which surface each car is on, from what it's touching
+pub fn detect_surfaces(
+    cars: Query<(&CollidingEntities, &mut OnSurface)>,
+    surfaces: Query<&Surface>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    for (touching, mut on) in cars {
+        let surface = touching
+            .iter()
+            .filter_map(|e| surfaces.get(*e).ok())
+            .copied()
+            .max_by_key(|s| s.priority())
+            .unwrap_or_default();
+        if on.surface != surface {
+            on.surface = surface;
+            on.params = SurfaceParams::from_config(config, surface);
+        }
+    }
+}
+
+/// Change how cars drive, according to the surface they're on
+pub fn apply_surfaces(
+    cars: Query<(&OnSurface, &mut ControlOutput, &mut MaxLinearSpeed)>,
+    time: Res<Time>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let max_speed = cfloat![config, MAX_SPEED];
+    for (on, mut co, mut top_speed) in cars {
+        co.throttle *= on.params.acceleration;
+        co.steer *= on.params.turning;
+        let target = max_speed * on.params.top_speed;
+        top_speed.0 = if target >= top_speed.0 {
+            target
+        } else {
+            let t = 1.0 - (-TOP_SPEED_EASING * time.delta_secs()).exp();
+            top_speed.0 + (target - top_speed.0) * t
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Config;
+    use crate::games::racing::surface::{Surface, SurfaceParams};
+    use serde_json::json;
+
+    #[test]
+    fn surfaces_come_from_config() {
+        let config = Config(json!({"racing": {"surfaces": {
+            "ice": {"acceleration": 0.5, "top-speed": 1.0, "friction": 0.1, "turning": 0.6},
+            "mud": {"acceleration": 0.3}
+        }}}));
+        assert_eq!(Surface::from_material_name("ice"), Some(Surface::Ice));
+        assert_eq!(Surface::from_material_name("collision"), None);
+        let ice = SurfaceParams::from_config(&config, Surface::Ice);
+        assert_eq!(ice.turning, 0.6);
+        assert_eq!(ice.friction, 0.1);
+        // incomplete or missing surfaces drive like road
+        let road = SurfaceParams::default();
+        assert_eq!(SurfaceParams::from_config(&config, Surface::Mud), road);
+        assert_eq!(SurfaceParams::from_config(&config, Surface::Grass), road);
+        assert_eq!(SurfaceParams::from_config(&config, Surface::Road), road);
+    }
+}
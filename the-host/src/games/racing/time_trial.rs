@@ -0,0 +1,378 @@
+use crate::PlayerNum;
+use crate::cli::HostOptions;
+use crate::config::{Config, ConfigAccessor};
+use crate::games::Player;
+use crate::games::racing::style::CarStyle;
+use crate::games::racing::track::{Lap, LapCounter};
+use crate::games::racing::{CAR_SIZE, GAME, RaceGameMarker, RaceMode, SceneInfo};
+use crate::identity::PlayerIdentities;
+use bevy::asset::{AssetServer, Assets};
+use bevy::color::{Alpha, Color};
+use bevy::math::{Quat, Vec3};
+use bevy::pbr::StandardMaterial;
+use bevy::prelude::{
+    BackgroundColor, Commands, Component, DetectChanges, Entity, Fixed, Node, PositionType, Query,
+    Res, ResMut, Resource, SceneRoot, Single, Text, TextColor, TextFont, Time, Transform, UiRect,
+    Val, Visibility, With, default, info, warn,
+};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+// Solo time trial, for something to do while waiting for friends. With
+// "race-mode" set to "time-trial" there are no AI cars, and every lap each
+// player drives is timed. The car's transform is recorded every physics tick,
+// and the fastest lap ever driven on the track is kept as a ghost: a
+// see-through car that drives that lap again, starting whenever a player
+// starts a lap. The ghost and a leaderboard of the best lap by each name are
+// saved for each track in the save directory, so they're still there next time.
+
+/// How many names the leaderboard keeps
+const LEADERBOARD_SIZE: usize = 10;
+/// How see-through the ghost is
+const GHOST_OPACITY: f32 = 0.35;
+
+/// The records for the track being raced on, and where they're saved
+#[derive(Resource)]
+pub struct TimeTrial {
+    path: PathBuf,
+    records: TimeTrialRecords,
+}
+
+/// What's saved for each track
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct TimeTrialRecords {
+    /// Fastest first, one per name
+    leaderboard: Vec<LapTime>,
+    /// The fastest lap on the leaderboard, tick by tick
+    ghost: Option<GhostLap>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct LapTime {
+    name: String,
+    seconds: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct GhostLap {
+    seconds: f32,
+    frames: Vec<GhostFrame>,
+}
+
+/// Where a car was for one physics tick
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct GhostFrame {
+    translation: [f32; 3],
+    rotation: [f32; 4],
+}
+
+impl From<&Transform> for GhostFrame {
+    fn from(transform: &Transform) -> Self {
+        GhostFrame {
+            translation: transform.translation.to_array(),
+            rotation: transform.rotation.to_array(),
+        }
+    }
+}
+
+impl TimeTrialRecords {
+    fn path(save_dir: &str, track: &str) -> PathBuf {
+        Path::new(save_dir)
+            .join("time-trial")
+            .join(format!("{track}.json"))
+    }
+
+    /// The records saved at `path`, or none if nothing has been saved there yet
+    fn load(path: &Path) -> TimeTrialRecords {
+        let Ok(file) = File::open(path) else {
+            return TimeTrialRecords::default();
+        };
+        serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+            warn!(
+                "Could not read time trial records from {}: {e}",
+                path.display()
+            );
+            TimeTrialRecords::default()
+        })
+    }
+
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = File::create(path)?;
+        serde_json::to_writer(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Put a lap on the leaderboard if it's the best by that name, and make it
+    /// the ghost if it's the best by anyone. Says whether it's the new best.
+    fn add_lap(&mut self, name: &str, seconds: f32, frames: Vec<GhostFrame>) -> bool {
+        match self.leaderboard.iter_mut().find(|lap| lap.name == name) {
+            Some(lap) => lap.seconds = lap.seconds.min(seconds),
+            None => self.leaderboard.push(LapTime {
+                name: name.to_string(),
+                seconds,
+            }),
+        }
+        self.leaderboard
+            .sort_by(|a, b| a.seconds.total_cmp(&b.seconds));
+        self.leaderboard.truncate(LEADERBOARD_SIZE);
+        let best = self
+            .ghost
+            .as_ref()
+            .is_none_or(|ghost| seconds < ghost.seconds);
+        if best {
+            self.ghost = Some(GhostLap { seconds, frames });
+        }
+        best
+    }
+}
+
+/// Like 1:02.345
+pub fn format_lap_time(seconds: f32) -> String {
+    let minutes = (seconds / 60.0).floor();
+    format!("{}:{:06.3}", minutes as u32, seconds - minutes * 60.0)
+}
+
+/// Times each lap of a player's car, and records it for the ghost
+#[derive(Component, Default)]
+pub struct LapRecorder {
+    lap: Lap,
+    frames: Vec<GhostFrame>,
+    last_lap: Option<f32>,
+}
+
+impl LapRecorder {
+    pub fn last_lap(&self) -> Option<f32> {
+        self.last_lap
+    }
+}
+
+/// Replays the best lap each time its player starts a lap
+#[derive(Component)]
+pub struct Ghost {
+    player: PlayerNum,
+    tick: usize,
+    faded: bool,
+}
+
+#[derive(Component)]
+pub struct Leaderboard;
+
+/// Load the track's records, and show the leaderboard, if it's a time trial
+pub fn start_time_trial(
+    mut commands: Commands,
+    race_mode: Res<RaceMode>,
+    options: Res<HostOptions>,
+    asset_server: Res<AssetServer>,
+    scene_info: Res<SceneInfo>,
+) {
+    if *race_mode != RaceMode::TimeTrial {
+        return;
+    }
+    let path = TimeTrialRecords::path(&options.save_dir, &scene_info.track);
+    info!("Time trial records are in {}", path.display());
+    let records = TimeTrialRecords::load(&path);
+    commands.insert_resource(TimeTrial { path, records });
+    commands.spawn((
+        RaceGameMarker,
+        Leaderboard,
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.0),
+            top: Val::Px(10.0),
+            padding: UiRect::all(Val::Px(8.0)),
+            ..default()
+        },
+        BackgroundColor(Color::BLACK.with_alpha(0.4)),
+        Text::new(""),
+        TextColor(Color::WHITE),
+        TextFont {
+            font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+            ..default()
+        },
+    ));
+}
+
+pub fn end_time_trial(mut commands: Commands) {
+    commands.remove_resource::<TimeTrial>();
+}
+
+/// Start timing every player when the race starts, and give each of them a ghost to chase
+pub fn start_lap_recorders(
+    mut commands: Commands,
+    cars: Query<(Entity, &Player, &Transform)>,
+    identities: Res<PlayerIdentities>,
+    scene_info: Res<SceneInfo>,
+) {
+    for (entity, player, transform) in cars {
+        commands.entity(entity).insert(LapRecorder::default());
+        commands.spawn((
+            RaceGameMarker,
+            Ghost {
+                player: player.0,
+                tick: 0,
+                faded: false,
+            },
+            SceneRoot(scene_info.car_handle.clone()),
+            CarStyle::new(identities.color(player.0)),
+            *transform,
+            Visibility::Hidden,
+        ));
+    }
+}
+
+/// Record where each car is this tick
+pub fn record_lap_frames(cars: Query<(&Transform, &mut LapRecorder)>) {
+    for (transform, mut recorder) in cars {
+        recorder.frames.push(transform.into());
+    }
+}
+
+/// When a lap's done, put it on the leaderboard, save it, and send the player's
+/// ghost round again. Runs along with the lap counting, before finished cars are
+/// taken off the track.
+pub fn record_laps(
+    cars: Query<(&Player, &LapCounter, &mut LapRecorder)>,
+    mut ghosts: Query<&mut Ghost>,
+    mut time_trial: ResMut<TimeTrial>,
+    identities: Res<PlayerIdentities>,
+    fixed_time: Res<Time<Fixed>>,
+) {
+    for (player, counter, mut recorder) in cars {
+        if counter.lap() != recorder.lap {
+            let frames = std::mem::take(&mut recorder.frames);
+            // a frame is recorded every fixed tick
+            let seconds = frames.len() as f32 * fixed_time.timestep().as_secs_f32();
+            let name = identities.nickname(player.0);
+            recorder.lap = counter.lap();
+            recorder.last_lap = Some(seconds);
+            let TimeTrial { path, records } = time_trial.as_mut();
+            if records.add_lap(&name, seconds, frames) {
+                info!("{name} set the best lap: {}", format_lap_time(seconds));
+            }
+            if let Err(e) = records.save(path) {
+                warn!(
+                    "Could not save time trial records to {}: {e}",
+                    path.display()
+                );
+            }
+            for mut ghost in ghosts.iter_mut().filter(|g| g.player == player.0) {
+                ghost.tick = 0;
+            }
+        }
+    }
+}
+
+/// Move each ghost to where the best lap was this tick
+pub fn replay_ghosts(
+    mut commands: Commands,
+    ghosts: Query<(
+        Entity,
+        &mut Ghost,
+        &mut Transform,
+        &mut Visibility,
+        &CarStyle,
+    )>,
+    cars: Query<&Player, With<LapRecorder>>,
+    time_trial: Res<TimeTrial>,
+    mut material_assets: ResMut<Assets<StandardMaterial>>,
+    configs: Res<Assets<Config>>,
+    config_resource: Res<ConfigAccessor>,
+) {
+    let config = configs.get(&config_resource.handle).expect("no config!");
+    let car_size = cfloat![config, CAR_SIZE];
+    for (entity, mut ghost, mut transform, mut visibility, style) in ghosts {
+        if !cars.iter().any(|p| p.0 == ghost.player) {
+            // finished or gone
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let frame = time_trial
+            .records
+            .ghost
+            .as_ref()
+            .and_then(|lap| lap.frames.get(ghost.tick).or(lap.frames.last()));
+        let Some(frame) = frame else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        *transform = Transform {
+            translation: Vec3::from_array(frame.translation),
+            rotation: Quat::from_array(frame.rotation),
+            scale: Vec3::splat(car_size),
+        };
+        *visibility = Visibility::Inherited;
+        ghost.tick += 1;
+        // the car's material is made when its scene loads
+        if !ghost.faded && style.handle != default() {
+            style.set_opacity(GHOST_OPACITY, material_assets.as_mut());
+            ghost.faded = true;
+        }
+    }
+}
+
+pub fn update_leaderboard(
+    mut leaderboard: Single<&mut Text, With<Leaderboard>>,
+    time_trial: Res<TimeTrial>,
+) {
+    if !time_trial.is_changed() {
+        return;
+    }
+    let mut text = "Best laps".to_string();
+    for (i, lap) in time_trial.records.leaderboard.iter().enumerate() {
+        text += &format!(
+            "\n{}. {}  {}",
+            i + 1,
+            lap.name,
+            format_lap_time(lap.seconds)
+        );
+    }
+    if time_trial.records.leaderboard.is_empty() {
+        text += "\nNone yet!";
+    }
+    leaderboard.0 = text;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::games::racing::time_trial::{
+        GhostFrame, LEADERBOARD_SIZE, TimeTrialRecords, format_lap_time,
+    };
+    use bevy::prelude::Transform;
+
+    #[test]
+    fn leaderboard_keeps_the_best_laps() {
+        let frame = GhostFrame::from(&Transform::from_xyz(1.0, 2.0, 3.0));
+        let mut records = TimeTrialRecords::default();
+        assert!(records.add_lap("alice", 30.0, vec![frame; 3]));
+        assert!(!records.add_lap("bob", 31.0, vec![frame; 4]));
+        assert!(records.add_lap("bob", 29.0, vec![frame; 2]));
+        // a slower lap doesn't replace alice's best
+        assert!(!records.add_lap("alice", 35.0, vec![]));
+        let names: Vec<_> = records
+            .leaderboard
+            .iter()
+            .map(|l| (l.name.as_str(), l.seconds))
+            .collect();
+        assert_eq!(names, [("bob", 29.0), ("alice", 30.0)]);
+        assert_eq!(records.ghost.as_ref().map(|g| g.frames.len()), Some(2));
+
+        for i in 0..20 {
+            records.add_lap(&format!("player {i}"), 40.0 + i as f32, vec![]);
+        }
+        assert_eq!(records.leaderboard.len(), LEADERBOARD_SIZE);
+        assert_eq!(records.leaderboard[0].name, "bob");
+
+        // survives being saved
+        let json = serde_json::to_string(&records).unwrap();
+        assert_eq!(
+            serde_json::from_str::<TimeTrialRecords>(&json).unwrap(),
+            records
+        );
+        assert_eq!(format_lap_time(62.345), "1:02.345");
+    }
+}
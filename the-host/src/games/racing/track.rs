@@ -1,5 +1,6 @@
 use bevy::math::Vec3;
 use bevy::prelude::{Component, Transform};
+use std::collections::HashMap;
 
 /// Which lap you're on (starts at 0)
 pub type Lap = usize;
@@ -8,7 +9,8 @@ pub type Sector = usize;
 
 #[derive(Component)]
 pub enum Tether {
-    Anchor(AnchorId),
+    /// Distance along the centreline from the start line
+    Along(f32),
     Lost
 }
 
@@ -16,19 +18,57 @@ pub enum Tether {
 pub struct LapCounter {
     lap: Lap,
     sector: Sector,
-    checkpoints: usize
+    checkpoints: usize,
+    /// The sector the tether was in last time
+    last_seen: Sector,
+}
+
+/// What happened when a car's tether moved to a sector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectorChange {
+    /// Nothing worth counting
+    Same,
+    /// Reached the next checkpoint
+    Advanced,
+    /// Went forwards past the next checkpoint without reaching it, skipping this many
+    Missed(usize),
 }
 
-#[derive(Copy, Clone)]
-pub struct AnchorId(usize);
+/// Going backwards this fast along the track (or faster) counts as going the wrong way
+const WRONG_WAY_SPEED: f32 = 0.5;
+/// How long someone has to go the wrong way before we tell them
+const WRONG_WAY_DELAY: f32 = 1.0;
+
+/// Which way something has been going along the track
+#[derive(Component, Default, Debug)]
+pub struct TrackDirection {
+    /// How far the tether moved last tick (negative is backwards)
+    last_move: f32,
+    /// How long it's been going the wrong way
+    wrong_way_for: f32,
+}
+
+/// How many straight pieces each span between two anchors is split into
+const SAMPLES_PER_SPAN: usize = 8;
+/// Grid cells are this many (average) segments wide
+const SEGMENTS_PER_CELL: f32 = 4.0;
 
 /// Portmanteau of "track" and "magnet"
-/// Collection of points ("anchors") along the center line of the track
-/// that are used to keep cars from going outside the track.
+/// A smooth line through points ("anchors") along the center line of the track,
+/// used to keep cars from going outside the track and to measure how far along it they are.
+///
+/// The line is a closed centripetal Catmull-Rom spline, sampled finely enough that
+/// it can be treated as a polyline. A grid over the samples makes finding the closest
+/// point quick, however many anchors there are.
 #[derive(Component)]
 pub struct Tragnet {
-    /// These act as a circular buffer
-    points: Vec<TragnetAnchor>,
+    /// How many anchors the line was built from
+    anchors: usize,
+    /// Points along the line. Segment `i` goes from sample `i` to sample `i + 1` (wrapping).
+    samples: Vec<Vec3>,
+    /// Distance along the line to each sample. The extra last entry is the length of a lap.
+    distances: Vec<f32>,
+    grid: SegmentGrid,
     /// How many checkpoints there are
     checkpoints: usize,
 }
@@ -38,72 +78,323 @@ pub struct TragnetAnchor {
     pub transform: Transform
 }
 
+/// The closest point on the tragnet to something
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackPoint {
+    /// Distance along the centreline from the start line
+    pub distance: f32,
+    pub position: Vec3,
+    /// Direction of travel along the centreline (normalized)
+    pub tangent: Vec3,
+    /// How far left (positive) or right (negative) of the centreline it is, ignoring height
+    pub lateral: f32,
+}
+
+impl TrackPoint {
+    /// Horizontal direction pointing to the left of the centreline
+    pub fn left(&self) -> Vec3 {
+        Vec3::Y.cross(self.tangent).normalize_or_zero()
+    }
+}
+
 impl Tragnet {
     pub fn new(points: Vec<TragnetAnchor>, num_checkpoints: usize) -> Tragnet {
+        assert!(!points.is_empty(), "Tragnet is empty, cannot build a centreline.");
+        let anchors: Vec<Vec3> = points.iter().map(|p| p.transform.translation).collect();
+        let samples = sample_spline(&anchors);
+        let mut distances = Vec::with_capacity(samples.len() + 1);
+        distances.push(0.0);
+        for i in 0..samples.len() {
+            let next = samples[(i + 1) % samples.len()];
+            distances.push(distances[i] + samples[i].distance(next));
+        }
+        let grid = SegmentGrid::new(&samples, distances[samples.len()]);
         Tragnet {
-            points,
+            anchors: anchors.len(),
+            samples,
+            distances,
+            grid,
             checkpoints: num_checkpoints,
         }
     }
-    
+
+    /// Length of one lap along the centreline
+    pub fn length(&self) -> f32 {
+        self.distances[self.samples.len()]
+    }
+
+    /// Average distance between the anchors the tragnet was built from
+    pub fn anchor_spacing(&self) -> f32 {
+        self.length() / self.anchors as f32
+    }
+
     pub fn get_current_sector(&self, tether: &Tether) -> Sector {
-        if let Tether::Anchor(anchor_id) = tether {
-            (anchor_id.0 as f32 / self.points.len() as f32 * self.checkpoints as f32) as usize
+        if let Tether::Along(distance) = tether {
+            let sector = (distance / self.length() * self.checkpoints as f32) as usize;
+            sector.min(self.checkpoints - 1)
         } else {
             0
         }
     }
 
-    pub fn find_nearest(&self, query: Vec3) -> AnchorId {
-        if self.points.len() == 0 {
-            panic!("Tragnet is empty, cannot find a nearest point.");
-        }
-        let mut nearest = 0;
-        let mut first = true;
-        let mut dist = 0.0;
-        for (i, p) in self.points.iter().enumerate() {
-            let dist_i = p.transform.translation.distance(query);
-            if dist_i < dist || first {
-                first = false;
-                nearest = i;
-                dist = dist_i;
-            }
-        }
-        AnchorId(nearest)
+    /// Closest point on the whole centreline
+    pub fn closest_point(&self, query: Vec3) -> TrackPoint {
+        let segment = self.grid.closest_segment(query, |i| self.segment_distance(i, query));
+        self.point_on_segment(segment, query)
     }
 
-    pub fn update_tether(&self, current: &mut Tether, query: Vec3, k: usize) -> i32 {
-        let current = match current {
+    /// Move the tether to the closest point on the centreline within `window` of where
+    /// it was, so that cars don't jump between bits of track that pass close by each other.
+    pub fn update_tether(&self, current: &mut Tether, query: Vec3, window: f32) -> TrackPoint {
+        let distance = match current {
             Tether::Lost => {
-                *current = Tether::Anchor(self.find_nearest(query));
-                return 0;
+                let point = self.closest_point(query);
+                *current = Tether::Along(point.distance);
+                return point;
             },
-            Tether::Anchor(anchor_id) => anchor_id,
+            Tether::Along(distance) => distance,
         };
-        let start_i = (current.0 + self.points.len() - k) % self.points.len();
-        let mut nearest = current.0;
-        let mut nearest_dist = self.points[nearest].transform.translation.distance(query);
-        let mut progress = 0;
-        for j in 0..=k * 2 {
-            let i = (start_i + j) % self.points.len();
-            let dist = self.points[i].transform.translation.distance(query);
-            if dist < nearest_dist {
-                nearest_dist = dist;
-                nearest = i;
-                progress = j as i32 - k as i32;
-            }
+        let segments = self.segments_between(*distance - window, *distance + window);
+        let nearest = segments
+            .min_by(|a, b| {
+                self.segment_distance(*a, query)
+                    .total_cmp(&self.segment_distance(*b, query))
+            })
+            .expect("Tragnet has no segments");
+        let point = self.point_on_segment(nearest, query);
+        *distance = point.distance;
+        point
+    }
+
+    /// How far along the race something is, in laps (e.g. 1.5 is halfway through the second lap)
+    pub fn race_progress(&self, tether: &Tether, counter: &LapCounter) -> f32 {
+        let checkpoints = self.checkpoints as f32;
+        let completed = (counter.lap() * self.checkpoints + counter.sector()) as f32 / checkpoints;
+        let Tether::Along(distance) = tether else {
+            return completed;
+        };
+        // only count progress within the sector if the lap counter agrees on the sector
+        if self.get_current_sector(tether) != counter.sector() {
+            return completed;
         }
-        current.0 = nearest;
-        progress
+        let sector_len = self.length() / checkpoints;
+        let in_sector = (distance - counter.sector() as f32 * sector_len) / sector_len;
+        completed + in_sector.clamp(0.0, 1.0) / checkpoints
     }
-    
+
+    /// How far along the centreline a sector starts
+    pub fn sector_start(&self, sector: Sector) -> f32 {
+        self.length() * (sector % self.checkpoints) as f32 / self.checkpoints as f32
+    }
+
+    /// Signed distance from one point on the track to another, taking the short way
+    /// round (so crossing the start line forwards is a small positive number)
+    pub fn signed_gap(&self, from: f32, to: f32) -> f32 {
+        let gap = (to - from).rem_euclid(self.length());
+        if gap > self.length() / 2.0 {
+            gap - self.length()
+        } else {
+            gap
+        }
+    }
+
+    /// Where the centreline is `ahead` further along the track than the tether
+    pub fn position_ahead(&self, tether: &Tether, ahead: f32) -> Option<Vec3> {
+        let Tether::Along(distance) = tether else {
+            return None;
+        };
+        Some(self.point_at(distance + ahead).0)
+    }
+
+    /// Position and direction of travel at a distance along the centreline (wraps around)
+    pub fn point_at(&self, distance: f32) -> (Vec3, Vec3) {
+        let distance = distance.rem_euclid(self.length());
+        let segment = self.segment_at(distance);
+        let (start, end) = self.segment(segment);
+        let segment_len = self.distances[segment + 1] - self.distances[segment];
+        let t = if segment_len > 0.0 {
+            (distance - self.distances[segment]) / segment_len
+        } else {
+            0.0
+        };
+        (start.lerp(end, t), (end - start).normalize_or_zero())
+    }
+
+    /// Centreline point the tether is on, turned like a car driving along the track
+    /// (cars drive towards their local +Z)
     pub fn get_tether_transform(&self, current: &Tether) -> Transform {
-        if let Tether::Anchor(anchor_id) = current {
-            self.points[anchor_id.0].transform.clone()
+        if let Tether::Along(distance) = current {
+            let (position, tangent) = self.point_at(*distance);
+            Transform::from_translation(position).looking_to(-tangent, Vec3::Y)
         } else {
             panic!("Tether is lost!");
         }
     }
+
+    fn segment(&self, i: usize) -> (Vec3, Vec3) {
+        (self.samples[i], self.samples[(i + 1) % self.samples.len()])
+    }
+
+    /// Which segment a distance (already wrapped to one lap) falls on
+    fn segment_at(&self, distance: f32) -> usize {
+        let i = self.distances.partition_point(|d| *d <= distance);
+        i.saturating_sub(1).min(self.samples.len() - 1)
+    }
+
+    /// Every segment from one distance to another, going forwards and wrapping around
+    fn segments_between(&self, from: f32, to: f32) -> impl Iterator<Item = usize> + '_ {
+        let count = self.samples.len();
+        let first = self.segment_at(from.rem_euclid(self.length()));
+        let span = if to - from >= self.length() {
+            count
+        } else {
+            let last = self.segment_at(to.rem_euclid(self.length()));
+            (last + count - first) % count + 1
+        };
+        (0..span).map(move |j| (first + j) % count)
+    }
+
+    fn segment_distance(&self, i: usize, query: Vec3) -> f32 {
+        let (start, end) = self.segment(i);
+        closest_on_segment(start, end, query).0.distance(query)
+    }
+
+    fn point_on_segment(&self, i: usize, query: Vec3) -> TrackPoint {
+        let (start, end) = self.segment(i);
+        let (position, t) = closest_on_segment(start, end, query);
+        let segment_len = self.distances[i + 1] - self.distances[i];
+        let mut point = TrackPoint {
+            distance: (self.distances[i] + t * segment_len) % self.length(),
+            position,
+            tangent: (end - start).normalize_or_zero(),
+            lateral: 0.0,
+        };
+        point.lateral = (query - position).dot(point.left());
+        point
+    }
+}
+
+/// Closest point on the line from `start` to `end`, and how far along the line it is (0 to 1)
+fn closest_on_segment(start: Vec3, end: Vec3, query: Vec3) -> (Vec3, f32) {
+    let along = end - start;
+    let len_sq = along.length_squared();
+    if len_sq <= f32::EPSILON {
+        return (start, 0.0);
+    }
+    let t = ((query - start).dot(along) / len_sq).clamp(0.0, 1.0);
+    (start + along * t, t)
+}
+
+/// Sample a closed centripetal Catmull-Rom spline through the anchors.
+/// Centripetal (rather than uniform) keeps it from looping or overshooting
+/// where the anchors are unevenly spaced.
+fn sample_spline(anchors: &[Vec3]) -> Vec<Vec3> {
+    let n = anchors.len();
+    if n < 3 {
+        // too few anchors to curve
+        return anchors.to_vec();
+    }
+    let mut samples = Vec::with_capacity(n * SAMPLES_PER_SPAN);
+    for i in 0..n {
+        let p0 = anchors[(i + n - 1) % n];
+        let p1 = anchors[i];
+        let p2 = anchors[(i + 1) % n];
+        let p3 = anchors[(i + 2) % n];
+        for j in 0..SAMPLES_PER_SPAN {
+            samples.push(catmull_rom(p0, p1, p2, p3, j as f32 / SAMPLES_PER_SPAN as f32));
+        }
+    }
+    samples
+}
+
+/// Point `t` (0 to 1) of the way from p1 to p2 (Barry and Goldman's formulation)
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let knot = |a: Vec3, b: Vec3| a.distance(b).sqrt().max(1e-4);
+    let t0 = 0.0;
+    let t1 = t0 + knot(p0, p1);
+    let t2 = t1 + knot(p1, p2);
+    let t3 = t2 + knot(p2, p3);
+    let u = t1 + (t2 - t1) * t;
+    let mix = |a: Vec3, b: Vec3, ta: f32, tb: f32| a.lerp(b, (u - ta) / (tb - ta));
+    let a1 = mix(p0, p1, t0, t1);
+    let a2 = mix(p1, p2, t1, t2);
+    let a3 = mix(p2, p3, t2, t3);
+    let b1 = mix(a1, a2, t0, t2);
+    let b2 = mix(a2, a3, t1, t3);
+    mix(b1, b2, t1, t2)
+}
+
+/// Which segments of the centreline pass through each square of a grid laid over the
+/// ground (the XZ plane)
+struct SegmentGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    /// Corners of the box holding every non-empty cell
+    min: (i32, i32),
+    max: (i32, i32),
+}
+
+impl SegmentGrid {
+    fn new(samples: &[Vec3], length: f32) -> SegmentGrid {
+        let cell_size = (length / samples.len() as f32 * SEGMENTS_PER_CELL).max(1e-3);
+        let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        let cell_of = |v: Vec3| ((v.x / cell_size).floor() as i32, (v.z / cell_size).floor() as i32);
+        for i in 0..samples.len() {
+            let (ax, az) = cell_of(samples[i]);
+            let (bx, bz) = cell_of(samples[(i + 1) % samples.len()]);
+            for x in ax.min(bx)..=ax.max(bx) {
+                for z in az.min(bz)..=az.max(bz) {
+                    cells.entry((x, z)).or_default().push(i);
+                }
+            }
+        }
+        let (min, max) = cells.keys().fold(
+            ((i32::MAX, i32::MAX), (i32::MIN, i32::MIN)),
+            |(min, max), (x, z)| ((min.0.min(*x), min.1.min(*z)), (max.0.max(*x), max.1.max(*z))),
+        );
+        SegmentGrid {
+            cell_size,
+            cells,
+            min,
+            max,
+        }
+    }
+
+    /// Search outwards ring by ring from the query's cell, stopping once no
+    /// unsearched cell could hold anything closer than what's been found
+    fn closest_segment(&self, query: Vec3, distance: impl Fn(usize) -> f32) -> usize {
+        let cx = (query.x / self.cell_size).floor() as i32;
+        let cz = (query.z / self.cell_size).floor() as i32;
+        let mut best: Option<(usize, f32)> = None;
+        // enough rings to cover every cell, even from outside the grid
+        let reach = [cx - self.min.0, self.max.0 - cx, cz - self.min.1, self.max.1 - cz]
+            .into_iter()
+            .map(i32::abs)
+            .max()
+            .unwrap_or(0);
+        for ring in 0..=reach {
+            if let Some((_, best_dist)) = best {
+                // anything in this ring is at least this far away (horizontally)
+                if best_dist <= (ring - 1) as f32 * self.cell_size {
+                    break;
+                }
+            }
+            for x in cx - ring..=cx + ring {
+                for z in cz - ring..=cz + ring {
+                    if (x - cx).abs() != ring && (z - cz).abs() != ring {
+                        continue;
+                    }
+                    for &i in self.cells.get(&(x, z)).into_iter().flatten() {
+                        let d = distance(i);
+                        if best.is_none_or(|(_, best_dist)| d < best_dist) {
+                            best = Some((i, d));
+                        }
+                    }
+                }
+            }
+        }
+        best.expect("Tragnet has no segments").0
+    }
 }
 
 impl LapCounter {
@@ -112,6 +403,7 @@ impl LapCounter {
             lap: 0,
             sector: 0,
             checkpoints: num_checkpoints,
+            last_seen: 0,
         }
     }
     pub fn lap(&self) -> Lap {
@@ -120,13 +412,138 @@ impl LapCounter {
     pub fn sector(&self) -> Sector {
         self.sector
     }
-    pub fn update_sector(&mut self, sector: Sector) {
-        if sector == (self.sector + 1) % self.checkpoints {
-            self.sector += 1;
-            if self.sector == self.checkpoints {
-                self.sector = 0;
-                self.lap += 1;
-            }
+    /// Count the checkpoint if the tether just moved forwards into the next sector.
+    /// If it jumped forwards over a whole sector instead, checkpoints were skipped.
+    /// Going backwards never counts, so a lap driven the wrong way isn't a lap.
+    pub fn update_sector(&mut self, sector: Sector, forwards: bool) -> SectorChange {
+        let jumped = (sector + self.checkpoints - self.last_seen) % self.checkpoints;
+        self.last_seen = sector;
+        if forwards && jumped == 1 && sector == (self.sector + 1) % self.checkpoints {
+            self.advance_to(sector);
+            SectorChange::Advanced
+        } else if forwards && jumped >= 2 && sector != self.sector {
+            let skipped = (sector + self.checkpoints - self.sector - 1) % self.checkpoints;
+            SectorChange::Missed(skipped)
+        } else {
+            SectorChange::Same
+        }
+    }
+
+    /// Count every checkpoint up to `sector` as reached
+    pub fn advance_to(&mut self, sector: Sector) {
+        if sector <= self.sector {
+            self.lap += 1;
         }
+        self.sector = sector;
     }
-}
\ No newline at end of file
+}
+
+impl TrackDirection {
+    /// Update with how far the tether moved and how fast the car is going along the track
+    pub fn update(&mut self, moved: f32, speed_along_track: f32, dt: f32) {
+        self.last_move = moved;
+        if speed_along_track < -WRONG_WAY_SPEED {
+            self.wrong_way_for += dt;
+        } else if speed_along_track > WRONG_WAY_SPEED {
+            self.wrong_way_for = 0.0;
+        }
+    }
+
+    pub fn is_forwards(&self) -> bool {
+        self.last_move > 0.0
+    }
+
+    pub fn is_wrong_way(&self) -> bool {
+        self.wrong_way_for > WRONG_WAY_DELAY
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::games::racing::track::{
+        LapCounter, SectorChange, Tether, Tragnet, TragnetAnchor,
+    };
+    use bevy::prelude::{Transform, Vec3};
+    use std::f32::consts::TAU;
+
+    /// Anticlockwise (seen from above) circle around the origin, starting at +X
+    fn circle(radius: f32, anchors: usize) -> Tragnet {
+        let points = (0..anchors)
+            .map(|i| {
+                let angle = i as f32 / anchors as f32 * TAU;
+                TragnetAnchor {
+                    transform: Transform::from_xyz(radius * angle.cos(), 0.0, radius * angle.sin()),
+                }
+            })
+            .collect();
+        Tragnet::new(points, 4)
+    }
+
+    #[test]
+    fn length_does_not_depend_on_anchor_density() {
+        let expected = TAU * 10.0;
+        for anchors in [8, 32, 200] {
+            let tragnet = circle(10.0, anchors);
+            let error = (tragnet.length() - expected).abs() / expected;
+            assert!(error < 0.02, "{anchors} anchors gave length {}", tragnet.length());
+        }
+    }
+
+    #[test]
+    fn closest_point_and_lateral_offset() {
+        let tragnet = circle(10.0, 32);
+        let point = tragnet.closest_point(Vec3::new(12.0, 1.0, 0.0));
+        assert!(point.position.distance(Vec3::new(10.0, 0.0, 0.0)) < 0.05);
+        assert!(point.tangent.distance(Vec3::Z) < 0.05);
+        // travelling towards +Z, +X is on the left
+        assert!((point.lateral - 2.0).abs() < 0.05);
+
+        // a quarter of the way round
+        let point = tragnet.closest_point(Vec3::new(0.0, 0.0, 9.0));
+        assert!((point.distance - tragnet.length() / 4.0).abs() < 0.1);
+        assert!((point.lateral + 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn tether_follows_the_car() {
+        let tragnet = circle(10.0, 32);
+        let mut tether = Tether::Lost;
+        tragnet.update_tether(&mut tether, Vec3::new(10.0, 0.0, 0.5), 2.0);
+        assert_eq!(tragnet.get_current_sector(&tether), 0);
+        for i in 1..=12 {
+            let angle = i as f32 / 12.0 * TAU * 0.6;
+            let car = Vec3::new(10.0 * angle.cos(), 0.0, 10.0 * angle.sin());
+            tragnet.update_tether(&mut tether, car, 8.0);
+        }
+        assert_eq!(tragnet.get_current_sector(&tether), 2);
+    }
+
+    #[test]
+    fn skipped_checkpoints_are_noticed() {
+        let mut counter = LapCounter::at_start(4);
+        assert_eq!(counter.update_sector(1, true), SectorChange::Advanced);
+        assert_eq!(counter.update_sector(1, true), SectorChange::Same);
+        // reversing into the previous sector isn't skipping anything
+        assert_eq!(counter.update_sector(0, false), SectorChange::Same);
+        assert_eq!(counter.update_sector(1, true), SectorChange::Same);
+        // nor is driving forwards again after reversing a long way
+        assert_eq!(counter.update_sector(0, false), SectorChange::Same);
+        assert_eq!(counter.update_sector(3, false), SectorChange::Same);
+        assert_eq!(counter.update_sector(0, true), SectorChange::Same);
+        assert_eq!(counter.update_sector(1, true), SectorChange::Same);
+        // jumping from 1 to 3 skips 2
+        assert_eq!(counter.update_sector(3, true), SectorChange::Missed(1));
+        assert_eq!(counter.update_sector(3, true), SectorChange::Same);
+        assert_eq!((counter.lap(), counter.sector()), (0, 1));
+        counter.advance_to(3);
+        assert_eq!(counter.update_sector(0, true), SectorChange::Advanced);
+        assert_eq!((counter.lap(), counter.sector()), (1, 0));
+
+        // two laps the wrong way don't count for anything
+        let mut counter = LapCounter::at_start(3);
+        for sector in [2, 1, 0, 2, 1, 0] {
+            assert_eq!(counter.update_sector(sector, false), SectorChange::Same);
+        }
+        assert_eq!((counter.lap(), counter.sector()), (0, 0));
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::{HashMap, HashSet};
 use crate::{PlayerInputs, PlayerMapping, PlayerNum};
+use crate::identity::PlayerIdentities;
 use crate::games::{GamePhase, Player};
 use crate::games::racing::style::CarStyle;
 use bevy::color::{Alpha, Color};
@@ -7,7 +8,8 @@ use bevy::prelude::{AlignSelf, Commands, Component, Entity, JustifyContent, Just
 use bevy::text::{TextColor, TextLayout};
 use bevy::ui::{AlignContent, AlignItems, BackgroundColor, Display, GridPlacement, JustifyItems, Node, UiRect, Val};
 use game_42_net::controls::{ButtonType, PlayerInput};
-use crate::games::racing::track::{Lap, LapCounter};
+use crate::games::racing::track::{Lap, LapCounter, TrackDirection};
+use crate::games::racing::relay::{RelayTeams, player_color};
 /*
 Plan:
 
@@ -34,6 +36,7 @@ pub struct PlayerBlurb {
 pub struct PlayerIndicator {
     ready: bool,
     lap: Lap,
+    wrong_way: bool,
 }
 
 #[derive(Component)]
@@ -70,31 +73,37 @@ pub fn start_pregame_ui(mut commands: Commands) {
 
 /// Update player indicators in the table (ready, lapcount, etc.)
 pub fn update_indicators(
-    players: Query<(&Player, &LapCounter)>,
+    players: Query<(&Player, &LapCounter, &TrackDirection)>,
     player_input: Res<PlayerInputs>,
     player_mapping: Res<PlayerMapping>,
     indicators: Query<(&PlayerRef, &mut PlayerIndicator)>,
 ) {
-    let players_laps: HashMap<&Player, &LapCounter> = players.iter()
+    let players_laps: HashMap<&Player, (&LapCounter, &TrackDirection)> = players.iter()
+        .map(|(player, lap, direction)| (player, (lap, direction)))
         .collect();
     for (player, mut indicator) in indicators {
         let player = &player.0;
         if let Some(player_input) = player_mapping.0.get(&player.0).and_then(|user| player_input.0.get(user)) {
             indicator.ready = player_input.is_pressed(ButtonType::A);
         }
-        if let Some(lap) = players_laps.get(player) {
+        if let Some((lap, direction)) = players_laps.get(player) {
             indicator.lap = lap.lap();
+            indicator.wrong_way = direction.is_wrong_way();
         }
     }
 }
 
 pub fn update_table_ui(
     phase: Res<State<GamePhase>>,
-    blurbs: Query<(&PlayerRef, &PlayerBlurb, &mut Text), Without<PlayerIndicator>>,
+    identities: Res<PlayerIdentities>,
+    relay_teams: Option<Res<RelayTeams>>,
+    blurbs: Query<(&PlayerRef, &PlayerBlurb, &mut Text, &mut BackgroundColor), Without<PlayerIndicator>>,
     indicators: Query<(&PlayerIndicator, &mut Text)>,
 ) {
-    for (_player, blurb, mut text) in blurbs {
-        text.0 = format!("Player {}", blurb.number);
+    for (_player, blurb, mut text, mut background) in blurbs {
+        text.0 = identities.nickname(blurb.number);
+        let color = player_color(blurb.number, &identities, relay_teams.as_deref());
+        background.0 = color.with_saturation(0.7);
     }
     for (indicator, mut text) in indicators {
         match phase.get() {
@@ -107,7 +116,11 @@ pub fn update_table_ui(
                 text.0 = format!("{}", ind);
             }
             GamePhase::PlayingGame => {
-                text.0 = format!("{}", indicator.lap);
+                text.0 = if indicator.wrong_way {
+                    "WRONG WAY".to_string()
+                } else {
+                    format!("{}", indicator.lap)
+                };
             }
             _ => {}
         }
@@ -161,7 +174,7 @@ pub fn roster_join_leave(
                 .spawn((
                     UiMarker,
                     PlayerRef(player.clone()),
-                    PlayerIndicator { ready: false, lap: 0 },
+                    PlayerIndicator { ready: false, lap: 0, wrong_way: false },
                     Node {
                         grid_row: GridPlacement::start_span(rr, 1),
                         grid_column: GridPlacement::start_span(2, 1),
@@ -188,6 +201,26 @@ pub fn roster_join_leave(
     commands.entity(*overlay).add_children(&new_child_ui);
 }
 
+/// In a relay, keep teammates next to each other in the roster
+pub fn group_roster_by_team(
+    teams: Res<RelayTeams>,
+    rows: Query<(&PlayerRef, &mut PlayerRosterRow, &mut Node)>,
+) {
+    let mut players: Vec<_> = rows.iter().map(|(p, _, _)| p.0.0).collect();
+    players.sort_by_key(|player| (teams.team(*player), *player));
+    players.dedup();
+    for (player, mut row, mut node) in rows {
+        let Some(index) = players.iter().position(|p| *p == player.0.0) else {
+            continue;
+        };
+        let wanted = index as i16 + 1;
+        if row.row != wanted {
+            row.row = wanted;
+            node.grid_row = GridPlacement::start_span(wanted, 1);
+        }
+    }
+}
+
 /// Move UI for race mode
 pub fn ui_to_playing_transition(
     overlay: Single<(Entity, &mut Node), With<OverlayContainerMarker>>,
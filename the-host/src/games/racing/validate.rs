@@ -0,0 +1,423 @@
+use crate::cli::HostOptions;
+use crate::games::racing::centreline::{CentrelineError, is_centreline};
+use crate::games::racing::scene::{anchor_index, centreline_points};
+use crate::games::racing::track::{Tether, Tragnet, TragnetAnchor};
+use crate::games::racing::{COLLISION_MAT_NAME, RACE_CHECKPOINTS, TRAGNET_MAT_NAME};
+use crate::headless::HeadlessPlugins;
+use avian3d::prelude::Collider;
+use bevy::app::{App, PluginGroup};
+use bevy::asset::{AssetPlugin, AssetServer, Assets};
+use bevy::gltf::{GltfAssetLabel, GltfExtras, GltfMaterialName};
+use bevy::math::Vec3;
+use bevy::prelude::{
+    Children, Mesh, Mesh3d, Name, Query, Res, ResMut, Resource, Transform, TransformHelper, Trigger,
+    default,
+};
+use bevy::scene::{SceneInstanceReady, SceneRoot};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// Checks a track for the mistakes that otherwise only show up mid-race.
+// The track is loaded into a headless app and read with the same conventions
+// as on_scene_load, so what passes here is what the game will see.
+
+/// Give up if the track takes longer than this to load
+const LOAD_TIMEOUT: Duration = Duration::from_secs(30);
+/// Anchors this many times further apart (or closer together) than usual are suspicious
+const SPACING_OUTLIER_FACTOR: f32 = 3.0;
+/// Sectors with this many times more anchors than another are suspicious
+const CHECKPOINT_IMBALANCE_FACTOR: f32 = 3.0;
+
+/// Everything in the track that matters to the race
+#[derive(Default)]
+struct TrackContents {
+    /// Old style anchors: name, the number in the name, and where they are
+    anchors: Vec<(String, Option<usize>, Vec3)>,
+    centreline: Option<Result<Vec<Vec3>, CentrelineError>>,
+    /// Collision meshes, and whether a collider could be made from them
+    colliders: Vec<(String, bool)>,
+}
+
+#[derive(Resource, Default)]
+struct LoadedTrack(Option<TrackContents>);
+
+#[derive(Default)]
+struct Report {
+    problems: Vec<String>,
+    notes: Vec<String>,
+}
+
+impl Report {
+    fn problem(&mut self, message: String) {
+        self.problems.push(message);
+    }
+
+    fn note(&mut self, message: String) {
+        self.notes.push(message);
+    }
+}
+
+/// Load a track glb (relative to the asset root), print what's wrong with it,
+/// and return whether it's fit to race on
+pub fn validate_track(options: &HostOptions, path: &str) -> bool {
+    println!("Validating {path}");
+    let contents = match load_track(options, path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            println!("  error: {e}");
+            return false;
+        }
+    };
+    let report = check_track(&contents);
+    for note in &report.notes {
+        println!("  {note}");
+    }
+    for problem in &report.problems {
+        println!("  error: {problem}");
+    }
+    if report.problems.is_empty() {
+        println!("{path} is OK");
+    } else {
+        println!("{path} has {} problem(s)", report.problems.len());
+    }
+    report.problems.is_empty()
+}
+
+fn load_track(options: &HostOptions, path: &str) -> Result<TrackContents, String> {
+    let mut app = App::new();
+    app.add_plugins(HeadlessPlugins.set(AssetPlugin {
+        file_path: options.assets.clone(),
+        ..default()
+    }))
+    .init_resource::<LoadedTrack>();
+    app.finish();
+    app.cleanup();
+
+    let handle = app
+        .world()
+        .resource::<AssetServer>()
+        .load(GltfAssetLabel::Scene(0).from_asset(path.to_string()));
+    app.world_mut()
+        .spawn(SceneRoot(handle.clone()))
+        .observe(read_track);
+
+    let start = Instant::now();
+    loop {
+        app.update();
+        if let Some(contents) = app.world_mut().resource_mut::<LoadedTrack>().0.take() {
+            return Ok(contents);
+        }
+        if let Some(bevy::asset::LoadState::Failed(e)) =
+            app.world().resource::<AssetServer>().get_load_state(&handle)
+        {
+            return Err(format!("could not load the track: {e}"));
+        }
+        if start.elapsed() > LOAD_TIMEOUT {
+            return Err("timed out loading the track".to_string());
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Same walk over the scene as on_scene_load, but without changing anything
+fn read_track(
+    trigger: Trigger<SceneInstanceReady>,
+    gltf_children: Query<(&GltfMaterialName, &Mesh3d, &Name)>,
+    named: Query<(&Name, Option<&GltfExtras>)>,
+    meshes_3d: Query<&Mesh3d>,
+    meshes: Res<Assets<Mesh>>,
+    children: Query<&Children>,
+    transform_helper: TransformHelper,
+    mut loaded: ResMut<LoadedTrack>,
+) {
+    let mut contents = TrackContents::default();
+    for descendant in children.iter_descendants(trigger.target()) {
+        let position = || {
+            transform_helper
+                .compute_global_transform(descendant)
+                .map(|t| t.translation())
+                .unwrap_or_default()
+        };
+        if let Ok((name, extras)) = named.get(descendant) {
+            if contents.centreline.is_none() && is_centreline(name.as_str()) {
+                let points =
+                    centreline_points(descendant, extras, &children, &meshes_3d, &meshes).map(
+                        |(source, points)| {
+                            let global = transform_helper
+                                .compute_global_transform(source)
+                                .unwrap_or_default();
+                            points.into_iter().map(|p| global.transform_point(p)).collect()
+                        },
+                    );
+                contents.centreline = Some(points);
+            }
+        }
+        if let Ok((gltf_name, mesh, name)) = gltf_children.get(descendant) {
+            if gltf_name.0 == COLLISION_MAT_NAME {
+                let ok = meshes
+                    .get(&mesh.0)
+                    .and_then(Collider::convex_hull_from_mesh)
+                    .is_some();
+                contents.colliders.push((name.to_string(), ok));
+            } else if gltf_name.0 == TRAGNET_MAT_NAME {
+                contents
+                    .anchors
+                    .push((name.to_string(), anchor_index(name.as_str()), position()));
+            }
+        }
+    }
+    loaded.0 = Some(contents);
+}
+
+fn check_track(contents: &TrackContents) -> Report {
+    let mut report = Report::default();
+    let points = match &contents.centreline {
+        Some(Ok(points)) => {
+            report.note(format!("Centreline with {} points", points.len()));
+            if !contents.anchors.is_empty() {
+                report.note(format!(
+                    "{} old style tragnet anchors are ignored, because there's a centreline",
+                    contents.anchors.len()
+                ));
+            }
+            Some(points.clone())
+        }
+        Some(Err(e)) => {
+            report.problem(format!("Centreline: {e}"));
+            None
+        }
+        None if contents.anchors.is_empty() => {
+            report.problem(format!(
+                "Missing start: no centreline, and no meshes with the material \"{TRAGNET_MAT_NAME}\""
+            ));
+            None
+        }
+        None => {
+            report.note(format!(
+                "No centreline, so using {} old style tragnet anchors",
+                contents.anchors.len()
+            ));
+            check_anchor_numbers(&contents.anchors, &mut report)
+        }
+    };
+    if let Some(points) = points {
+        check_spacing(&points, &mut report);
+        check_checkpoints(&points, &mut report);
+    }
+    check_colliders(&contents.colliders, &mut report);
+    report
+}
+
+/// Gaps, duplicates and missing numbers. Returns the anchors in order if there are any.
+fn check_anchor_numbers(
+    anchors: &[(String, Option<usize>, Vec3)],
+    report: &mut Report,
+) -> Option<Vec<Vec3>> {
+    let mut numbered: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+    for (name, index, _) in anchors {
+        match index {
+            Some(index) => numbered.entry(*index).or_default().push(name),
+            None => report.problem(format!(
+                "Anchor \"{name}\" has no number, so it would be treated as anchor 0"
+            )),
+        }
+    }
+    for (index, names) in &numbered {
+        if names.len() > 1 {
+            report.problem(format!("Anchor number {index} is used more than once: {names:?}"));
+        }
+    }
+    let (Some(first), Some(last)) = (numbered.keys().next(), numbered.keys().last()) else {
+        return None;
+    };
+    if *first != 0 {
+        report.problem(format!("Missing start: there's no anchor 0 (the first is {first})"));
+    }
+    let missing: Vec<_> = (*first..=*last).filter(|i| !numbered.contains_key(i)).collect();
+    if !missing.is_empty() {
+        report.problem(format!("Gaps in the anchor numbers: {missing:?} are missing"));
+    }
+    let positions = anchors
+        .iter()
+        .filter_map(|(_, index, position)| index.map(|i| (i, *position)))
+        .collect::<BTreeMap<_, _>>();
+    Some(positions.into_values().collect())
+}
+
+/// Points much further apart or closer together than the rest
+fn check_spacing(points: &[Vec3], report: &mut Report) {
+    let gaps: Vec<f32> = (0..points.len())
+        .map(|i| points[i].distance(points[(i + 1) % points.len()]))
+        .collect();
+    let mut sorted = gaps.clone();
+    sorted.sort_by(f32::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    report.note(format!("Points are usually {median:.2} apart"));
+    for (i, gap) in gaps.iter().enumerate() {
+        let next = (i + 1) % points.len();
+        if *gap <= f32::EPSILON {
+            report.problem(format!("Points {i} and {next} are in the same place"));
+        } else if *gap > median * SPACING_OUTLIER_FACTOR || *gap < median / SPACING_OUTLIER_FACTOR {
+            report.problem(format!(
+                "Points {i} and {next} are {gap:.2} apart, which is out of line with the rest"
+            ));
+        }
+    }
+}
+
+/// Checkpoints split the lap into equal lengths, but each needs enough of the
+/// track in it to be tracked properly
+fn check_checkpoints(points: &[Vec3], report: &mut Report) {
+    let tragnet = Tragnet::new(
+        points
+            .iter()
+            .map(|p| TragnetAnchor {
+                transform: Transform::from_translation(*p),
+            })
+            .collect(),
+        RACE_CHECKPOINTS,
+    );
+    let mut per_sector = vec![0usize; RACE_CHECKPOINTS];
+    for point in points {
+        let tether = Tether::Along(tragnet.closest_point(*point).distance);
+        per_sector[tragnet.get_current_sector(&tether)] += 1;
+    }
+    report.note(format!(
+        "Lap is {:.1} long, with {RACE_CHECKPOINTS} checkpoints of {:.1}. Points per checkpoint: {per_sector:?}",
+        tragnet.length(),
+        tragnet.length() / RACE_CHECKPOINTS as f32,
+    ));
+    let fewest = *per_sector.iter().min().unwrap_or(&0);
+    let most = *per_sector.iter().max().unwrap_or(&0);
+    if fewest < 2 {
+        report.problem("A checkpoint has fewer than 2 points in it".to_string());
+    } else if most as f32 > fewest as f32 * CHECKPOINT_IMBALANCE_FACTOR {
+        report.problem(format!(
+            "Checkpoints are unbalanced: between {fewest} and {most} points each"
+        ));
+    }
+}
+
+fn check_colliders(colliders: &[(String, bool)], report: &mut Report) {
+    if colliders.is_empty() {
+        // the game always puts flat ground under the track, so it can still be raced on
+        report.note(format!(
+            "No meshes with the material \"{COLLISION_MAT_NAME}\", so there are no walls and the cars drive on the flat ground"
+        ));
+        return;
+    }
+    report.note(format!("{} collision meshes", colliders.len()));
+    for (name, ok) in colliders {
+        if !ok {
+            report.problem(format!("Could not make a collider from \"{name}\""));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::games::racing::centreline::CentrelineError;
+    use crate::games::racing::validate::{Report, TrackContents, check_track};
+    use bevy::math::Vec3;
+    use std::f32::consts::TAU;
+
+    fn circle(points: usize) -> Vec<Vec3> {
+        (0..points)
+            .map(|i| {
+                let angle = i as f32 / points as f32 * TAU;
+                Vec3::new(angle.cos(), 0.0, angle.sin()) * 10.0
+            })
+            .collect()
+    }
+
+    fn good_track() -> TrackContents {
+        TrackContents {
+            anchors: vec![],
+            centreline: Some(Ok(circle(24))),
+            colliders: vec![("walls".to_string(), true)],
+        }
+    }
+
+    fn has_problem(report: &Report, text: &str) -> bool {
+        report.problems.iter().any(|problem| problem.contains(text))
+    }
+
+    #[test]
+    fn a_good_track_passes() {
+        let report = check_track(&good_track());
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+        // no walls is fine, as there's always ground
+        let report = check_track(&TrackContents {
+            colliders: vec![],
+            ..good_track()
+        });
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+    }
+
+    #[test]
+    fn problems_are_reported() {
+        let missing = check_track(&TrackContents {
+            centreline: None,
+            ..good_track()
+        });
+        assert!(has_problem(&missing, "Missing start"));
+
+        let broken = check_track(&TrackContents {
+            centreline: Some(Err(CentrelineError::TooFewPoints(2))),
+            ..good_track()
+        });
+        assert!(has_problem(&broken, "Centreline"));
+
+        let points = circle(8);
+        let anchor =
+            |name: &str, index: Option<usize>, i: usize| (name.to_string(), index, points[i]);
+        let numbering = check_track(&TrackContents {
+            anchors: vec![
+                anchor("tragnet.001", Some(1), 1),
+                anchor("tragnet.002", Some(2), 2),
+                anchor("tragnet.002b", Some(2), 3),
+                anchor("tragnet.005", Some(5), 5),
+                anchor("tragnet", None, 6),
+            ],
+            centreline: None,
+            ..good_track()
+        });
+        assert!(has_problem(&numbering, "has no number"));
+        assert!(has_problem(&numbering, "used more than once"));
+        assert!(has_problem(&numbering, "there's no anchor 0"));
+        assert!(has_problem(&numbering, "[3, 4] are missing"));
+
+        let mut uneven = circle(24);
+        uneven[5] = uneven[4];
+        uneven.drain(10..16);
+        let spacing = check_track(&TrackContents {
+            centreline: Some(Ok(uneven)),
+            ..good_track()
+        });
+        assert!(has_problem(&spacing, "are in the same place"));
+        assert!(has_problem(&spacing, "out of line with the rest"));
+
+        // nearly all the points are in the first third of the lap
+        let mut bunched: Vec<_> = (0..30)
+            .map(|i| {
+                let angle = i as f32 / 90.0 * TAU;
+                Vec3::new(angle.cos(), 0.0, angle.sin()) * 10.0
+            })
+            .collect();
+        bunched.extend(circle(6).into_iter().skip(2));
+        let checkpoints = check_track(&TrackContents {
+            centreline: Some(Ok(bunched)),
+            ..good_track()
+        });
+        assert!(
+            has_problem(&checkpoints, "unbalanced")
+                || has_problem(&checkpoints, "fewer than 2 points")
+        );
+
+        let colliders = check_track(&TrackContents {
+            colliders: vec![("walls".to_string(), true), ("rock".to_string(), false)],
+            ..good_track()
+        });
+        assert!(has_problem(&colliders, "from \"rock\""));
+    }
+}
@@ -0,0 +1,341 @@
+use crate::config::Config;
+use crate::games::racing::{ControlOutput, GAME, VEHICLE};
+use avian3d::prelude::AngularDamping;
+use bevy::math::{Quat, Vec3};
+use bevy::prelude::{Bundle, Component, Transform, warn};
+use game_42_net::controls::ButtonType;
+use serde::Deserialize;
+
+// How cars drive. Each car is a rigid body held up by four raycast wheels on
+// springs. Every tick each wheel that touches the ground pushes the body up
+// (the suspension), sideways against any sliding (the tyre's grip, which
+// gives out past a certain slip angle), and forwards or backwards (the engine,
+// brakes and rolling resistance). The front wheels steer, less so at speed,
+// and the handbrake takes most of the grip away from the back wheels so the
+// car can be thrown into a drift. All of it is impulses on the body, so the
+// physics engine does the rest. Lengths are in car sizes, and the numbers are
+// in the config under "vehicle".
+
+/// Below this speed (along the wheel) slip angles are worked out as if going this fast,
+/// so a car that's barely moving doesn't have wild slip angles
+const MIN_SLIP_SPEED: f32 = 0.5;
+/// Which button players hold for the handbrake
+pub const DRIFT_BUTTON: ButtonType = ButtonType::Y;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct VehicleParams {
+    /// How far the springs reach below the body when they're not loaded
+    pub suspension_length: f32,
+    pub wheel_radius: f32,
+    /// Upwards acceleration per wheel when a spring is squashed its full length
+    pub suspension_stiffness: f32,
+    /// How quickly the springs stop bouncing
+    pub suspension_damping: f32,
+    /// Forwards acceleration at full throttle
+    pub engine_acceleration: f32,
+    /// Slowing down when pressing against the direction of travel
+    pub brake_acceleration: f32,
+    /// How quickly the car slows down on its own (per unit of speed)
+    pub rolling_drag: f32,
+    /// How quickly the handbrake slows down the back wheels (per unit of speed)
+    pub handbrake_drag: f32,
+    /// Most grip a tyre has (as a multiple of the weight on it), at the peak slip angle
+    pub peak_grip: f32,
+    /// Slip angle (in degrees) where the tyre grips best. Past it, the tyre starts to slide.
+    pub peak_slip_angle: f32,
+    /// How much of its peak grip a tyre keeps when it's sliding well past its peak
+    pub slide_grip: f32,
+    /// How much of their grip the back tyres keep with the handbrake on
+    pub drift_grip: f32,
+    /// Furthest the front wheels turn (in degrees) when going slowly...
+    pub max_steer_angle: f32,
+    /// ...and when going at `high-speed` or faster
+    pub high_speed_steer_angle: f32,
+    pub high_speed: f32,
+    /// How quickly spinning slows down on its own
+    pub angular_damping: f32,
+}
+
+impl Default for VehicleParams {
+    fn default() -> Self {
+        VehicleParams {
+            suspension_length: 0.35,
+            wheel_radius: 0.15,
+            suspension_stiffness: 60.0,
+            suspension_damping: 6.0,
+            engine_acceleration: 4.0,
+            brake_acceleration: 8.0,
+            rolling_drag: 0.3,
+            handbrake_drag: 1.5,
+            peak_grip: 1.2,
+            peak_slip_angle: 8.0,
+            slide_grip: 0.7,
+            drift_grip: 0.35,
+            max_steer_angle: 35.0,
+            high_speed_steer_angle: 8.0,
+            high_speed: 15.0,
+            angular_damping: 3.0,
+        }
+    }
+}
+
+impl VehicleParams {
+    pub fn from_config(config: &Config) -> VehicleParams {
+        serde_json::from_value(config[GAME][VEHICLE].clone()).unwrap_or_else(|e| {
+            warn!("Vehicle config is missing or wrong ({e}), using the defaults");
+            VehicleParams::default()
+        })
+    }
+
+    /// How much a tyre grips (as a multiple of the weight on it) at a slip angle in radians.
+    /// Rises to the peak, then falls away to the sliding grip.
+    pub fn lateral_grip(&self, slip_angle: f32) -> f32 {
+        let slip = slip_angle.abs();
+        let peak = self.peak_slip_angle.to_radians();
+        if slip <= peak {
+            self.peak_grip * slip / peak
+        } else {
+            let past_peak = ((slip - peak) / peak).min(1.0);
+            self.peak_grip * (1.0 + (self.slide_grip - 1.0) * past_peak)
+        }
+    }
+
+    /// How far (in radians) the front wheels turn, for a steering input at a speed.
+    /// Positive is left.
+    pub fn steer_angle(&self, steer: f32, speed: f32) -> f32 {
+        let fast = (speed.abs() / self.high_speed).min(1.0);
+        let max =
+            self.max_steer_angle + (self.high_speed_steer_angle - self.max_steer_angle) * fast;
+        steer * max.to_radians()
+    }
+}
+
+/// Physics settings that come with the vehicle model
+pub fn vehicle_bundle(params: VehicleParams) -> impl Bundle {
+    (
+        Vehicle {
+            params,
+            ..Vehicle::default()
+        },
+        AngularDamping(params.angular_damping),
+    )
+}
+
+#[derive(Component, Debug)]
+pub struct Vehicle {
+    wheels: [Wheel; 4],
+    /// Read from the config when the car is spawned
+    pub params: VehicleParams,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Wheel {
+    /// Where the top of the spring is, in the car's own (unscaled) space
+    mount: Vec3,
+    front: bool,
+}
+
+impl Default for Vehicle {
+    fn default() -> Self {
+        // the corners of the underside of the car's collider (cars drive towards +Z)
+        let wheel = |x: f32, z: f32| Wheel {
+            mount: Vec3::new(x, -0.2, z),
+            front: z > 0.0,
+        };
+        Vehicle {
+            wheels: [
+                wheel(0.22, 0.4),
+                wheel(-0.22, 0.4),
+                wheel(0.22, -0.4),
+                wheel(-0.22, -0.4),
+            ],
+            params: VehicleParams::default(),
+        }
+    }
+}
+
+/// The state of a car's body that the wheels need to know about
+pub struct CarBody {
+    pub transform: Transform,
+    pub velocity: Vec3,
+    pub angular_velocity: Vec3,
+    pub mass: f32,
+    /// In world space
+    pub centre_of_mass: Vec3,
+    /// How grippy the surface is (1 is road)
+    pub grip: f32,
+}
+
+/// What a wheel's ray hit: how far away, and the ground's normal there
+pub struct GroundHit {
+    pub distance: f32,
+    pub normal: Vec3,
+}
+
+impl Vehicle {
+    /// The impulses (and the world space points to apply them at) that the wheels
+    /// put on the car this tick. `ground` casts a ray from a point in a direction,
+    /// up to a distance, and says what it hit.
+    pub fn wheel_impulses(
+        &self,
+        body: &CarBody,
+        controls: &ControlOutput,
+        ground: impl Fn(Vec3, Vec3, f32) -> Option<GroundHit>,
+        dt: f32,
+    ) -> Vec<(Vec3, Vec3)> {
+        let params = &self.params;
+        let rotation = body.transform.rotation;
+        let size = body.transform.scale.y;
+        let up = rotation.mul_vec3(Vec3::Y);
+        let forward = rotation.mul_vec3(Vec3::Z);
+        let speed = body.velocity.dot(forward);
+        let steer_angle = params.steer_angle(controls.steer, speed);
+        let rest_length = params.suspension_length * size;
+        let ray_length = rest_length + params.wheel_radius * size;
+        let wheel_mass = body.mass / self.wheels.len() as f32;
+
+        let mut impulses = vec![];
+        for wheel in &self.wheels {
+            let mount = body.transform.translation + rotation.mul_vec3(wheel.mount * size);
+            let Some(hit) = ground(mount, -up, ray_length) else {
+                // in the air
+                continue;
+            };
+            let velocity = body.velocity + body.angular_velocity.cross(mount - body.centre_of_mass);
+
+            // suspension
+            let compression = ((ray_length - hit.distance) / rest_length).max(0.0);
+            let spring = params.suspension_stiffness * compression
+                - params.suspension_damping * velocity.dot(up) / size;
+            let load = spring.max(0.0) * wheel_mass;
+
+            // which way the wheel is pointing, along the ground
+            let heading = if wheel.front {
+                Quat::from_axis_angle(up, steer_angle).mul_vec3(forward)
+            } else {
+                forward
+            };
+            let heading = heading
+                .reject_from_normalized(hit.normal)
+                .normalize_or(forward);
+            let side = hit.normal.cross(heading).normalize_or_zero();
+            let along = velocity.dot(heading);
+            let across = velocity.dot(side);
+
+            // grip against sliding, but never more than it takes to stop the slide
+            let slip_angle = across.atan2(along.abs().max(MIN_SLIP_SPEED));
+            let drifting = controls.handbrake && !wheel.front;
+            let grip = params.lateral_grip(slip_angle)
+                * body.grip
+                * if drifting { params.drift_grip } else { 1.0 };
+            let stop_sliding = across.abs() * wheel_mass / dt;
+            let lateral = -across.signum() * (grip * load).min(stop_sliding);
+
+            // engine, brakes and rolling resistance
+            let pedal = if controls.throttle * along < 0.0 {
+                params.brake_acceleration
+            } else {
+                params.engine_acceleration
+            };
+            let mut longitudinal = controls.throttle * pedal - along * params.rolling_drag;
+            if drifting {
+                longitudinal -= along * params.handbrake_drag;
+            }
+
+            let force = up * load + side * lateral + heading * longitudinal * wheel_mass;
+            impulses.push((force * dt, mount));
+        }
+        impulses
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::games::racing::ControlOutput;
+    use crate::games::racing::vehicle::{CarBody, GroundHit, Vehicle, VehicleParams};
+    use bevy::math::Vec3;
+    use bevy::prelude::Transform;
+
+    const GRAVITY: f32 = 20.0;
+    const DT: f32 = 1.0 / 64.0;
+
+    /// A car on flat ground at y = 0, with its mounts at the height where the springs hold it up
+    fn car_on_flat_ground(
+        velocity: Vec3,
+    ) -> (CarBody, impl Fn(Vec3, Vec3, f32) -> Option<GroundHit>) {
+        let params = VehicleParams::default();
+        let resting = params.suspension_length * (1.0 - GRAVITY / params.suspension_stiffness)
+            + params.wheel_radius;
+        let body = CarBody {
+            transform: Transform::from_xyz(0.0, resting + 0.2, 0.0),
+            velocity,
+            angular_velocity: Vec3::ZERO,
+            mass: 4.0,
+            centre_of_mass: Vec3::new(0.0, resting + 0.2, 0.0),
+            grip: 1.0,
+        };
+        let ground = |origin: Vec3, direction: Vec3, max: f32| {
+            let distance = origin.y / -direction.y;
+            (distance <= max).then_some(GroundHit {
+                distance,
+                normal: Vec3::Y,
+            })
+        };
+        (body, ground)
+    }
+
+    fn total(impulses: &[(Vec3, Vec3)]) -> Vec3 {
+        impulses.iter().map(|(impulse, _)| *impulse).sum()
+    }
+
+    #[test]
+    fn springs_hold_the_car_up() {
+        let (body, ground) = car_on_flat_ground(Vec3::ZERO);
+        let controls = ControlOutput::default();
+        let impulses = Vehicle::default().wheel_impulses(&body, &controls, ground, DT);
+        assert_eq!(impulses.len(), 4);
+        let weight = body.mass * GRAVITY * DT;
+        assert!((total(&impulses).y - weight).abs() < weight * 0.01);
+    }
+
+    #[test]
+    fn tyres_grip_until_the_handbrake_is_on() {
+        // sliding sideways (to the left) while going forwards
+        let (body, ground) = car_on_flat_ground(Vec3::new(2.0, 0.0, 10.0));
+        let gripping = total(&Vehicle::default().wheel_impulses(
+            &body,
+            &ControlOutput::default(),
+            &ground,
+            DT,
+        ));
+        assert!(gripping.x < 0.0, "the tyres push against the slide");
+        let drifting = total(&Vehicle::default().wheel_impulses(
+            &body,
+            &ControlOutput::default().with_handbrake(true),
+            &ground,
+            DT,
+        ));
+        assert!(
+            drifting.x < 0.0 && drifting.x > gripping.x,
+            "the back tyres let go"
+        );
+        assert!(drifting.z < gripping.z, "the handbrake slows the car");
+    }
+
+    #[test]
+    fn grip_and_steering_curves() {
+        let params = VehicleParams::default();
+        let peak = params.peak_slip_angle.to_radians();
+        assert_eq!(params.lateral_grip(0.0), 0.0);
+        assert_eq!(params.lateral_grip(peak), params.peak_grip);
+        assert_eq!(params.lateral_grip(-peak), params.peak_grip);
+        assert!(params.lateral_grip(peak * 1.5) < params.peak_grip);
+        assert!(
+            (params.lateral_grip(peak * 3.0) - params.peak_grip * params.slide_grip).abs() < 1e-5
+        );
+        // less steering at speed
+        assert!(params.steer_angle(1.0, 0.0) > params.steer_angle(1.0, params.high_speed));
+        assert!(params.steer_angle(-1.0, 0.0) < 0.0);
+    }
+}
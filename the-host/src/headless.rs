@@ -0,0 +1,104 @@
+use bevy::app::{App, Plugin, PluginGroup, PluginGroupBuilder, ScheduleRunnerPlugin};
+use bevy::asset::{AssetApp, AssetPlugin};
+use bevy::gltf::GltfPlugin;
+use bevy::input::InputPlugin;
+use bevy::pbr::{
+    CascadeShadowConfig, Cascades, CascadesVisibleEntities, CubemapVisibleEntities,
+    DirectionalLight, MeshMaterial3d, PointLight, SpotLight, StandardMaterial, VisibleMeshEntities,
+};
+use bevy::prelude::{
+    InheritedVisibility, MinimalPlugins, Resource, TransformPlugin, ViewVisibility, Visibility,
+    WindowPlugin, default,
+};
+use bevy::render::mesh::MeshPlugin;
+use bevy::render::primitives::{Aabb, CascadesFrusta, CubemapFrusta, Frustum};
+use bevy::render::texture::ImagePlugin;
+use bevy::render::view::VisibilityClass;
+use bevy::scene::ScenePlugin;
+use bevy::state::app::StatesPlugin;
+use bevy::text::{Font, FontLoader};
+use bevy::window::ExitCondition;
+use game_42_net::protocol::{AddressedHostPacket, AnnotatedClientPacket};
+use std::sync::Mutex;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
+
+// Headless mode runs the whole game without a window, a GPU or the web server,
+// so that gameplay can be tested in CI.
+//
+// There's no renderer at all, just the plugins the game logic needs: assets,
+// glTF scenes, input, states and a window plugin with no windows. Meshes,
+// images, materials and fonts are still loaded, as racing builds its colliders
+// and tragnet from the track's meshes and materials, but nothing is drawn.
+// Without the renderer's plugins, the components that glTF scenes are spawned
+// with have to be registered here instead (see HeadlessScenePlugin).
+
+/// How fast the headless app loops when it's run (rather than stepped by a test)
+const HEADLESS_LOOP_RATE: f64 = 1. / 64.;
+
+/// Plugins for running without a window or renderer. There's no LogPlugin, as tests
+/// build several apps in one process, and there is only one global logger.
+pub struct HeadlessPlugins;
+
+impl PluginGroup for HeadlessPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add_group(MinimalPlugins)
+            .set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                HEADLESS_LOOP_RATE,
+            )))
+            .add(TransformPlugin)
+            .add(InputPlugin)
+            .add(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                ..default()
+            })
+            .add(AssetPlugin::default())
+            .add(ScenePlugin)
+            .add(MeshPlugin)
+            .add(ImagePlugin::default())
+            .add(GltfPlugin::default())
+            .add(StatesPlugin)
+            .add(HeadlessScenePlugin)
+    }
+}
+
+/// The asset types and components that glTF scenes (and the game) use, which the
+/// renderer's plugins would normally set up
+struct HeadlessScenePlugin;
+
+impl Plugin for HeadlessScenePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<StandardMaterial>()
+            .init_asset::<Font>()
+            .init_asset_loader::<FontLoader>()
+            // meshes
+            .register_type::<MeshMaterial3d<StandardMaterial>>()
+            .register_type::<Aabb>()
+            .register_type::<Visibility>()
+            .register_type::<InheritedVisibility>()
+            .register_type::<ViewVisibility>()
+            .register_type::<VisibilityClass>()
+            // lights
+            .register_type::<PointLight>()
+            .register_type::<SpotLight>()
+            .register_type::<DirectionalLight>()
+            .register_type::<CubemapFrusta>()
+            .register_type::<CubemapVisibleEntities>()
+            .register_type::<Frustum>()
+            .register_type::<VisibleMeshEntities>()
+            .register_type::<Cascades>()
+            .register_type::<CascadesFrusta>()
+            .register_type::<CascadeShadowConfig>()
+            .register_type::<CascadesVisibleEntities>();
+    }
+}
+
+/// The net's end of the host interface, when there is no real net.
+/// Lets a test pretend to be phones, and see what the host sends them.
+#[derive(Resource)]
+pub struct SimulatedNet {
+    pub to_host: Sender<AnnotatedClientPacket>,
+    pub from_host: Mutex<Receiver<AddressedHostPacket>>,
+}
@@ -0,0 +1,191 @@
+use crate::PlayerNum;
+use bevy::color::{Color, Srgba};
+use bevy::prelude::Resource;
+use game_42_net::protocol::HostPacket;
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Colours players can pick from. These are chosen to be easy to tell apart.
+pub const PALETTE: [&str; 12] = [
+    "#e6194b", // red
+    "#3cb44b", // green
+    "#ffe119", // yellow
+    "#4363d8", // blue
+    "#f58231", // orange
+    "#911eb4", // purple
+    "#42d4f4", // cyan
+    "#f032e6", // magenta
+    "#bfef45", // lime
+    "#fabed4", // pink
+    "#469990", // teal
+    "#9a6324", // brown
+];
+
+const MAX_NICKNAME_LEN: usize = 16;
+
+/// What a player wants to be called and what they look like
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerIdentity {
+    pub nickname: String,
+    /// Index into [PALETTE]
+    pub color: usize,
+}
+
+/// Identities of all connected players. Every game should get names and colours from here.
+#[derive(Resource, Default)]
+pub struct PlayerIdentities(pub HashMap<PlayerNum, PlayerIdentity>);
+
+#[derive(Debug, Error, PartialEq)]
+pub enum IdentityError {
+    #[error("Colour {0} is not in the palette")]
+    NoSuchColor(usize),
+    #[error("Colour {0} is already taken by player {1}")]
+    ColorTaken(usize, PlayerNum),
+}
+
+pub fn default_nickname(player: PlayerNum) -> String {
+    format!("Player {player}")
+}
+
+pub fn palette_color(index: usize) -> Color {
+    Srgba::hex(PALETTE[index % PALETTE.len()])
+        .map(Color::Srgba)
+        .unwrap_or(Color::WHITE)
+}
+
+impl PlayerIdentity {
+    pub fn bevy_color(&self) -> Color {
+        palette_color(self.color)
+    }
+}
+
+impl PlayerIdentities {
+    /// Give a newly connected player a default name and the first free colour
+    pub fn assign_default(&mut self, player: PlayerNum) -> &PlayerIdentity {
+        let taken = self.taken();
+        let color = (0..PALETTE.len())
+            .find(|c| !taken.contains(c))
+            // more players than colours, so someone has to share
+            .unwrap_or((player as usize).saturating_sub(1) % PALETTE.len());
+        self.0.insert(
+            player,
+            PlayerIdentity {
+                nickname: default_nickname(player),
+                color,
+            },
+        );
+        &self.0[&player]
+    }
+
+    /// Change a player's identity. The nickname is cleaned up, and the colour must be free.
+    pub fn set(
+        &mut self,
+        player: PlayerNum,
+        nickname: &str,
+        color: usize,
+    ) -> Result<&PlayerIdentity, IdentityError> {
+        if color >= PALETTE.len() {
+            return Err(IdentityError::NoSuchColor(color));
+        }
+        if let Some((other, _)) = self
+            .0
+            .iter()
+            .find(|(other, identity)| **other != player && identity.color == color)
+        {
+            return Err(IdentityError::ColorTaken(color, *other));
+        }
+        let nickname: String = nickname
+            .trim()
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(MAX_NICKNAME_LEN)
+            .collect();
+        let nickname = if nickname.is_empty() {
+            default_nickname(player)
+        } else {
+            nickname
+        };
+        self.0.insert(player, PlayerIdentity { nickname, color });
+        Ok(&self.0[&player])
+    }
+
+    pub fn remove(&mut self, player: PlayerNum) -> Option<PlayerIdentity> {
+        self.0.remove(&player)
+    }
+
+    pub fn nickname(&self, player: PlayerNum) -> String {
+        self.0
+            .get(&player)
+            .map(|i| i.nickname.clone())
+            .unwrap_or_else(|| default_nickname(player))
+    }
+
+    pub fn color(&self, player: PlayerNum) -> Color {
+        self.0
+            .get(&player)
+            .map(|i| i.bevy_color())
+            .unwrap_or_else(|| palette_color((player as usize).saturating_sub(1)))
+    }
+
+    /// Colour of the player as a CSS hex string
+    pub fn hex(&self, player: PlayerNum) -> String {
+        let index = self
+            .0
+            .get(&player)
+            .map(|i| i.color)
+            .unwrap_or((player as usize).saturating_sub(1));
+        PALETTE[index % PALETTE.len()].to_string()
+    }
+
+    /// Palette indices that are in use
+    pub fn taken(&self) -> Vec<usize> {
+        let mut taken: Vec<_> = self.0.values().map(|i| i.color).collect();
+        taken.sort();
+        taken.dedup();
+        taken
+    }
+
+    pub fn palette_packet(&self) -> HostPacket {
+        HostPacket::Palette {
+            colors: PALETTE.iter().map(|c| c.to_string()).collect(),
+            taken: self.taken(),
+        }
+    }
+
+    pub fn identity_packet(&self, player: PlayerNum) -> Option<HostPacket> {
+        self.0.get(&player).map(|identity| HostPacket::Identity {
+            player,
+            nickname: identity.nickname.clone(),
+            color: identity.color,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::identity::{IdentityError, PlayerIdentities};
+
+    #[test]
+    fn defaults_get_distinct_colors() {
+        let mut identities = PlayerIdentities::default();
+        let a = identities.assign_default(1).color;
+        let b = identities.assign_default(2).color;
+        assert_ne!(a, b);
+        assert_eq!(identities.nickname(1), "Player 1");
+    }
+
+    #[test]
+    fn color_collisions_are_rejected() {
+        let mut identities = PlayerIdentities::default();
+        identities.assign_default(1);
+        identities.assign_default(2);
+        let taken = identities.0[&1].color;
+        assert_eq!(
+            identities.set(2, "Bob", taken),
+            Err(IdentityError::ColorTaken(taken, 1))
+        );
+        // keeping your own colour is fine
+        assert!(identities.set(1, "  Alice\n ", taken).is_ok());
+        assert_eq!(identities.nickname(1), "Alice");
+    }
+}
@@ -0,0 +1,126 @@
+use crate::PlayerInputs;
+use crate::cli::{HostOptions, is_deterministic};
+use bevy::ecs::schedule::{ExecutorKind, ScheduleLabel};
+use bevy::prelude::*;
+use game_42_net::controls::InputUpdate;
+use game_42_net::protocol::UserId;
+use std::collections::VecDeque;
+
+// Deterministic mode (`--deterministic`). Normally a phone's input is applied as
+// soon as it arrives, wherever in the frame that happens to be. In deterministic
+// mode each input is stamped with the next fixed tick and applied at the very
+// start of it, and the fixed schedules run their systems one at a time in the
+// same order every tick. Given the same seed and the same stamped inputs, a game
+// then plays out exactly the same every time. Games have to keep anything that
+// matters to the outcome in the fixed schedules (see racing's `race_rules`).
+
+/// How many fixed ticks have run
+#[derive(Resource, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedTick(pub u64);
+
+/// An input, and the fixed tick it should be applied on
+#[derive(Clone, Debug)]
+pub struct StampedInput {
+    pub tick: u64,
+    pub user_id: UserId,
+    pub update: InputUpdate,
+}
+
+/// Inputs waiting for their tick, in the order they arrived
+#[derive(Resource, Default, Debug)]
+pub struct InputQueue(VecDeque<StampedInput>);
+
+impl InputQueue {
+    /// Queue an input to be applied on the tick after `now`
+    pub fn stamp(&mut self, now: FixedTick, user_id: UserId, update: InputUpdate) {
+        self.0.push_back(StampedInput {
+            tick: now.0 + 1,
+            user_id,
+            update,
+        });
+    }
+
+    /// Take out every input due on or before `tick`
+    fn due(&mut self, tick: FixedTick) -> Vec<StampedInput> {
+        let due = self
+            .0
+            .iter()
+            .take_while(|input| input.tick <= tick.0)
+            .count();
+        self.0.drain(..due).collect()
+    }
+}
+
+pub fn init(app: &mut App) {
+    app.init_resource::<FixedTick>()
+        .init_resource::<InputQueue>()
+        .add_systems(
+            FixedFirst,
+            (
+                count_ticks,
+                apply_inputs.after(count_ticks).run_if(is_deterministic),
+            ),
+        );
+    if app.world().resource::<HostOptions>().deterministic {
+        // the multithreaded executor runs systems that aren't ordered against each
+        // other in whichever order they happen to become ready
+        run_on_one_thread(app, FixedFirst);
+        run_on_one_thread(app, FixedPreUpdate);
+        run_on_one_thread(app, FixedUpdate);
+        run_on_one_thread(app, FixedPostUpdate);
+        run_on_one_thread(app, FixedLast);
+    }
+}
+
+fn run_on_one_thread(app: &mut App, label: impl ScheduleLabel) {
+    app.edit_schedule(label, |schedule| {
+        schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+    });
+}
+
+fn count_ticks(mut tick: ResMut<FixedTick>) {
+    tick.0 += 1;
+}
+
+fn apply_inputs(
+    tick: Res<FixedTick>,
+    mut queue: ResMut<InputQueue>,
+    mut player_inputs: ResMut<PlayerInputs>,
+) {
+    for input in queue.due(*tick) {
+        // players who left since don't need their inputs any more
+        if let Some(entry) = player_inputs.0.get_mut(&input.user_id) {
+            entry.apply(input.update);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lockstep::{FixedTick, InputQueue};
+    use game_42_net::controls::{ButtonType, InputUpdate};
+    use game_42_net::protocol::UserId;
+
+    #[test]
+    fn inputs_wait_for_their_tick() {
+        let mut queue = InputQueue::default();
+        let press = InputUpdate::Button(ButtonType::A, true);
+        queue.stamp(FixedTick(3), UserId(0), press.clone());
+        queue.stamp(FixedTick(3), UserId(1), press);
+        queue.stamp(
+            FixedTick(5),
+            UserId(0),
+            InputUpdate::Button(ButtonType::A, false),
+        );
+        assert!(queue.due(FixedTick(3)).is_empty());
+        let due = queue.due(FixedTick(4));
+        assert_eq!(
+            due.iter().map(|input| input.user_id).collect::<Vec<_>>(),
+            [UserId(0), UserId(1)]
+        );
+        assert!(due.iter().all(|input| input.tick == 4
+            && matches!(input.update, InputUpdate::Button(ButtonType::A, true))));
+        assert_eq!(queue.due(FixedTick(10)).len(), 1);
+        assert!(queue.due(FixedTick(11)).is_empty());
+    }
+}
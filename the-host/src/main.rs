@@ -1,30 +1,62 @@
 mod assets;
+mod cli;
 mod debug_input;
 pub mod games;
 mod config;
+mod feedback;
+mod headless;
+mod identity;
+mod lockstep;
+mod remote;
+#[cfg(test)]
+mod sim;
 
 use std::collections::hash_map::Keys;
 use bevy::prelude::*;
 use bevy::window::{CursorGrabMode, WindowResized};
-use game_42_net::controls::{InputUpdate, PlayerInput};
+use game_42_net::controls::PlayerInput;
 use game_42_net::protocol::ClientPacket::Input;
-use game_42_net::protocol::{AnnotatedClientPacket, Packet, UserId};
+use game_42_net::protocol::{AddressedHostPacket, AnnotatedClientPacket, ClientPacket, HostPacket, Packet, Recipient, UserId};
 use games::racing;
 use std::collections::HashMap;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use bevy::remote::http::RemoteHttpPlugin;
-use bevy::remote::RemotePlugin;
 use rand_chacha::rand_core::SeedableRng;
 use crate::config::{Config, ConfigAccessor, ConfigAssetLoaderError, ConfigLoader};
+use crate::identity::PlayerIdentities;
+use crate::games::CurrentLayout;
+use crate::feedback::PlayerFeedbackSettings;
+use crate::headless::{HeadlessPlugins, SimulatedNet};
+use crate::cli::HostOptions;
+use crate::lockstep::{FixedTick, InputQueue};
+use bevy::log::LogPlugin;
+use bevy::window::{MonitorSelection, WindowMode};
+use clap::Parser;
 
 #[derive(Resource)]
 pub(crate) struct RandomSource(rand_chacha::ChaCha8Rng);
 
 /// Use this to message the net (probably a client?)
 #[derive(Resource)]
-pub struct MessageNet(pub Sender<()>);
+pub struct MessageNet(pub Sender<AddressedHostPacket>);
+
+impl MessageNet {
+    pub fn send_to(&self, user_id: UserId, packet: HostPacket) {
+        self.send(Recipient::User(user_id), packet);
+    }
+
+    pub fn broadcast(&self, packet: HostPacket) {
+        self.send(Recipient::Everyone, packet);
+    }
+
+    fn send(&self, recipient: Recipient, packet: HostPacket) {
+        if let Err(e) = self.0.send(AddressedHostPacket { recipient, packet }) {
+            error!("Could not message the net: {e}");
+        }
+    }
+}
 
 #[derive(Resource)]
 pub struct NetMessages(pub Mutex<Receiver<AnnotatedClientPacket>>);
@@ -38,37 +70,70 @@ type PlayerNum = u8;
 #[derive(Resource)]
 pub struct PlayerMapping(pub HashMap<PlayerNum, UserId>);
 
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+/// How the app is put together
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AppMode {
+    /// The real thing: window, renderer, web server and remote protocol
+    Windowed,
+    /// No window, renderer or web server. See [headless].
+    Headless,
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mode: Res<AppMode>,
+    options: Res<HostOptions>,
+) {
     info!("Setting up The Host");
-    // start the web server
     let (send_net, rx) = std::sync::mpsc::channel();
     let (tx, recv_net) = std::sync::mpsc::channel();
-    let host_interface = game_42_net::protocol::HostInterface::new(rx, tx);
-    thread::spawn(move || {
-        game_42_net::main(host_interface);
-    });
+    match *mode {
+        AppMode::Windowed => {
+            // start the web server
+            let host_interface = game_42_net::protocol::HostInterface::new(rx, tx);
+            let server_config = options.server_config();
+            thread::spawn(move || {
+                game_42_net::main(host_interface, server_config);
+            });
+        }
+        AppMode::Headless => {
+            commands.insert_resource(SimulatedNet {
+                to_host: tx,
+                from_host: Mutex::new(rx),
+            });
+        }
+    }
     
     // communications
     commands.insert_resource(NetMessages(Mutex::new(recv_net)));
     commands.insert_resource(MessageNet(send_net));
     commands.insert_resource(PlayerInputs(HashMap::new()));
     commands.insert_resource(PlayerMapping(HashMap::new()));
+    commands.insert_resource(PlayerIdentities::default());
     
     // https://bevyengine.org/examples/math/random-sampling/
-    let seeded_rng = rand_chacha::ChaCha8Rng::seed_from_u64(1029301923);
+    let seeded_rng = rand_chacha::ChaCha8Rng::seed_from_u64(options.seed);
     // let seeded_rng = ChaCha8Rng::from_os_rng();
     commands.insert_resource(RandomSource(seeded_rng));
     
     commands.insert_resource(ConfigAccessor {
-        handle: asset_server.load("config.json")
+        handle: asset_server.load(options.config.clone())
     });
 }
 
 // handle player connections
 fn process_messages(
     receiver: Res<NetMessages>,
+    message_net: Res<MessageNet>,
     mut pm: ResMut<PlayerMapping>,
     mut player_inputs: ResMut<PlayerInputs>,
+    mut identities: ResMut<PlayerIdentities>,
+    current_layout: Res<CurrentLayout>,
+    mut feedback_settings: ResMut<PlayerFeedbackSettings>,
+    options: Res<HostOptions>,
+    tick: Res<FixedTick>,
+    mut input_queue: ResMut<InputQueue>,
     mut commands: Commands,
 ) {
     let mut pi = &mut player_inputs.as_mut().0;
@@ -78,19 +143,30 @@ fn process_messages(
             Packet::Connected => {
                 let player_number = pm.connect_lowest_num(&msg.user_id);
                 info!(
-                    "Player {} connected as player {}!",
+                    "{} connected as player {}!",
                     msg.user_id, player_number
                 );
                 // spawn something here?
                 pi.insert(msg.user_id, PlayerInput::new());
+                identities.assign_default(player_number);
+                if let Some(packet) = identities.identity_packet(player_number) {
+                    message_net.send_to(msg.user_id, packet);
+                }
+                message_net.broadcast(identities.palette_packet());
+                message_net.send_to(msg.user_id, HostPacket::Layout(current_layout.0.clone()));
             }
             Packet::Disconnected => {
                 let disconnected = pm.remove(&msg.user_id);
                 info!(
-                    "Player {} disconnected from player {}!",
+                    "{} disconnected from player {}!",
                     msg.user_id,
                     disconnected.unwrap()
                 );
+                if let Some(player_number) = disconnected {
+                    identities.remove(player_number);
+                    feedback_settings.0.remove(&player_number);
+                    message_net.broadcast(identities.palette_packet());
+                }
                 // despawn something here?
                 if let None = pi.remove(&msg.user_id) {
                     error!(
@@ -99,18 +175,32 @@ fn process_messages(
                     );
                 }
             }
+            Packet::Client(ClientPacket::SetIdentity { nickname, color }) => {
+                let Some(player_number) = pm.get_player(&msg.user_id) else {
+                    error!("{} set their identity but isn't a player!", msg.user_id);
+                    continue;
+                };
+                if let Err(e) = identities.set(player_number, &nickname, color) {
+                    warn!("Player {player_number} could not change identity: {e}");
+                }
+                // always tell them what they ended up with
+                if let Some(packet) = identities.identity_packet(player_number) {
+                    message_net.send_to(msg.user_id, packet);
+                }
+                message_net.broadcast(identities.palette_packet());
+            }
+            Packet::Client(ClientPacket::FeedbackSettings(settings)) => {
+                if let Some(player_number) = pm.get_player(&msg.user_id) {
+                    feedback_settings.0.insert(player_number, settings);
+                }
+            }
             Packet::Client(packet) => {
                 if let Some(entry) = pi.get_mut(&msg.user_id) {
                     if let Input(inp) = packet {
-                        match inp {
-                            InputUpdate::Button(but, pressed) => {
-                                // info!("Updated button");
-                                entry.update_button(but, pressed);
-                            }
-                            InputUpdate::Joystick(joy, v) => {
-                                // info!("Updated joystick");
-                                entry.update_joystick(joy, v);
-                            }
+                        if options.deterministic {
+                            input_queue.stamp(*tick, msg.user_id, inp);
+                        } else {
+                            entry.apply(inp);
                         }
                     } else {
                         error!("Unsupported variant of ClientPacket.");
@@ -154,22 +244,45 @@ fn grab_mouse(
     }
 }
 
-pub fn is_debug_mode() -> bool {
-    false
-}
-
-fn main() {
+pub fn build_app(mode: AppMode, options: HostOptions) -> App {
     let mut app = App::new();
+    let asset_plugin = AssetPlugin {
+        file_path: options.assets.clone(),
+        ..default()
+    };
+    match mode {
+        AppMode::Windowed => {
+            let window_mode = if options.fullscreen {
+                WindowMode::BorderlessFullscreen(MonitorSelection::Current)
+            } else {
+                WindowMode::Windowed
+            };
+            app.add_plugins(remote::remote_plugin())
+                .add_plugins(RemoteHttpPlugin::default())
+                .add_plugins(
+                    DefaultPlugins
+                        .set(WindowPlugin {
+                            primary_window: Some(Window {
+                                resolution: (options.width, options.height).into(),
+                                mode: window_mode,
+                                ..default()
+                            }),
+                            ..default()
+                        })
+                        .set(LogPlugin {
+                            level: options.log_level,
+                            ..default()
+                        })
+                        .set(asset_plugin),
+                );
+        }
+        AppMode::Headless => {
+            app.add_plugins(HeadlessPlugins.set(asset_plugin));
+        }
+    }
     app
-        .add_plugins(RemotePlugin::default())
-        .add_plugins(RemoteHttpPlugin::default())
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                resolution: (1080., 1260.).into(),
-                ..default()
-            }),
-            ..default()
-        }))
+        .insert_resource(mode)
+        .insert_resource(options)
         .add_systems(Startup, setup)
         .add_systems(First, grab_mouse)
         .add_systems(Update, process_messages)
@@ -178,7 +291,23 @@ fn main() {
         ;
     games::init_games(&mut app);
     debug_input::init(&mut app);
-    app.run();
+    feedback::init(&mut app);
+    lockstep::init(&mut app);
+    app
+}
+
+fn main() {
+    let options = HostOptions::parse();
+    if let Some(track) = &options.validate_track {
+        let ok = racing::validate::validate_track(&options, track);
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+    let mode = if options.headless {
+        AppMode::Headless
+    } else {
+        AppMode::Windowed
+    };
+    build_app(mode, options).run();
 }
 
 impl PlayerMapping {
@@ -200,6 +329,13 @@ impl PlayerMapping {
         }
     }
     
+    pub fn get_player(&self, user_id: &UserId) -> Option<PlayerNum> {
+        self.0
+            .iter()
+            .find(|(_k, v)| *v == user_id)
+            .map(|(player_num, _uid)| *player_num)
+    }
+
     pub fn get_players(&self) -> Keys<PlayerNum, UserId> {
         self.0.keys()
     }
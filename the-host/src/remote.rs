@@ -0,0 +1,143 @@
+use crate::games::{CurrentGame, GamePhase};
+use crate::games::racing::{race_standings, watch_player};
+use crate::identity::PlayerIdentities;
+use crate::{PlayerInputs, PlayerMapping, PlayerNum};
+use bevy::prelude::{In, NextState, Res, ResMut, State, info};
+use bevy::remote::{BrpError, BrpResult, RemotePlugin, error_codes};
+use game_42_net::controls::InputUpdate;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Custom Bevy Remote Protocol (BRP) methods, so that scripts and test
+// harnesses can drive a running host without needing a phone.
+//
+// Example:
+// curl -X POST localhost:15702 -d '{"jsonrpc": "2.0", "id": 1, "method": "game42/players"}'
+
+pub const PLAYERS_METHOD: &str = "game42/players";
+pub const START_GAME_METHOD: &str = "game42/start_game";
+pub const SET_PHASE_METHOD: &str = "game42/set_phase";
+pub const RACE_STANDINGS_METHOD: &str = "game42/race_standings";
+pub const INJECT_INPUT_METHOD: &str = "game42/inject_input";
+pub const WATCH_PLAYER_METHOD: &str = "game42/watch_player";
+
+/// [RemotePlugin] with all the game42 methods registered
+pub fn remote_plugin() -> RemotePlugin {
+    RemotePlugin::default()
+        .with_method(PLAYERS_METHOD, players)
+        .with_method(START_GAME_METHOD, start_game)
+        .with_method(SET_PHASE_METHOD, set_phase)
+        .with_method(RACE_STANDINGS_METHOD, race_standings)
+        .with_method(INJECT_INPUT_METHOD, inject_input)
+        .with_method(WATCH_PLAYER_METHOD, watch_player)
+}
+
+#[derive(Serialize)]
+struct PlayerEntry {
+    player: PlayerNum,
+    user_id: u64,
+    nickname: String,
+}
+
+#[derive(Deserialize)]
+struct StartGameParams {
+    game: CurrentGame,
+}
+
+#[derive(Deserialize)]
+struct SetPhaseParams {
+    phase: GamePhase,
+}
+
+#[derive(Deserialize)]
+struct InjectInputParams {
+    player: PlayerNum,
+    update: InputUpdate,
+}
+
+/// Parse the params of a request, or complain about it
+pub fn parse_params<T: DeserializeOwned>(params: Option<Value>) -> Result<T, BrpError> {
+    let params = params.ok_or(BrpError {
+        code: error_codes::INVALID_PARAMS,
+        message: "Params not provided".to_string(),
+        data: None,
+    })?;
+    serde_json::from_value(params).map_err(|e| BrpError {
+        code: error_codes::INVALID_PARAMS,
+        message: e.to_string(),
+        data: None,
+    })
+}
+
+pub fn to_brp_result<T: Serialize>(value: T) -> BrpResult {
+    serde_json::to_value(value).map_err(|e| BrpError {
+        code: error_codes::INTERNAL_ERROR,
+        message: e.to_string(),
+        data: None,
+    })
+}
+
+/// `game42/players`: list connected players, their user IDs and nicknames
+fn players(
+    In(_params): In<Option<Value>>,
+    player_mapping: Res<PlayerMapping>,
+    identities: Res<PlayerIdentities>,
+) -> BrpResult {
+    let mut entries: Vec<_> = player_mapping
+        .0
+        .iter()
+        .map(|(player, user_id)| PlayerEntry {
+            player: *player,
+            user_id: user_id.0,
+            nickname: identities.nickname(*player),
+        })
+        .collect();
+    entries.sort_by_key(|e| e.player);
+    to_brp_result(entries)
+}
+
+/// `game42/start_game`: skip voting and go to the pre-game of `game`
+fn start_game(
+    In(params): In<Option<Value>>,
+    mut next_game: ResMut<NextState<CurrentGame>>,
+    mut next_phase: ResMut<NextState<GamePhase>>,
+) -> BrpResult {
+    let StartGameParams { game } = parse_params(params)?;
+    info!("Remote request to start {game:?}");
+    next_game.set(game);
+    next_phase.set(GamePhase::PreGame);
+    Ok(Value::Null)
+}
+
+/// `game42/set_phase`: force the global [GamePhase]. Returns the phase before the change.
+fn set_phase(
+    In(params): In<Option<Value>>,
+    phase: Res<State<GamePhase>>,
+    mut next_phase: ResMut<NextState<GamePhase>>,
+) -> BrpResult {
+    let SetPhaseParams { phase: new_phase } = parse_params(params)?;
+    info!("Remote request to set phase to {new_phase:?}");
+    next_phase.set(new_phase);
+    to_brp_result(phase.get())
+}
+
+/// `game42/inject_input`: apply an [InputUpdate] as if it came from a player's phone
+fn inject_input(
+    In(params): In<Option<Value>>,
+    player_mapping: Res<PlayerMapping>,
+    mut player_inputs: ResMut<PlayerInputs>,
+) -> BrpResult {
+    let InjectInputParams { player, update } = parse_params(params)?;
+    let input = player_mapping
+        .0
+        .get(&player)
+        .and_then(|user_id| player_inputs.0.get_mut(user_id))
+        .ok_or(BrpError {
+            code: error_codes::INVALID_PARAMS,
+            message: format!("Player {player} is not connected"),
+            data: None,
+        })?;
+    input.apply(update);
+    Ok(Value::Null)
+}
@@ -0,0 +1,318 @@
+use crate::games::racing::track::LapCounter;
+use crate::games::racing::{AiDifficulty, AiDriver, race_standings};
+use crate::games::{CurrentGame, GamePhase, Player};
+use crate::cli::HostOptions;
+use crate::config::{Config, ConfigAccessor};
+use crate::headless::SimulatedNet;
+use crate::{AppMode, PlayerMapping, build_app};
+use bevy::prelude::{App, Assets, Entity, Fixed, NextState, State, Time, With, Without, World};
+use bevy::time::TimeUpdateStrategy;
+use game_42_net::controls::{ButtonType, InputUpdate};
+use game_42_net::protocol::{AddressedHostPacket, AnnotatedClientPacket, ClientPacket, Packet, UserId};
+use serde_json::Value;
+
+// Test harness that drives a headless host. Every call to `step` advances
+// time by exactly one fixed timestep, so FixedUpdate runs exactly once.
+
+/// Give up on anything taking longer than this many ticks (~30 seconds)
+pub const MAX_WAIT_TICKS: u32 = 64 * 30;
+
+pub struct SimHarness {
+    pub app: App,
+    next_user_id: u64,
+}
+
+/// Inputs to feed in at certain ticks, relative to when the script starts
+#[derive(Default)]
+pub struct InputScript(pub Vec<(u32, UserId, InputUpdate)>);
+
+impl InputScript {
+    pub fn at(mut self, tick: u32, user: UserId, update: InputUpdate) -> Self {
+        self.0.push((tick, user, update));
+        self
+    }
+
+    /// Hold `button` from `from` until (not including) `to`
+    pub fn hold(self, user: UserId, button: ButtonType, from: u32, to: u32) -> Self {
+        self.at(from, user, InputUpdate::Button(button, true))
+            .at(to, user, InputUpdate::Button(button, false))
+    }
+
+    fn len(&self) -> u32 {
+        self.0.iter().map(|(tick, _, _)| *tick + 1).max().unwrap_or(0)
+    }
+}
+
+impl SimHarness {
+    /// Build a headless host and wait for the config to load
+    pub fn new() -> Self {
+        SimHarness::with_options(HostOptions::default())
+    }
+
+    /// Same as `new`, but with different command line options
+    pub fn with_options(options: HostOptions) -> Self {
+        let mut app = build_app(AppMode::Headless, options);
+        let fixed_timestep = app.world().resource::<Time<Fixed>>().timestep();
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(fixed_timestep));
+        app.finish();
+        app.cleanup();
+        let mut sim = SimHarness {
+            app,
+            next_user_id: 0,
+        };
+        assert!(
+            sim.wait_until(MAX_WAIT_TICKS, |world| world.contains_resource::<SimulatedNet>()
+                && crate::games::config_loaded(world)),
+            "Config never loaded"
+        );
+        sim
+    }
+
+    /// Change a racing setting from what's in the config file, before the race starts
+    pub fn set_racing_config(&mut self, key: &str, value: Value) {
+        let world = self.app.world_mut();
+        let handle = world.resource::<ConfigAccessor>().handle.clone();
+        let mut configs = world.resource_mut::<Assets<Config>>();
+        let config = configs.get_mut(&handle).expect("no config!");
+        config.0["racing"][key] = value;
+    }
+
+    /// Advance the simulation by `ticks` fixed timesteps
+    pub fn step(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.app.update();
+        }
+    }
+
+    /// Step until `condition` holds. Returns false if it didn't within `max_ticks`.
+    pub fn wait_until(&mut self, max_ticks: u32, condition: impl Fn(&mut World) -> bool) -> bool {
+        for _ in 0..max_ticks {
+            if condition(self.app.world_mut()) {
+                return true;
+            }
+            self.step(1);
+        }
+        condition(self.app.world_mut())
+    }
+
+    fn send(&mut self, user_id: UserId, packet: Packet) {
+        self.app
+            .world()
+            .resource::<SimulatedNet>()
+            .to_host
+            .send(AnnotatedClientPacket { user_id, packet })
+            .expect("Host stopped listening");
+    }
+
+    /// Pretend a phone just connected
+    pub fn connect_player(&mut self) -> UserId {
+        let user_id = UserId(self.next_user_id);
+        self.next_user_id += 1;
+        self.send(user_id, Packet::Connected);
+        self.step(1);
+        user_id
+    }
+
+    pub fn disconnect_player(&mut self, user_id: UserId) {
+        self.send(user_id, Packet::Disconnected);
+        self.step(1);
+    }
+
+    pub fn input(&mut self, user_id: UserId, update: InputUpdate) {
+        self.send(user_id, Packet::Client(ClientPacket::Input(update)));
+    }
+
+    /// Feed a script in, one tick at a time
+    pub fn run_script(&mut self, script: &InputScript) {
+        for tick in 0..script.len() {
+            for (_, user, update) in script.0.iter().filter(|(t, _, _)| *t == tick) {
+                self.input(*user, update.clone());
+            }
+            self.step(1);
+        }
+    }
+
+    pub fn set_game(&mut self, game: CurrentGame, phase: GamePhase) {
+        let world = self.app.world_mut();
+        world.resource_mut::<NextState<CurrentGame>>().set(game);
+        world.resource_mut::<NextState<GamePhase>>().set(phase);
+        self.step(1);
+    }
+
+    pub fn phase(&self) -> GamePhase {
+        *self.app.world().resource::<State<GamePhase>>().get()
+    }
+
+    pub fn player_number(&self, user_id: UserId) -> Option<u8> {
+        self.app.world().resource::<PlayerMapping>().get_player(&user_id)
+    }
+
+    /// Lap counters of all the players' cars
+    pub fn lap_counters(&mut self) -> Vec<(u8, usize, usize)> {
+        let world = self.app.world_mut();
+        let mut query = world.query::<(&Player, &LapCounter)>();
+        let mut counters: Vec<_> = query
+            .iter(world)
+            .map(|(p, c)| (p.number(), c.lap(), c.sector()))
+            .collect();
+        counters.sort();
+        counters
+    }
+
+    /// Let the AI drive the phones' cars from now on, to get round the track without
+    /// scripting every turn
+    pub fn hand_cars_to_ai(&mut self) {
+        let world = self.app.world_mut();
+        let mut query =
+            world.query_filtered::<Entity, (With<Player>, With<LapCounter>, Without<AiDriver>)>();
+        let cars: Vec<_> = query.iter(world).collect();
+        for car in cars {
+            world
+                .entity_mut(car)
+                .insert(AiDriver::new(AiDifficulty::default()));
+        }
+    }
+
+    /// Same as the `game42/race_standings` remote method
+    pub fn standings(&mut self) -> Value {
+        self.app
+            .world_mut()
+            .run_system_cached_with(race_standings, None)
+            .expect("Could not run race_standings")
+            .expect("race_standings failed")
+    }
+
+    /// Everything the host has sent to the (pretend) phones since last time
+    pub fn sent_packets(&mut self) -> Vec<AddressedHostPacket> {
+        let net = self.app.world().resource::<SimulatedNet>();
+        let receiver = net.from_host.lock().unwrap();
+        receiver.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cli::HostOptions;
+    use crate::games::racing::track::{LapCounter, Tragnet};
+    use crate::games::{CurrentGame, GamePhase};
+    use crate::sim::{InputScript, MAX_WAIT_TICKS, SimHarness};
+    use bevy::prelude::State;
+    use game_42_net::controls::{ButtonType, InputUpdate};
+    use game_42_net::protocol::HostPacket;
+    use serde_json::json;
+
+    #[test]
+    fn players_connect_and_get_identities() {
+        let mut sim = SimHarness::new();
+        let alice = sim.connect_player();
+        let bob = sim.connect_player();
+        assert_eq!(sim.player_number(alice), Some(1));
+        assert_eq!(sim.player_number(bob), Some(2));
+        let packets = sim.sent_packets();
+        assert!(packets
+            .iter()
+            .any(|p| matches!(p.packet, HostPacket::Identity { player: 2, .. })));
+        sim.disconnect_player(alice);
+        assert_eq!(sim.player_number(alice), None);
+    }
+
+    #[test]
+    fn race_starts_when_everyone_is_ready() {
+        let mut sim = SimHarness::new();
+        sim.set_racing_config("ai-opponents", json!(2));
+        let alice = sim.connect_player();
+        let bob = sim.connect_player();
+        sim.set_game(CurrentGame::Racing, GamePhase::PreGame);
+
+        // wait for the track to load and the cars to spawn
+        assert!(sim.wait_until(MAX_WAIT_TICKS, |world| {
+            world.query::<&Tragnet>().iter(world).count() == 1
+        }));
+        sim.step(64 * 2);
+        // two phones, plus the two AI opponents
+        assert_eq!(sim.lap_counters().len(), 4);
+        assert_eq!(sim.phase(), GamePhase::PreGame);
+
+        let ready = InputScript::default()
+            .at(0, alice, InputUpdate::Button(ButtonType::A, true))
+            .at(0, bob, InputUpdate::Button(ButtonType::A, true));
+        sim.run_script(&ready);
+        sim.step(1);
+        assert_eq!(sim.phase(), GamePhase::PlayingGame);
+
+        // everyone floors it
+        let drive = InputScript::default()
+            .hold(alice, ButtonType::Up, 0, 64 * 3)
+            .hold(bob, ButtonType::Up, 0, 64 * 3);
+        sim.run_script(&drive);
+        let standings = sim.standings();
+        let standings = standings.as_array().expect("standings should be a list");
+        assert_eq!(standings.len(), 4);
+        assert_eq!(standings[0]["position"], 1);
+
+        // then everyone has to get round a lap for the race to end
+        sim.hand_cars_to_ai();
+        assert!(
+            sim.wait_until(MAX_WAIT_TICKS, |world| {
+                world
+                    .query::<&LapCounter>()
+                    .iter(world)
+                    .any(|counter| counter.lap() > 0 || counter.sector() > 0)
+            }),
+            "Nobody reached a checkpoint"
+        );
+        assert!(
+            sim.wait_until(MAX_WAIT_TICKS * 4, |world| {
+                *world.resource::<State<GamePhase>>().get() == GamePhase::PostGame
+            }),
+            "The race never finished"
+        );
+    }
+
+    /// Race once on a deterministic host, and return the standings exactly as the
+    /// remote method would send them
+    fn deterministic_race(seed: u64) -> String {
+        let options = HostOptions {
+            deterministic: true,
+            seed,
+            ..HostOptions::default()
+        };
+        let mut sim = SimHarness::with_options(options);
+        sim.set_racing_config("ai-opponents", json!(2));
+        let alice = sim.connect_player();
+        let bob = sim.connect_player();
+        sim.set_game(CurrentGame::Racing, GamePhase::PreGame);
+        assert!(sim.wait_until(MAX_WAIT_TICKS, |world| {
+            world.query::<&Tragnet>().iter(world).count() == 1
+        }));
+        sim.step(64 * 2);
+
+        let ready = InputScript::default()
+            .at(0, alice, InputUpdate::Button(ButtonType::A, true))
+            .at(0, bob, InputUpdate::Button(ButtonType::A, true));
+        sim.run_script(&ready);
+        assert!(sim.wait_until(64, |world| {
+            *world.resource::<State<GamePhase>>().get() == GamePhase::PlayingGame
+        }));
+
+        // enough going on that any difference would show: steering, drifting,
+        // items, and bumping into each other and the AI
+        let drive = InputScript::default()
+            .hold(alice, ButtonType::Up, 0, 64 * 4)
+            .hold(alice, ButtonType::Left, 40, 70)
+            .hold(bob, ButtonType::Up, 10, 64 * 4)
+            .hold(bob, ButtonType::Right, 30, 50)
+            .hold(bob, ButtonType::Y, 90, 120)
+            .hold(alice, ButtonType::B, 150, 151)
+            .hold(bob, ButtonType::B, 160, 161);
+        sim.run_script(&drive);
+        sim.standings().to_string()
+    }
+
+    #[test]
+    fn same_seed_and_inputs_give_the_same_race() {
+        let first = deterministic_race(42);
+        let second = deterministic_race(42);
+        assert_eq!(first, second);
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use crate::controls::{ButtonType, JoystickAxis};
+
+/// Describes what the controller page should show. Sent by the host whenever
+/// the game or phase changes, and rendered by the phone from top to bottom.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct ControllerLayout {
+    /// Title shown at the top of the page
+    pub title: String,
+    /// CSS colour of the page background
+    pub background: Option<String>,
+    pub elements: Vec<LayoutElement>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum LayoutElement {
+    /// A button that reports [crate::controls::InputUpdate::Button]
+    Button {
+        button: ButtonType,
+        label: String,
+        /// Emoji or short symbol shown above the label
+        icon: Option<String>,
+        /// CSS colour of the button
+        color: Option<String>,
+    },
+    /// A thumbstick that reports two axes of [crate::controls::InputUpdate::Joystick]
+    Thumbstick { x: JoystickAxis, y: JoystickAxis },
+    /// Free text, reported as [crate::controls::InputUpdate::Text]
+    TextField {
+        id: String,
+        label: String,
+        placeholder: String,
+    },
+    /// Pick one of `options`, reported as [crate::controls::InputUpdate::Choice]
+    Choice {
+        id: String,
+        label: String,
+        options: Vec<String>,
+    },
+    /// Nickname and colour picker
+    Identity,
+    /// Forces the following elements onto a new row
+    Break,
+}
+
+impl ControllerLayout {
+    pub fn new(title: &str) -> Self {
+        ControllerLayout {
+            title: title.to_string(),
+            background: None,
+            elements: vec![],
+        }
+    }
+
+    pub fn with_background(mut self, color: &str) -> Self {
+        self.background = Some(color.to_string());
+        self
+    }
+
+    pub fn with(mut self, element: LayoutElement) -> Self {
+        self.elements.push(element);
+        self
+    }
+
+    pub fn with_button(self, button: ButtonType, label: &str) -> Self {
+        self.with(LayoutElement::button(button, label))
+    }
+
+    /// Up, down, left and right buttons
+    pub fn with_arrows(self) -> Self {
+        self.with(LayoutElement::button(ButtonType::Up, "").icon("↑"))
+            .with(LayoutElement::button(ButtonType::Down, "").icon("↓"))
+            .with(LayoutElement::button(ButtonType::Left, "").icon("←"))
+            .with(LayoutElement::button(ButtonType::Right, "").icon("→"))
+    }
+}
+
+impl LayoutElement {
+    pub fn button(button: ButtonType, label: &str) -> Self {
+        LayoutElement::Button {
+            button,
+            label: label.to_string(),
+            icon: None,
+            color: None,
+        }
+    }
+
+    /// Set the icon of a button. Does nothing for other elements.
+    pub fn icon(mut self, new_icon: &str) -> Self {
+        if let LayoutElement::Button { icon, .. } = &mut self {
+            *icon = Some(new_icon.to_string());
+        }
+        self
+    }
+
+    /// Set the colour of a button. Does nothing for other elements.
+    pub fn color(mut self, new_color: &str) -> Self {
+        if let LayoutElement::Button { color, .. } = &mut self {
+            *color = Some(new_color.to_string());
+        }
+        self
+    }
+}
@@ -1,9 +1,12 @@
 
+pub mod layout;
+
+use std::collections::HashMap;
 use values_macro_derive::{EnumValues, Mapping};
 use serde::{Deserialize, Serialize};
 
 /// Identifies a button
-#[derive(EnumValues, Mapping, Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(EnumValues, Mapping, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ButtonType {
     A,
     B,
@@ -16,7 +19,7 @@ pub enum ButtonType {
 }
 
 /// Identifies which axis it is (a traditional joystick has 2 axes, X and Y).
-#[derive(EnumValues, Mapping, Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(EnumValues, Mapping, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum JoystickAxis {
     LeftX,
     LeftY,
@@ -28,6 +31,14 @@ pub enum JoystickAxis {
 pub struct PlayerInput {
     buttons: ButtonTypeMapping<ButtonState>,
     joysticks: JoystickAxisMapping<JoystickState>,
+    /// Values of text fields and choice lists, by the id given in the layout
+    fields: HashMap<String, FieldValue>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Text(String),
+    Choice(usize),
 }
 
 #[derive(Default)]
@@ -45,6 +56,10 @@ pub struct JoystickState {
 pub enum InputUpdate {
     Button(ButtonType, bool),
     Joystick(JoystickAxis, f32),
+    /// Text field (by id) was changed
+    Text(String, String),
+    /// Option (by index) was picked in a choice list (by id)
+    Choice(String, usize),
 }
 
 impl PlayerInput {
@@ -52,6 +67,7 @@ impl PlayerInput {
         PlayerInput {
             buttons: ButtonTypeMapping::new(|_b| ButtonState::default()),
             joysticks: JoystickAxisMapping::new(|_j| JoystickState::new()),
+            fields: HashMap::new(),
         }
     }
 
@@ -62,10 +78,42 @@ impl PlayerInput {
     pub fn update_joystick(&mut self, joystick_axis: JoystickAxis, value: f32) {
         self.joysticks.get_mut(joystick_axis).update(value);
     }
+
+    /// Apply an update received from a client (or injected by the host)
+    pub fn apply(&mut self, update: InputUpdate) {
+        match update {
+            InputUpdate::Button(but, pressed) => self.update_button(but, pressed),
+            InputUpdate::Joystick(joy, v) => self.update_joystick(joy, v),
+            InputUpdate::Text(id, text) => {
+                self.fields.insert(id, FieldValue::Text(text));
+            }
+            InputUpdate::Choice(id, index) => {
+                self.fields.insert(id, FieldValue::Choice(index));
+            }
+        }
+    }
     
     pub fn is_pressed(&self, button_type: ButtonType) -> bool {
         self.buttons.get(button_type).pressed
     }
+
+    pub fn joystick(&self, joystick_axis: JoystickAxis) -> f32 {
+        self.joysticks.get(joystick_axis).get()
+    }
+
+    pub fn text(&self, id: &str) -> Option<&str> {
+        match self.fields.get(id) {
+            Some(FieldValue::Text(text)) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn choice(&self, id: &str) -> Option<usize> {
+        match self.fields.get(id) {
+            Some(FieldValue::Choice(index)) => Some(*index),
+            _ => None,
+        }
+    }
 }
 impl ButtonState {
     pub fn update(&mut self, pressed: bool) {
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+
+/// Something for the phone to do so the player can feel/hear what happened
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Feedback {
+    /// Vibration pattern in milliseconds: on, off, on, off, ...
+    /// (same as `navigator.vibrate`). Empty means no vibration.
+    pub vibrate: Vec<u32>,
+    pub sound: Option<SoundCue>,
+}
+
+/// Short sounds the controller page knows how to play
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoundCue {
+    Bump,
+    Checkpoint,
+    Lap,
+    Finish,
+}
+
+/// What a phone can do, and what the player wants it to do
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeedbackSettings {
+    pub can_vibrate: bool,
+    pub can_play_sound: bool,
+    pub muted: bool,
+}
+
+impl Default for FeedbackSettings {
+    fn default() -> Self {
+        FeedbackSettings {
+            can_vibrate: true,
+            can_play_sound: true,
+            muted: false,
+        }
+    }
+}
+
+impl Feedback {
+    pub fn new(vibrate: &[u32], sound: Option<SoundCue>) -> Self {
+        Feedback {
+            vibrate: vibrate.to_vec(),
+            sound,
+        }
+    }
+
+    pub fn bump() -> Self {
+        Feedback::new(&[60], Some(SoundCue::Bump))
+    }
+
+    pub fn checkpoint() -> Self {
+        Feedback::new(&[30], Some(SoundCue::Checkpoint))
+    }
+
+    pub fn lap() -> Self {
+        Feedback::new(&[80, 60, 80], Some(SoundCue::Lap))
+    }
+
+    pub fn finish() -> Self {
+        Feedback::new(&[200, 100, 200, 100, 400], Some(SoundCue::Finish))
+    }
+
+    /// Drop whatever the phone can't (or shouldn't) do
+    pub fn filtered(mut self, settings: &FeedbackSettings) -> Self {
+        if !settings.can_vibrate {
+            self.vibrate.clear();
+        }
+        if settings.muted || !settings.can_play_sound {
+            self.sound = None;
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vibrate.is_empty() && self.sound.is_none()
+    }
+}
@@ -1,11 +1,14 @@
 pub mod protocol;
 pub mod websocket;
 pub mod controls;
+pub mod feedback;
 
 #[macro_use]
 extern crate rocket;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::thread;
+use rocket::futures::channel::mpsc::UnboundedSender;
 use rocket_ws::{Channel, WebSocket};
 
 use std::sync::mpsc::{Receiver, Sender};
@@ -13,12 +16,34 @@ use std::sync::{Arc, Mutex};
 use rocket::{Build, Rocket, State};
 use rocket::fs::{relative, FileServer, Options};
 use rocket::response::status;
-use crate::protocol::{HostInterface, UserId};
+use crate::protocol::{AddressedHostPacket, AnnotatedClientPacket, HostInterface, HostPacket, Recipient, UserId};
 use crate::websocket::handle_socket;
 
+/// Where the web server listens
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub address: String,
+    pub port: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            // when you want to visit it from outside
+            address: "0.0.0.0".to_string(),
+            port: 8000,
+        }
+    }
+}
+
+/// Sending half of the host interface, shared with every socket
+pub(crate) struct ToHost(pub Sender<AnnotatedClientPacket>);
+
 #[derive(Default)]
 pub(crate) struct Users {
-    connected: HashSet<UserId>
+    connected: HashSet<UserId>,
+    /// Outgoing queues for each open socket
+    clients: HashMap<UserId, UnboundedSender<HostPacket>>,
 }
 
 impl Users {
@@ -31,6 +56,39 @@ impl Users {
         self.connected.insert(uid);
         uid
     }
+
+    pub fn add_client(&mut self, uid: UserId, sender: UnboundedSender<HostPacket>) {
+        self.clients.insert(uid, sender);
+    }
+
+    pub fn remove_client(&mut self, uid: &UserId) {
+        self.clients.remove(uid);
+    }
+
+    fn deliver(&self, packet: AddressedHostPacket) {
+        match packet.recipient {
+            Recipient::User(uid) => {
+                if let Some(client) = self.clients.get(&uid) {
+                    let _ = client.unbounded_send(packet.packet);
+                }
+            }
+            Recipient::Everyone => {
+                for client in self.clients.values() {
+                    let _ = client.unbounded_send(packet.packet.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Forward everything the host sends to the right sockets
+fn route_host_packets(recv: Receiver<AddressedHostPacket>, users: Arc<Mutex<Users>>) {
+    while let Ok(packet) = recv.recv() {
+        if let Ok(users) = users.lock() {
+            users.deliver(packet);
+        }
+    }
+    info!("Host closed its sending channel, no longer routing packets.");
 }
 
 #[get("/")]
@@ -41,26 +99,30 @@ fn index() -> &'static str {
 #[get("/ws")]
 fn updates<'r>(
     ws: WebSocket,
-    host_interface: &'r State<HostInterface>,
+    to_host: &'r State<ToHost>,
     users: &'r State<Arc<Mutex<Users>>>,
 ) -> Result<Channel<'r>, status::Forbidden<&'static str>> {
-    Ok(ws.channel(move |stream| Box::pin(handle_socket(stream, host_interface, users))))
+    Ok(ws.channel(move |stream| Box::pin(handle_socket(stream, to_host, users))))
 }
 
-fn rocket(host_interface: HostInterface) -> Rocket<Build> {
+fn rocket(to_host: ToHost, users: Arc<Mutex<Users>>, server_config: ServerConfig) -> Rocket<Build> {
     let figment = rocket::Config::figment()
-        .merge(("port", 8000))
-        .merge(("address", "0.0.0.0")) // when you want to visit it from outside
+        .merge(("port", server_config.port))
+        .merge(("address", server_config.address))
     ;
     rocket::custom(figment)
-        .manage(host_interface)
-        .manage(Arc::new(Mutex::new(Users::default())))
+        .manage(to_host)
+        .manage(users)
         .mount("/", FileServer::new(relative!["static"], Options::default()))
         .mount("/game", routes![index, updates])
 }
 
-pub fn main(host_interface: HostInterface) {
+pub fn main(host_interface: HostInterface, server_config: ServerConfig) {
+    let HostInterface { recv, send } = host_interface;
+    let users = Arc::new(Mutex::new(Users::default()));
+    let router_users = users.clone();
+    thread::spawn(move || route_host_packets(recv, router_users));
     rocket::async_main(async move {
-        let _ = rocket(host_interface).launch().await;
+        let _ = rocket(ToHost(send), users, server_config).launch().await;
     });
 }
\ No newline at end of file
@@ -4,6 +4,8 @@ use rocket_ws::Message;
 use serde::{Deserialize, Serialize};
 use serde::ser::Error;
 use crate::controls::InputUpdate;
+use crate::controls::layout::ControllerLayout;
+use crate::feedback::{Feedback, FeedbackSettings};
 
 #[derive(Serialize, Deserialize, Ord, PartialOrd, Eq, PartialEq, Clone, Copy, Debug, Hash)]
 pub struct UserId(pub u64);
@@ -26,21 +28,70 @@ pub enum Packet {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ClientPacket {
     Input(InputUpdate),
+    /// Player wants to be called `nickname` and use the palette colour at index `color`
+    SetIdentity { nickname: String, color: usize },
+    /// What the phone supports and whether the player muted it
+    FeedbackSettings(FeedbackSettings),
     // seldom other things
 }
 
+/// Packet going from host to client, through the net (here)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum HostPacket {
+    /// Colours (as hex strings) players can choose from, and the indices already taken
+    Palette { colors: Vec<String>, taken: Vec<usize> },
+    /// The identity the host has on record for this player
+    Identity { player: u8, nickname: String, color: usize },
+    /// What the controller page should look like now
+    Layout(ControllerLayout),
+    /// Vibrate and/or play a sound
+    Feedback(Feedback),
+    /// What to show in the status area of the phone. `None` hides it.
+    Status(Option<PlayerStatus>),
+}
+
+/// A player's own view of how they're doing in the current game
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct PlayerStatus {
+    pub player: u8,
+    /// CSS colour of the player
+    pub color: String,
+    pub ready: bool,
+    /// Place in the game (1 is first) and out of how many
+    pub position: Option<(usize, usize)>,
+    /// Current lap (1 is the first lap) and out of how many
+    pub lap: Option<(usize, usize)>,
+    /// Current sector (1 is the first sector) and out of how many
+    pub sector: Option<(usize, usize)>,
+    /// Anything else worth shouting about (e.g. "Finished!")
+    pub message: Option<String>,
+}
+
+/// Who a [HostPacket] should be delivered to
+#[derive(Clone, Copy, Debug)]
+pub enum Recipient {
+    User(UserId),
+    Everyone,
+}
+
+/// Packet going from host to net (here), annotated with who it's for
+#[derive(Debug)]
+pub struct AddressedHostPacket {
+    pub recipient: Recipient,
+    pub packet: HostPacket,
+}
+
 /// Primitives for communication with The Host
 pub struct HostInterface {
     /// Receiving from Host
-    // pub recv: Receiver<()>,
+    pub recv: Receiver<AddressedHostPacket>,
     /// Sending to host
     pub send: Sender<AnnotatedClientPacket>,
 }
 
 impl HostInterface {
-    pub fn new(recv: Receiver<()>, send: Sender<AnnotatedClientPacket>) -> Self {
-        // todo: make a router for recv
-        HostInterface { send }
+    pub fn new(recv: Receiver<AddressedHostPacket>, send: Sender<AnnotatedClientPacket>) -> Self {
+        HostInterface { recv, send }
     }
 }
 
@@ -56,14 +107,14 @@ impl ClientPacket {
 
 impl Display for UserId {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Player {}", self.0)
+        write!(f, "user {}", self.0)
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::controls::{ButtonType, InputUpdate, JoystickAxis};
-    use crate::protocol::ClientPacket;
+    use crate::protocol::{ClientPacket, HostPacket};
 
     #[test]
     fn example_serialize() {
@@ -74,5 +125,13 @@ mod test {
         let packet = ClientPacket::Input(InputUpdate::Joystick(JoystickAxis::LeftX, 0.5));
         let json = serde_json::to_string_pretty(&packet).unwrap();
         println!("{packet:?} is \n{json}");
+
+        let packet = ClientPacket::SetIdentity { nickname: "Bob".to_string(), color: 3 };
+        let json = serde_json::to_string_pretty(&packet).unwrap();
+        println!("{packet:?} is \n{json}");
+
+        let packet = HostPacket::Palette { colors: vec!["#e6194b".to_string()], taken: vec![0] };
+        let json = serde_json::to_string_pretty(&packet).unwrap();
+        println!("{packet:?} is \n{json}");
     }
 }
\ No newline at end of file
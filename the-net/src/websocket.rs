@@ -1,18 +1,19 @@
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc::SendError;
 use std::sync::mpsc::Sender;
-use rocket::futures::channel::mpsc::{UnboundedReceiver};
-use rocket::futures::StreamExt;
+use rocket::futures::channel::mpsc::unbounded;
+use rocket::futures::{SinkExt, StreamExt};
 use rocket::State;
+use rocket_ws::Message;
 use rocket_ws::Message::Close;
 use rocket_ws::result::Error;
 use rocket_ws::stream::DuplexStream;
 use tokio::select;
 use tokio::task::JoinHandle;
 use log::error;
-use crate::protocol::{AnnotatedClientPacket, ClientPacket, HostInterface, Packet};
+use crate::protocol::{AnnotatedClientPacket, ClientPacket, HostPacket, Packet};
 use crate::protocol::Packet::Connected;
-use crate::Users;
+use crate::{ToHost, Users};
 use crate::websocket::ClientStreamError::HostClosed;
 
 #[derive(Debug)]
@@ -24,13 +25,18 @@ pub enum ClientStreamError {
 
 pub(crate) async fn handle_socket(
     channel: DuplexStream,
-    host_interface: &State<HostInterface>,
+    to_host: &State<ToHost>,
     users: &State<Arc<Mutex<Users>>>,
 ) -> rocket_ws::result::Result<(), Error> {
-    let to_host = host_interface.send.clone();
+    let to_host = to_host.0.clone();
     let dis_host = to_host.clone();
+    // register the outgoing queue before the host hears about us,
+    // so that nothing it sends on connection gets lost
+    let (to_client, mut from_host) = unbounded::<HostPacket>();
     let uid = users.lock().map(|mut users| {
-        users.add_next()
+        let uid = users.add_next();
+        users.add_client(uid, to_client);
+        uid
     }).unwrap();
     if let Err(e) = to_host.send(AnnotatedClientPacket {
         user_id: uid,
@@ -39,8 +45,8 @@ pub(crate) async fn handle_socket(
         error!("Error while sending connection message: {e:?}");
         return Err(Error::ConnectionClosed)
     }
-    let (sender, mut receiver) = channel.split();
-    let receive_task: JoinHandle<Result<(), ClientStreamError>> = tokio::spawn(async move {
+    let (mut sender, mut receiver) = channel.split();
+    let mut receive_task: JoinHandle<Result<(), ClientStreamError>> = tokio::spawn(async move {
         while let Some(Ok(msg)) = receiver.next().await {
             match msg {
                 Close(_c) => {
@@ -68,14 +74,28 @@ pub(crate) async fn handle_socket(
     });
 
     // Sending task (handles outgoing messages)
-    // let send_task: JoinHandle<Result<(), Error>> = tokio::spawn(async move {
-    //
-    // });
+    let mut send_task: JoinHandle<Result<(), ClientStreamError>> = tokio::spawn(async move {
+        while let Some(packet) = from_host.next().await {
+            let text = serde_json::to_string(&packet)?;
+            sender.send(Message::Text(text)).await.map_err(ClientStreamError::Socket)?;
+        }
+        Ok(())
+    });
 
     // Wait for either task to complete
     select! {
-        e = receive_task => info!("Channel closed from receiver end with {e:?}"),
-        // _ = send_task => info!("Channel closed from sender end"),
+        e = &mut receive_task => {
+            info!("Channel closed from receiver end with {e:?}");
+            send_task.abort();
+        },
+        e = &mut send_task => {
+            info!("Channel closed from sender end with {e:?}");
+            receive_task.abort();
+        },
+    }
+
+    if let Ok(mut users) = users.lock() {
+        users.remove_client(&uid);
     }
 
     if let Err(e) = dis_host.send(AnnotatedClientPacket {